@@ -0,0 +1,133 @@
+//! Alerts on loss or latency going over a threshold (--alert-loss, --alert-rtt), judged
+//! over the last few probes so one lost packet isn't an outage. An alert fires when the
+//! thresholds are crossed, and clears when they aren't anymore, but either only once
+//! it's held for the debounce time: a flapping link gets one alert, not one a minute.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::stats::{self, Breach, ProbeGroup};
+
+pub struct Alert {
+    pub max_loss: Option<f32>,
+    pub max_rtt: Option<Duration>, // Against the average over the window
+    pub window: usize,             // Probes the thresholds are judged over
+    pub debounce: Duration,
+    pub url: Option<String>, // Gets a POST whenever an alert fires or clears
+}
+
+impl Alert {
+    /// Everything about the group that's over the thresholds
+    pub fn breaches(&self, group: &ProbeGroup) -> Vec<Breach> {
+        stats::breaches(group, self.max_loss, self.max_rtt, None)
+    }
+}
+
+/// Parses a loss threshold like `10%` (or just `10`)
+pub fn parse_loss(loss: &str) -> Option<f32> {
+    loss.trim().trim_end_matches('%').parse().ok().filter(|loss: &f32| (0.0..=100.0).contains(loss))
+}
+
+/// One destination's alert, and the probes it's judged on
+#[derive(Default)]
+pub struct State {
+    recent: VecDeque<Option<Duration>>, // None for lost probes, oldest first
+    pub firing: bool,
+    changing_since: Option<Instant>, // When the thresholds started saying otherwise
+}
+
+/// An alert firing (or clearing), with the probes that made it
+pub struct Change {
+    pub firing: bool,
+    pub group: ProbeGroup,
+    pub breaches: Vec<Breach>,
+}
+
+impl State {
+    /// Count a probe (`rtt` is None if it was lost), returns the change if the alert
+    /// fires or clears because of it
+    pub fn record(&mut self, alert: &Alert, rtt: Option<Duration>) -> Option<Change> {
+        self.recent.push_back(rtt);
+        if self.recent.len() > alert.window {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < alert.window {
+            return None; // Not enough to go on yet
+        }
+
+        let mut group = ProbeGroup::default();
+        for &rtt in &self.recent {
+            group.record(rtt);
+        }
+        let breaches = alert.breaches(&group);
+
+        if breaches.is_empty() != self.firing {
+            self.changing_since = None;
+            return None;
+        }
+        let since = *self.changing_since.get_or_insert_with(Instant::now);
+        if since.elapsed() < alert.debounce {
+            return None;
+        }
+
+        self.firing = !self.firing;
+        self.changing_since = None;
+        Some(Change { firing: self.firing, group, breaches })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const LOST: Option<Duration> = None;
+    const ANSWERED: Option<Duration> = Some(Duration::from_millis(10));
+
+    fn alert(debounce: Duration) -> Alert {
+        Alert { max_loss: Some(40.0), max_rtt: None, window: 2, debounce, url: None }
+    }
+
+    #[test]
+    fn nothing_fires_before_the_window_is_full() {
+        let (alert, mut state) = (alert(Duration::ZERO), State::default());
+        assert!(state.record(&alert, LOST).is_none());
+        let change = state.record(&alert, LOST).unwrap();
+        assert!(change.firing && state.firing);
+        assert_eq!((change.group.sent, change.group.lost), (2, 2));
+        assert_eq!(change.breaches.iter().map(Breach::canonical).collect::<Vec<_>>(), ["loss 100.00% over 40%"]);
+    }
+
+    #[test]
+    fn clears_once_back_under_the_thresholds() {
+        let (alert, mut state) = (alert(Duration::ZERO), State::default());
+        state.record(&alert, LOST);
+        state.record(&alert, LOST);
+        assert!(state.record(&alert, ANSWERED).is_none()); // Still 50% over the last two
+        let change = state.record(&alert, ANSWERED).unwrap();
+        assert!(!change.firing && !state.firing);
+        assert!(change.breaches.is_empty());
+    }
+
+    #[test]
+    fn only_fires_once_the_breach_has_held_for_the_debounce() {
+        let (alert, mut state) = (alert(Duration::from_millis(50)), State::default());
+        state.record(&alert, LOST);
+        assert!(state.record(&alert, LOST).is_none());
+        thread::sleep(Duration::from_millis(60));
+        assert!(state.record(&alert, LOST).unwrap().firing);
+    }
+
+    #[test]
+    fn a_flap_starts_the_debounce_over() {
+        let (alert, mut state) = (alert(Duration::from_millis(50)), State::default());
+        state.record(&alert, LOST);
+        assert!(state.record(&alert, LOST).is_none());
+        state.record(&alert, ANSWERED);
+        assert!(state.record(&alert, ANSWERED).is_none()); // Back under, nothing's changing anymore
+        thread::sleep(Duration::from_millis(60));
+        state.record(&alert, LOST);
+        assert!(state.record(&alert, LOST).is_none()); // Breached again, but only just now
+        assert!(!state.firing);
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV6};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use socket2::{Socket, Domain, Protocol, SockAddr};
+
+use crate::packet::{self, ArpPacket, EthernetHeader, NeighborMessage};
+use crate::ping::{PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::util::{self, Interface};
+
+/// Link-layer reachability for hosts on our own subnet: ARP requests for IPv4 and
+/// Neighbor Solicitations for IPv6. Hosts have to answer these to be on the network at
+/// all, so it works even when ICMP is firewalled. Only makes sense for local targets,
+/// nothing past the first router will ever see them.
+pub struct ArpProbe {
+    target: IpAddr,
+    interface: Interface,
+    socket: Socket,
+    coder: bincode::Config,
+
+    sequence: u16,
+    sent_at: Instant,
+}
+
+impl ArpProbe {
+    pub fn new(target: IpAddr) -> Result<Self> {
+        let interface = util::local_interface(target)?;
+
+        let socket = match target {
+            IpAddr::V4(_) => {
+                let protocol = (libc::ETH_P_ARP as u16).to_be() as i32;
+                let socket = Socket::new(Domain::from(libc::AF_PACKET), socket2::Type::raw().cloexec(), Some(Protocol::from(protocol)))?;
+
+                // Only listen on (and send from) the interface the target is on
+                let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+                address.sll_family = libc::AF_PACKET as u16;
+                address.sll_protocol = protocol as u16;
+                address.sll_ifindex = interface.index as i32;
+                socket.bind(&unsafe { SockAddr::from_raw_parts(&address as *const _ as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t) })?;
+                socket
+            }
+            IpAddr::V6(_) => {
+                let socket = Socket::new(Domain::ipv6(), socket2::Type::raw().cloexec(), Some(Protocol::icmpv6()))?;
+                // Neighbor discovery is ignored unless it comes with a hop limit of 255, proving it wasn't routed
+                socket.set_multicast_hops_v6(255)?;
+                socket.set_unicast_hops_v6(255)?;
+                socket.set_multicast_if_v6(interface.index)?;
+                socket
+            }
+        };
+
+        socket.set_nonblocking(true)?; // Waits go through util::wait_readable, so Ctrl+C isn't stuck behind them
+
+        let mut coder = bincode::config();
+        coder.big_endian();
+
+        Ok(ArpProbe {
+            target, interface, socket, coder,
+            sequence: 0,
+            sent_at: Instant::now(),
+        })
+    }
+
+    fn send_arp(&self, target: Ipv4Addr) -> Result<()> {
+        let ip = match self.interface.address {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => unreachable!(),
+        };
+
+        let (header, arp) = packet::arp_request(self.interface.mac, ip, target);
+        let mut frame = self.coder.serialize(&header).unwrap();
+        frame.append(&mut self.coder.serialize(&arp).unwrap());
+        self.socket.send(&frame)?;
+        Ok(())
+    }
+
+    fn send_solicitation(&self, target: Ipv6Addr) -> Result<()> {
+        let (message, option) = packet::neighbor_solicitation(self.interface.mac, target);
+        let mut buf = self.coder.serialize(&message).unwrap();
+        buf.append(&mut self.coder.serialize(&option).unwrap());
+
+        let to = SocketAddrV6::new(packet::solicited_node_address(target), 0, 0, self.interface.index);
+        self.socket.send_to(&buf, &SockAddr::from(to))?;
+        Ok(())
+    }
+
+    // Wait for the target's answer, its hardware address and when it came in
+    fn receive_answer(&self, timeout: Duration) -> Result<([u8; 6], Instant)> {
+        let begin_time = Instant::now();
+        let mut buf = [0u8; 1500];
+
+        loop {
+            if !util::wait_readable(self.socket.as_raw_fd(), timeout.saturating_sub(begin_time.elapsed()))? {
+                return Err(Error::new(ErrorKind::WouldBlock, "timed out"));
+            }
+
+            let bytes = match self.socket.recv(&mut buf) {
+                Ok(bytes) => bytes,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            let received_at = Instant::now();
+
+            // No identifiers at this layer, any answer from the target is an answer to our latest request
+            if let Some(mac) = self.parse(&buf[..bytes]) {
+                return Ok((mac, received_at));
+            }
+        }
+    }
+
+    // The hardware address in `buf` if it's the answer we're after
+    fn parse(&self, buf: &[u8]) -> Option<[u8; 6]> {
+        match self.target {
+            IpAddr::V4(target) => {
+                if buf.len() < packet::ETHERNET_HEADER_LEN + packet::ARP_PACKET_LEN {
+                    return None;
+                }
+                let header: EthernetHeader = self.coder.deserialize(&buf[..packet::ETHERNET_HEADER_LEN]).ok()?;
+                let arp: ArpPacket = self.coder.deserialize(&buf[packet::ETHERNET_HEADER_LEN..]).ok()?;
+
+                if header.ethertype == packet::ETHERTYPE_ARP && arp.operation == packet::ARP_REPLY && arp.sender_ip == target.octets() {
+                    Some(arp.sender_mac)
+                } else {
+                    None
+                }
+            }
+            IpAddr::V6(target) => {
+                // Raw ICMPv6 sockets don't include the ipv6 header
+                if buf.len() < packet::NEIGHBOR_MESSAGE_LEN {
+                    return None;
+                }
+                let message: NeighborMessage = self.coder.deserialize(buf).ok()?;
+
+                if message.message_type == packet::NEIGHBOR_ADVERTISEMENT && message.target == target.octets() {
+                    // Solicited advertisements should carry the address, but it's optional
+                    Some(packet::target_link_address(&buf[packet::NEIGHBOR_MESSAGE_LEN..]).unwrap_or([0; 6]))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// The hardware address of a target on our own subnet, for telling which device it
+/// is. From the kernel's neighbor table if it's there, otherwise by asking. None for
+/// anything past a router, or that doesn't answer
+pub fn lookup_mac(target: IpAddr) -> Option<[u8; 6]> {
+    let interface = util::local_interface(target).ok()?;
+    if target == interface.address {
+        return Some(interface.mac); // Nobody answers an ARP for themselves
+    }
+
+    if let IpAddr::V4(ip) = target {
+        if let Some(mac) = arp_cache(ip, &interface.name) {
+            return Some(mac);
+        }
+    }
+
+    // Needs a packet socket (or raw ICMPv6), without one there's no way to ask
+    let mut probe = ArpProbe::new(target).ok()?;
+    probe.ping().ok()?;
+    probe.receive_answer(LOOKUP_TIMEOUT).ok().map(|(mac, _)| mac).filter(|&mac| mac != [0; 6])
+}
+
+// Anything on the same link should be much quicker than this
+const LOOKUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+// A complete entry for `ip` in the kernel's ARP cache
+fn arp_cache(ip: Ipv4Addr, interface: &str) -> Option<[u8; 6]> {
+    let table = std::fs::read_to_string("/proc/net/arp").ok()?;
+    table.lines().skip(1).find_map(|line| {
+        // IP address, HW type, Flags, HW address, Mask, Device
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let complete = fields.get(2).and_then(|flags| u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok())
+            .is_some_and(|flags| flags & libc::ATF_COM as u32 != 0);
+        if fields.len() < 6 || fields[0].parse() != Ok(ip) || fields[5] != interface || !complete {
+            return None;
+        }
+        packet::parse_mac(fields[3])
+    })
+}
+
+impl Probe for ArpProbe {
+    fn ping(&mut self) -> Result<u16> {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.sent_at = Instant::now();
+
+        match self.target {
+            IpAddr::V4(target) => self.send_arp(target)?,
+            IpAddr::V6(target) => self.send_solicitation(target)?,
+        }
+        Ok(self.sequence)
+    }
+
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
+        let (mac, received_at) = self.receive_answer(timeout)?;
+        Ok(PongResult {
+            address: self.target,
+            hostname: util::hostname(&self.target),
+
+            sequence: sequence_num,
+            ttl: None,
+            route: None,
+            size: 0,
+            rtt: received_at.duration_since(self.sent_at),
+            mtype: ReplyType::Reply,
+            phases: Vec::new(),
+            detail: Some(format!("at {}", packet::format_mac(&mac))),
+            transport: None,
+            corruption: None,
+            tos: None,
+            duplicate: false,
+            late: false,
+            hardware: None,
+        })
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(format!("via {} on {}", if self.target.is_ipv6() { "ndp" } else { "arp" }, self.interface.name))
+    }
+
+    fn backend(&self) -> &'static str {
+        if self.target.is_ipv6() { "ndp" } else { "arp" }
+    }
+}
@@ -0,0 +1,238 @@
+//! What's normal for each destination (--baseline), learned across runs and kept in a
+//! file. Once there's enough history, probes and periods way off from it get flagged,
+//! so a regression shows up even without any thresholds set up by hand.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{Result, Error, ErrorKind};
+use std::time::Duration;
+
+// Probes per period, each one adds a median rtt and a loss to the history
+const PERIOD: usize = 20;
+// Periods of history kept, older ones age out so the baseline follows lasting changes
+const HISTORY: usize = 100;
+// Periods needed before anything gets flagged
+const MIN_HISTORY: usize = 3;
+
+// How far off is an anomaly
+const PROBE_FACTOR: f64 = 4.0;  // A single rtt against the baseline median
+const PERIOD_FACTOR: f64 = 2.0; // A period's median rtt
+const LOSS_MARGIN: f32 = 10.0;  // Percentage points over the typical loss
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Period {
+    median_ms: f64,
+    loss: f32,
+}
+
+/// The learned history of one destination
+#[derive(Serialize, Deserialize, Default)]
+pub struct Baseline {
+    periods: VecDeque<Period>, // Oldest first
+
+    #[serde(skip)]
+    current: Vec<Option<Duration>>, // The period being filled in, None for lost probes
+}
+
+/// A period that was off from the baseline
+pub struct PeriodAnomaly {
+    pub sent: u32,
+    pub lost: u32,
+    pub median: Option<Duration>,
+    pub baseline_rtt: Option<f64>, // What it was compared against, in ms
+    pub baseline_loss: Option<f32>,
+    pub anomalies: Vec<String>,
+}
+
+impl Baseline {
+    /// Median rtt over the history, None until there's enough of it
+    pub fn rtt(&self) -> Option<f64> {
+        if self.periods.len() < MIN_HISTORY {
+            return None;
+        }
+        median(self.periods.iter().map(|period| period.median_ms).collect())
+    }
+
+    /// Typical loss over the history, in percent
+    pub fn loss(&self) -> Option<f32> {
+        if self.periods.len() < MIN_HISTORY {
+            return None;
+        }
+        median(self.periods.iter().map(|period| period.loss as f64).collect()).map(|loss| loss as f32)
+    }
+
+    /// Whether a single reply is way slower than usual, ex: "rtt 6x baseline"
+    pub fn probe_anomaly(&self, rtt: Duration) -> Option<String> {
+        let baseline = self.rtt()?;
+        let factor = ms(rtt) / baseline;
+        if factor >= PROBE_FACTOR { Some(format!("rtt {:.0}x baseline", factor)) } else { None }
+    }
+
+    /// Count a probe (`rtt` is None if it was lost). At the end of a period it's learned,
+    /// and returned if it was off from what came before
+    pub fn record(&mut self, rtt: Option<Duration>) -> Option<PeriodAnomaly> {
+        self.current.push(rtt);
+        if self.current.len() < PERIOD {
+            return None;
+        }
+
+        let probes = std::mem::take(&mut self.current);
+        let lost = probes.iter().filter(|rtt| rtt.is_none()).count() as u32;
+        let median_rtt = median(probes.iter().flatten().map(|&rtt| ms(rtt)).collect());
+        let loss = 100f32 * lost as f32 / probes.len() as f32;
+
+        // Compared against the history before it's added in
+        let (baseline_rtt, baseline_loss) = (self.rtt(), self.loss());
+        let mut anomalies = Vec::new();
+        if let (Some(period), Some(baseline)) = (median_rtt, baseline_rtt) {
+            if period / baseline >= PERIOD_FACTOR {
+                anomalies.push(format!("median rtt {:.1}x baseline ({:.2}ms vs {:.2}ms)", period / baseline, period, baseline));
+            }
+        }
+        if let Some(typical) = baseline_loss {
+            if loss >= typical + LOSS_MARGIN {
+                anomalies.push(format!("loss {:.0}% vs {:.0}% baseline", loss, typical));
+            }
+        }
+
+        // A period with nothing coming back has no rtt to learn, its loss still counts
+        if let Some(median_ms) = median_rtt.or(baseline_rtt) {
+            self.periods.push_back(Period { median_ms, loss });
+            if self.periods.len() > HISTORY {
+                self.periods.pop_front();
+            }
+        }
+
+        if anomalies.is_empty() {
+            return None;
+        }
+        Some(PeriodAnomaly {
+            sent: probes.len() as u32, lost,
+            median: median_rtt.map(|median| Duration::from_nanos((median * 1e6) as u64)),
+            baseline_rtt, baseline_loss,
+            anomalies,
+        })
+    }
+
+    /// Drop the period being filled in, to start a fresh one
+    pub fn restart_period(&mut self) {
+        self.current.clear();
+    }
+}
+
+/// Every destination's baseline, as kept in the --baseline file
+pub struct Store {
+    path: String,
+    baselines: BTreeMap<String, Baseline>,
+}
+
+impl Store {
+    /// Loads the file, a missing one is just a fresh start
+    pub fn load(path: &str) -> Result<Self> {
+        let baselines = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Store { path: path.to_string(), baselines })
+    }
+
+    /// Hands out a destination's baseline, to be given back with `put` once it's done learning
+    pub fn take(&mut self, host: &str) -> Baseline {
+        self.baselines.remove(host).unwrap_or_default()
+    }
+
+    pub fn put(&mut self, host: &str, baseline: Baseline) {
+        self.baselines.insert(host.to_string(), baseline);
+    }
+
+    /// Writes the file, through a temporary one so a crash halfway doesn't lose the history
+    pub fn save(&self) -> Result<()> {
+        let temporary = format!("{}.tmp", self.path);
+        fs::write(&temporary, serde_json::to_string_pretty(&self.baselines).unwrap())?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    Some(values[values.len() / 2]) // Upper middle for an even count, close enough
+}
+
+fn ms(time: Duration) -> f64 {
+    time.as_nanos() as f64 / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A whole period at `rtt_ms`, with `lost` of its probes lost. What it was flagged for
+    fn period(baseline: &mut Baseline, rtt_ms: u64, lost: usize) -> Option<PeriodAnomaly> {
+        (0..PERIOD).map(|i| baseline.record(if i < lost { None } else { Some(Duration::from_millis(rtt_ms)) })).last().unwrap()
+    }
+
+    fn learned(periods: &[u64]) -> Baseline {
+        let mut baseline = Baseline::default();
+        for &rtt_ms in periods {
+            period(&mut baseline, rtt_ms, 0);
+        }
+        baseline
+    }
+
+    #[test]
+    fn nothing_is_flagged_before_there_is_enough_history() {
+        let mut baseline = Baseline::default();
+        assert!(period(&mut baseline, 10, 0).is_none());
+        assert!(period(&mut baseline, 100, 0).is_none());
+        assert!(period(&mut baseline, 1, PERIOD).is_none());
+        assert_eq!((baseline.rtt(), baseline.loss()), (None, None));
+        assert!(baseline.probe_anomaly(Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn slow_periods_and_probes_are_flagged() {
+        let mut baseline = learned(&[10, 10, 10]);
+        assert!(period(&mut baseline, 19, 0).is_none());
+        let anomaly = period(&mut baseline, 20, 0).unwrap();
+        assert_eq!(anomaly.anomalies, ["median rtt 2.0x baseline (20.00ms vs 10.00ms)"]);
+        assert_eq!((anomaly.median, anomaly.baseline_rtt), (Some(Duration::from_millis(20)), Some(10.0)));
+
+        assert_eq!(baseline.probe_anomaly(Duration::from_millis(40)).as_deref(), Some("rtt 4x baseline"));
+        assert!(baseline.probe_anomaly(Duration::from_millis(39)).is_none());
+    }
+
+    #[test]
+    fn loss_is_flagged_past_the_margin() {
+        let mut baseline = learned(&[10, 10, 10]);
+        assert!(period(&mut baseline, 10, 1).is_none()); // 5%
+        let anomaly = period(&mut baseline, 10, 2).unwrap(); // 10%
+        assert_eq!(anomaly.anomalies, ["loss 10% vs 0% baseline"]);
+        assert_eq!((anomaly.sent, anomaly.lost), (PERIOD as u32, 2));
+    }
+
+    #[test]
+    fn a_period_with_everything_lost_learns_the_baseline_rtt() {
+        let mut baseline = learned(&[10, 10, 10]);
+        let anomaly = period(&mut baseline, 10, PERIOD).unwrap();
+        assert_eq!((anomaly.median, anomaly.anomalies.len()), (None, 1));
+        assert_eq!(baseline.periods.len(), 4);
+        assert_eq!(baseline.periods.back().map(|period| (period.median_ms, period.loss)), Some((10.0, 100.0)));
+        assert_eq!(baseline.rtt(), Some(10.0));
+    }
+
+    #[test]
+    fn old_periods_age_out() {
+        let mut baseline = learned(&[10; HISTORY]);
+        for _ in 0..HISTORY / 2 + 1 {
+            period(&mut baseline, 30, 0);
+        }
+        assert_eq!(baseline.periods.len(), HISTORY);
+        assert_eq!(baseline.rtt(), Some(30.0));
+    }
+}
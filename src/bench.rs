@@ -0,0 +1,86 @@
+use clap::ArgMatches;
+
+use std::time::{Duration, Instant};
+
+use crate::ping::Pinger;
+use crate::{locale, privilege, util};
+use crate::color::*;
+
+/// Pings as fast as possible (one outstanding probe at a time) against a local target,
+/// to find out how many probes per second this machine can push through ring.
+pub fn run(matches: &ArgMatches) {
+    let target = matches.value_of("target").unwrap_or("127.0.0.1");
+    let target = util::resolve_dest(target).expect("Error resolving benchmark target");
+
+    let duration = matches.value_of("duration").unwrap_or("5s");
+    let duration = humantime::parse_duration(duration).expect("Invalid duration for benchmark (ex: -d 5s, -d 1m)");
+
+    let mut pinger = privilege::unwrap_socket(Pinger::new(target), target, "Error constructing pinger");
+
+    println!("{} {} for {}", "BENCH".cyan(), target.to_string().bold(), humantime::format_duration(duration));
+
+    let mut send_times = Vec::new();
+    let mut rtts = Vec::new();
+    let mut receive_times = Vec::new();
+    let mut lost = 0;
+
+    let begin = Instant::now();
+    while begin.elapsed() < duration {
+        let send_start = Instant::now();
+        let sequence_num = match pinger.ping() {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Error sending ping: {}", e);
+                lost += 1;
+                continue;
+            }
+        };
+        send_times.push(send_start.elapsed());
+
+        let receive_start = Instant::now();
+        match pinger.receive_pong(sequence_num, Duration::from_secs(1)) {
+            Ok(pong) => {
+                receive_times.push(receive_start.elapsed());
+                rtts.push(pong.rtt);
+            }
+            Err(_) => lost += 1,
+        }
+    }
+    let elapsed = begin.elapsed();
+
+    let probes = send_times.len() + lost;
+    println!("{} probes in {}s: {} probes/s, {} lost",
+        probes.to_string().bold(), locale::decimal(elapsed.as_secs_f32(), 2),
+        format!("{:.0}", probes as f32 / elapsed.as_secs_f32()).bold(), lost);
+
+    // The receive stage covers the rtt, parsing, and everything after (reverse dns, etc.)
+    let processing = pinger.processing_stats();
+    let mut other: Vec<Duration> = receive_times.iter().zip(rtts.iter())
+        .map(|(receive, rtt)| receive.saturating_sub(*rtt))
+        .collect();
+
+    println!("{:>18} {:>10} {:>10} {:>10}", "stage", "avg", "p99", "max");
+    print_stage("send", &mut send_times);
+    print_stage("rtt (kernel+wire)", &mut rtts);
+    println!("{:>18} {:>10} {:>10} {:>10}", "parse",
+        format_us(processing.average()), "-", format_us(processing.max));
+    print_stage("after receive", &mut other);
+}
+
+fn print_stage(name: &str, times: &mut [Duration]) {
+    if times.is_empty() {
+        println!("{:>18} {:>10} {:>10} {:>10}", name, "-", "-", "-");
+        return;
+    }
+
+    times.sort();
+    let avg = times.iter().sum::<Duration>() / times.len() as u32;
+    let p99 = times[(times.len() - 1) * 99 / 100];
+    let max = times[times.len() - 1];
+
+    println!("{:>18} {:>10} {:>10} {:>10}", name, format_us(avg), format_us(p99), format_us(max));
+}
+
+fn format_us(time: Duration) -> String {
+    format!("{}us", locale::decimal(time.as_nanos() as f32 / 1000f32, 2))
+}
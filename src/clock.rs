@@ -0,0 +1,104 @@
+//! Noticing when time didn't pass normally. Round trips are all timed with the
+//! monotonic clock, so wall clock steps (NTP, someone running `date -s`) can't bend
+//! them, but that clock also stops while the system is suspended. A probe that was
+//! out over a laptop lid being closed is meaningless either way, so sessions watch
+//! for both and leave the probes caught in them out of the statistics. A suspend, or
+//! the process being stopped for a long while, also starts a new segment of the stats.
+
+use std::time::Duration;
+
+// Anything smaller is just scheduling noise, or NTP slewing
+const THRESHOLD_NANOS: i128 = 1_000_000_000;
+// How much longer than expected a gap between checks can be before it's a stall
+const STALL_SLACK: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy)]
+pub enum Jump {
+    Suspend(Duration), // How long the system was asleep
+    Stall(Duration),   // How long we went without running (Ctrl+Z, a paused VM), past what was expected
+    Step(i128),        // How far the wall clock moved, in nanoseconds (negative is backwards)
+}
+
+impl Jump {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Jump::Suspend(_) => "suspend",
+            Jump::Stall(_) => "stall",
+            Jump::Step(_) => "step",
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        match *self {
+            Jump::Suspend(time) | Jump::Stall(time) => time.as_secs_f64(),
+            Jump::Step(nanos) => nanos as f64 / 1e9,
+        }
+    }
+
+    /// For people, ex: "suspended for 34m 2s" or "wall clock stepped -3.20s"
+    pub fn describe(&self) -> String {
+        match *self {
+            Jump::Suspend(time) => format!("suspended for {}", whole_seconds(time)),
+            Jump::Stall(time) => format!("stalled for {}", whole_seconds(time)),
+            Jump::Step(nanos) => format!("wall clock stepped {:+.2}s", nanos as f64 / 1e9),
+        }
+    }
+
+    /// Whether time went missing, rather than just the wall clock moving. Those split a
+    /// run into segments, an hour asleep isn't an hour long outage
+    pub fn is_gap(&self) -> bool {
+        !matches!(self, Jump::Step(_))
+    }
+
+    /// What a segment started after, ex: "34m 2s suspend"
+    pub fn cause(&self) -> String {
+        match *self {
+            Jump::Suspend(time) | Jump::Stall(time) => format!("{} {}", whole_seconds(time), self.kind()),
+            Jump::Step(_) => self.describe(),
+        }
+    }
+}
+
+/// Compares the clocks every time it's checked, for jumps since the last check
+pub struct Watch {
+    monotonic: i128,
+    boottime: i128,
+    realtime: i128,
+}
+
+impl Watch {
+    pub fn start() -> Self {
+        Watch { monotonic: read(libc::CLOCK_MONOTONIC), boottime: read(libc::CLOCK_BOOTTIME), realtime: read(libc::CLOCK_REALTIME) }
+    }
+
+    /// Any jump since the last check, which was at most `expected` ago if nothing got in
+    /// the way. Boottime is monotonic time plus time suspended, and the wall clock should
+    /// move along with it
+    pub fn check(&mut self, expected: Duration) -> Option<Jump> {
+        let now = Watch::start();
+        let monotonic = now.monotonic - self.monotonic;
+        let boottime = now.boottime - self.boottime;
+        let realtime = now.realtime - self.realtime;
+        *self = now;
+
+        if boottime - monotonic >= THRESHOLD_NANOS {
+            Some(Jump::Suspend(Duration::from_nanos((boottime - monotonic) as u64)))
+        } else if monotonic > (expected + STALL_SLACK).as_nanos() as i128 {
+            Some(Jump::Stall(Duration::from_nanos((monotonic - expected.as_nanos() as i128) as u64)))
+        } else if (realtime - boottime).abs() >= THRESHOLD_NANOS {
+            Some(Jump::Step(realtime - boottime))
+        } else {
+            None
+        }
+    }
+}
+
+fn whole_seconds(time: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(time.as_secs().max(1)))
+}
+
+fn read(clock: libc::clockid_t) -> i128 {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(clock, &mut time) };
+    time.tv_sec as i128 * 1_000_000_000 + time.tv_nsec as i128
+}
@@ -0,0 +1,26 @@
+//! Colors for the human output, or plain text when built without the `color` feature
+//! (minimal builds, for routers with serial consoles nobody reads colors on). Everything
+//! uses the colored crate's methods through here, so neither way needs any cfg elsewhere.
+
+#[cfg(feature = "color")]
+pub use colored::{Colorize, ColoredString};
+
+#[cfg(not(feature = "color"))]
+pub type ColoredString = String;
+
+#[cfg(not(feature = "color"))]
+pub trait Colorize {
+    fn plain(self) -> ColoredString;
+
+    fn bold(self) -> ColoredString where Self: Sized { self.plain() }
+    fn cyan(self) -> ColoredString where Self: Sized { self.plain() }
+    fn green(self) -> ColoredString where Self: Sized { self.plain() }
+    fn magenta(self) -> ColoredString where Self: Sized { self.plain() }
+    fn red(self) -> ColoredString where Self: Sized { self.plain() }
+    fn yellow(self) -> ColoredString where Self: Sized { self.plain() }
+}
+
+#[cfg(not(feature = "color"))]
+impl Colorize for &str {
+    fn plain(self) -> ColoredString { self.to_string() }
+}
@@ -0,0 +1,141 @@
+//! The config file, the layer under the environment. Any option that can be set with a
+//! RING_ variable can be set here too, by its long name. It's ~/.config/ring/config (or
+//! under $XDG_CONFIG_HOME), RING_CONFIG names another one:
+//!
+//!     interval = 500ms
+//!     format = json
+//!
+//!     # Same as --rtt-colors 50ms:200ms
+//!     [rtt-colors]
+//!     slow = 50ms
+//!     very_slow = 200ms
+//!
+//! Settings fill in the RING_ variables that aren't set already, so the environment wins
+//! over the file, and the command line over both, like it always has.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// One `key = value` line
+#[derive(Debug, PartialEq)]
+struct Setting {
+    line: usize,
+    section: Option<String>,
+    key: String,
+    value: String,
+}
+
+/// Put the config file's settings in the environment, where they aren't already.
+/// `variables` are the ones the options read. Comes back with the warnings for settings
+/// that can't be used, fails if the file can't be read or parsed
+pub fn load(variables: &[String]) -> Result<Vec<String>, String> {
+    // A missing file is only a problem when it was asked for
+    let (path, named) = match env::var_os("RING_CONFIG") {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Vec::new()),
+        },
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound && !named => return Ok(Vec::new()),
+        Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+    };
+    let settings = parse(&text).map_err(|line| format!("{}:{}: expected key = value", path.display(), line))?;
+
+    let mut warnings = Vec::new();
+    let (mut slow, mut very_slow) = (None, None);
+    for setting in &settings {
+        let at = format!("{}:{}", path.display(), setting.line);
+        match setting.section.as_deref() {
+            None => {
+                let variable = format!("RING_{}", setting.key.to_uppercase().replace('-', "_"));
+                if variables.contains(&variable) {
+                    fill(&variable, &setting.value);
+                } else {
+                    warnings.push(format!("{}: {} can't be set in the config file, only options that take a value can", at, setting.key));
+                }
+            }
+            Some("rtt-colors") => match setting.key.as_str() {
+                "slow" => slow = Some(&setting.value),
+                "very_slow" | "very-slow" => very_slow = Some(&setting.value),
+                key => warnings.push(format!("{}: [rtt-colors] only has slow and very_slow, not {}", at, key)),
+            },
+            Some(section) => warnings.push(format!("{}: no [{}] section, {} isn't used", at, section, setting.key)),
+        }
+    }
+    match (slow, very_slow) {
+        (Some(slow), Some(very_slow)) => fill("RING_RTT_COLORS", &format!("{}:{}", slow, very_slow)),
+        (None, None) => {}
+        _ => warnings.push(format!("{}: [rtt-colors] needs both slow and very_slow", path.display())),
+    }
+    Ok(warnings)
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("ring").join("config"))
+}
+
+// The environment wins, only fill in what isn't there. This runs first thing in main,
+// before there are any other threads to see the environment change
+fn fill(variable: &str, value: &str) {
+    if env::var_os(variable).is_none() {
+        env::set_var(variable, value);
+    }
+}
+
+// Blank lines and #comments are skipped, [section] applies to the lines after it. Fails
+// with the number of the first line that's none of those or `key = value`
+fn parse(text: &str) -> Result<Vec<Setting>, usize> {
+    let mut settings = Vec::new();
+    let mut section = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = Some(name.trim().to_string());
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(number + 1)?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() {
+            return Err(number + 1);
+        }
+        // Quotes are optional, for values with spaces at the ends or a # in them
+        let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+        settings.push(Setting { line: number + 1, section: section.clone(), key: key.to_string(), value: value.to_string() });
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(line: usize, section: Option<&str>, key: &str, value: &str) -> Setting {
+        Setting { line, section: section.map(String::from), key: key.to_string(), value: value.to_string() }
+    }
+
+    #[test]
+    fn settings_take_the_section_above_them() {
+        let text = "# ring\ninterval = 500ms\n\n[rtt-colors]\nslow=50ms\n  very_slow = \"200ms\"  \n";
+        assert_eq!(parse(text).unwrap(), vec![
+            setting(2, None, "interval", "500ms"),
+            setting(5, Some("rtt-colors"), "slow", "50ms"),
+            setting(6, Some("rtt-colors"), "very_slow", "200ms"),
+        ]);
+    }
+
+    #[test]
+    fn lines_that_arent_settings_fail_with_their_number() {
+        assert_eq!(parse("interval = 1s\nnumeric\n"), Err(2));
+        assert_eq!(parse("= 1s"), Err(1));
+    }
+}
@@ -0,0 +1,368 @@
+//! Just enough DNS to ask one server directly (--resolver) instead of going through the
+//! system's resolver, with /etc/hosts, nsswitch and whatever resolv.conf says in the way.
+//! For seeing what that server in particular answers, when another one's answers
+//! are in doubt. Over HTTPS (--doh) or TLS (--dot) when the network's DNS can't be
+//! trusted at all, since nothing on the way can see or change those answers. Also what
+//! `ring sweep --axfr` speaks.
+
+use std::io::{Read, Write, Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use rand::random;
+
+use crate::{http, util};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_SOA: u16 = 6;
+#[cfg(feature = "dns")]
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_AXFR: u16 = 252;
+
+// How long each try over UDP gets, and how many before giving up
+const TRY_TIMEOUT: Duration = Duration::from_secs(2);
+const TRIES: usize = 3;
+
+/// Which server to ask, and how
+pub enum Server {
+    Udp(SocketAddr),         // Plain DNS, over TCP when the answer doesn't fit
+    Https(String, IpAddr),   // DNS over HTTPS to this url, at this address
+    Tls(SocketAddr, String), // DNS over TLS, and the name its certificate has to be for
+}
+
+impl Server {
+    /// DNS over HTTPS to `url`. Its own name is looked up the usual way, once
+    pub fn https(url: &str) -> Result<Self> {
+        if !url.starts_with("https://") {
+            return Err(Error::new(ErrorKind::InvalidInput, "DNS over HTTPS takes an https:// url"));
+        }
+        let host = http::host(url)?;
+        Ok(Server::Https(url.to_string(), util::resolve_dest(&host)?))
+    }
+
+    /// DNS over TLS to "host[:port]", port 853 if not given. Its own name is looked up the
+    /// usual way, once
+    pub fn tls(server: &str) -> Result<Self> {
+        let (host, port) = match server.rsplit_once(':') {
+            // A bare IPv6 address is all colons, it needs brackets to have a port
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') =>
+                (host.trim_start_matches('[').trim_end_matches(']'), port.parse::<u16>().map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid port"))?),
+            _ => (server.trim_start_matches('[').trim_end_matches(']'), 853),
+        };
+        Ok(Server::Tls(SocketAddr::new(util::resolve_dest(host)?, port), host.to_string()))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Server::Udp(address) => address.to_string(),
+            Server::Https(url, _) => url.clone(),
+            Server::Tls(_, host) => format!("{} (TLS)", host),
+        }
+    }
+}
+
+/// A record from the answer section, its data is `length` bytes at `at` in the message
+pub struct Record {
+    pub rtype: u16,
+    pub at: usize,
+    pub length: usize,
+}
+
+/// The A and AAAA records for `name`, the A ones first, as `server` answers them
+pub fn lookup_host(server: &Server, name: &str) -> Result<Vec<IpAddr>> {
+    let mut addresses = Vec::new();
+    for rtype in &[TYPE_A, TYPE_AAAA] {
+        let message = exchange(server, name, *rtype)?;
+        // CNAMEs on the way are in there too, only the addresses at the end matter
+        for record in parse(&message, None)?.1 {
+            let data = &message[record.at..record.at + record.length];
+            match (record.rtype, record.length) {
+                (TYPE_A, 4) => addresses.push(IpAddr::from(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+                (TYPE_AAAA, 16) => addresses.push(IpAddr::from(ipv6(data))),
+                _ => {}
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} has no addresses for {}", server.describe(), name)));
+    }
+    log::info!("{} says {} is {}", server.describe(), name, addresses.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", "));
+    Ok(addresses)
+}
+
+/// The name `server` has for `address` (its PTR record), if any. Minimal builds don't
+/// look names up for addresses at all
+#[cfg(feature = "dns")]
+pub fn lookup_addr(server: &Server, address: IpAddr) -> Result<String> {
+    let name = match address {
+        IpAddr::V4(address) => {
+            let [a, b, c, d] = address.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        // A label per nibble, backwards
+        IpAddr::V6(address) => {
+            let nibbles: Vec<String> = address.octets().iter().rev().flat_map(|byte| vec![byte & 0xF, byte >> 4])
+                .map(|nibble| format!("{:x}", nibble)).collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    };
+
+    let message = exchange(server, &name, TYPE_PTR)?;
+    parse(&message, None)?.1.iter().find(|record| record.rtype == TYPE_PTR)
+        .and_then(|record| read_name(&message, record.at))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} has no name for {}", server.describe(), address)))
+}
+
+/// A query for `name`, with `id`. Recursion is wanted for everything but zone transfers
+pub fn query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut query = Vec::new();
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(if qtype == TYPE_AXFR { &[0x00, 0] } else { &[0x01, 0] });
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // One question
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid name {}", name)));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes()); // IN
+    Ok(query)
+}
+
+/// The response code and answers in a response. With an `id` it has to be the answer to that query
+pub fn parse(message: &[u8], id: Option<u16>) -> Result<(u16, Vec<Record>)> {
+    let truncated = || Error::new(ErrorKind::InvalidData, "truncated DNS response");
+    let word = |at: usize| message.get(at..at + 2).map(|word| u16::from_be_bytes([word[0], word[1]])).ok_or_else(truncated);
+    if id.is_some_and(|id| word(0).ok() != Some(id)) {
+        return Err(Error::new(ErrorKind::InvalidData, "answer to some other query"));
+    }
+    let rcode = word(2)? & 0xF;
+
+    let (questions, answers) = (word(4)?, word(6)?);
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(message, at).ok_or_else(truncated)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(message, at).ok_or_else(truncated)?;
+        let (rtype, length) = (word(at)?, word(at + 8)? as usize);
+        if message.len() < at + 10 + length {
+            return Err(truncated());
+        }
+        records.push(Record { rtype, at: at + 10, length });
+        at += 10 + length;
+    }
+    Ok((rcode, records))
+}
+
+// Asks `server` about `name`, and checks it could answer
+fn exchange(server: &Server, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    // Over HTTPS the id should be 0, so caches in between can tell the same question apart
+    let id = if let Server::Https(..) = server { 0 } else { random::<u16>() };
+    let query = query(id, name, qtype)?;
+    log::info!("asking {} for the {} records of {}", server.describe(), type_name(qtype), name);
+    let message = match server {
+        Server::Udp(address) => udp(*address, id, &query)?,
+        Server::Https(url, address) => http::post_dns(url, *address, &query, TRY_TIMEOUT * TRIES as u32)?,
+        Server::Tls(address, host) => tls(*address, host, &query)?,
+    };
+
+    let server = server.describe();
+    log::debug!("{} answered with {} bytes", server, message.len());
+    match parse(&message, Some(id))?.0 {
+        0 => Ok(message),
+        3 => Err(Error::new(ErrorKind::NotFound, format!("{} says there's no {}", server, name))),
+        5 => Err(Error::new(ErrorKind::PermissionDenied, format!("{} refused to answer", server))),
+        rcode => Err(Error::other(format!("{} couldn't answer (rcode {})", server, rcode))),
+    }
+}
+
+// What a record type's called, for -v
+fn type_name(rtype: u16) -> String {
+    match rtype {
+        TYPE_A => "A".to_string(),
+        TYPE_SOA => "SOA".to_string(),
+        #[cfg(feature = "dns")]
+        TYPE_PTR => "PTR".to_string(),
+        TYPE_AAAA => "AAAA".to_string(),
+        TYPE_AXFR => "AXFR".to_string(),
+        rtype => format!("type {}", rtype),
+    }
+}
+
+// Over UDP, and then TCP if the answer didn't fit
+fn udp(server: SocketAddr, id: u16, query: &[u8]) -> Result<Vec<u8>> {
+    let socket = UdpSocket::bind(if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+    socket.connect(server)?; // Only takes answers from the server
+    socket.set_read_timeout(Some(TRY_TIMEOUT))?;
+
+    let mut buf = [0; 4096];
+    let mut message = None;
+    for _ in 0..TRIES {
+        socket.send(query)?;
+        // Anything that isn't the answer (a late one to an earlier try) is skipped
+        loop {
+            match socket.recv(&mut buf) {
+                Ok(read) if buf[..read].starts_with(&id.to_be_bytes()) => {
+                    message = Some(buf[..read].to_vec());
+                    break;
+                }
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if message.is_some() {
+            break;
+        }
+        log::debug!("no answer from {} in {:?}", server, TRY_TIMEOUT);
+    }
+    let mut message = message.ok_or_else(|| Error::new(ErrorKind::TimedOut, format!("no answer from {}", server)))?;
+
+    if message.len() > 2 && message[2] & 0x02 != 0 {
+        log::debug!("the answer from {} didn't fit, asking again over TCP", server);
+        let mut stream = TcpStream::connect_timeout(&server, TRY_TIMEOUT)?;
+        stream.set_read_timeout(Some(TRY_TIMEOUT))?;
+        write_tcp(&mut stream, query)?;
+        message = read_tcp(&mut stream)?;
+    }
+    Ok(message)
+}
+
+// Over TCP like always, just with TLS around it
+#[cfg(feature = "https")]
+fn tls(server: SocketAddr, host: &str, query: &[u8]) -> Result<Vec<u8>> {
+    let stream = TcpStream::connect_timeout(&server, TRY_TIMEOUT)?;
+    let mut tls = http::handshake(std::sync::Arc::new(http::tls_config()), host, stream, || Ok(TRY_TIMEOUT))?;
+    tls.sock.set_read_timeout(Some(TRY_TIMEOUT))?;
+    write_tcp(&mut tls, query)?;
+    read_tcp(&mut tls)
+}
+
+#[cfg(not(feature = "https"))]
+fn tls(_server: SocketAddr, _host: &str, _query: &[u8]) -> Result<Vec<u8>> {
+    Err(Error::new(ErrorKind::InvalidInput, "DNS over TLS needs the `https` feature, which this ring was built without"))
+}
+
+/// Sends a query over TCP, where every message goes with its length first
+pub fn write_tcp(stream: &mut impl Write, query: &[u8]) -> Result<()> {
+    stream.write_all(&(query.len() as u16).to_be_bytes())?;
+    stream.write_all(query)
+}
+
+/// The next message over TCP
+pub fn read_tcp(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+// Where the name starting at `at` ends, it's either labels down to the root or a pointer
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(at)?;
+        match length {
+            0 => return Some(at + 1),
+            _ if length & 0xC0 == 0xC0 => return Some(at + 2),
+            _ => at += 1 + length as usize,
+        }
+    }
+}
+
+// The name starting at `at`, following pointers (but not forever)
+#[cfg(feature = "dns")]
+fn read_name(message: &[u8], mut at: usize) -> Option<String> {
+    let mut labels: Vec<String> = Vec::new();
+    for _ in 0..128 {
+        let length = *message.get(at)? as usize;
+        match length {
+            0 => return Some(labels.join(".")),
+            _ if length & 0xC0 == 0xC0 => at = (length & 0x3F) << 8 | *message.get(at + 1)? as usize,
+            _ => {
+                labels.push(String::from_utf8_lossy(message.get(at + 1..at + 1 + length)?).into_owned());
+                at += 1 + length;
+            }
+        }
+    }
+    None
+}
+
+pub fn ipv6(data: &[u8]) -> Ipv6Addr {
+    let mut octets = [0; 16];
+    octets.copy_from_slice(data);
+    Ipv6Addr::from(octets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An answer to the query for example.com, with an A record for it and an AAAA for
+    // www.example.com, both names compressed down to pointers at the question's
+    fn answer() -> Vec<u8> {
+        let mut message = query(0x1234, "example.com", TYPE_A).unwrap();
+        message[2..4].copy_from_slice(&[0x81, 0x80]);
+        message[6..8].copy_from_slice(&2u16.to_be_bytes());
+        message.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 1]);
+        message.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 12, 0, 28, 0, 1, 0, 0, 0x0E, 0x10, 0, 16]);
+        message.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        message
+    }
+
+    #[test]
+    fn queries_spell_out_each_label() {
+        let query = query(0xBEEF, "www.example.com.", TYPE_AAAA).unwrap();
+        assert_eq!(&query[..12], &[0xBE, 0xEF, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..], b"\x03www\x07example\x03com\x00\x00\x1c\x00\x01");
+    }
+
+    #[test]
+    fn queries_refuse_labels_dns_cant_carry() {
+        assert!(query(1, &format!("{}.example", "a".repeat(63)), TYPE_A).is_ok());
+        assert!(query(1, &format!("{}.example", "a".repeat(64)), TYPE_A).is_err());
+        assert!(query(1, "www..example", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn answers_are_found_past_compressed_names() {
+        let message = answer();
+        let (rcode, records) = parse(&message, Some(0x1234)).unwrap();
+        assert_eq!(rcode, 0);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].rtype, &message[records[0].at..records[0].at + records[0].length]), (TYPE_A, &[192, 0, 2, 1][..]));
+        assert_eq!((records[1].rtype, records[1].length), (TYPE_AAAA, 16));
+        assert_eq!(ipv6(&message[records[1].at..]), "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn truncated_answers_and_other_ids_fail() {
+        let message = answer();
+        assert!(parse(&message, Some(0x4321)).is_err());
+        // Cut anywhere past the header, something's missing
+        for length in 12..message.len() {
+            assert_eq!(parse(&message[..length], None).err().map(|e| e.kind()), Some(ErrorKind::InvalidData), "cut at {}", length);
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn names_follow_pointers_but_not_forever() {
+        let message = answer();
+        let www = message.len() - 16 - 16;
+        assert_eq!(read_name(&message, www).as_deref(), Some("www.example.com"));
+        assert_eq!(read_name(&message, 12).as_deref(), Some("example.com"));
+
+        // A pointer to itself, and two pointing at each other
+        assert_eq!(read_name(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xC0, 12], 12), None);
+        assert_eq!(read_name(&[1, b'a', 0xC0, 4, 1, b'b', 0xC0, 0], 0), None);
+        // Running off the end of the message
+        assert_eq!(read_name(&[5, b'a', b'b'], 0), None);
+    }
+}
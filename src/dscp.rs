@@ -0,0 +1,136 @@
+use clap::ArgMatches;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ping::{Pinger, ReplyType};
+use crate::stats::{self, ProbeGroup};
+use crate::{locale, privilege, util};
+use crate::color::*;
+
+/// Ping with pairs of probes, one best effort and one marked with a DSCP class, to see
+/// whether the path treats the class differently. Rounds where only one of the pair
+/// gets an answer are the interesting part: that's a class being dropped (or favoured).
+pub fn run(matches: &ArgMatches) {
+    let host = matches.value_of("DESTINATION").unwrap();
+    let destination = util::resolve_dest(host).expect("Error resolving destination");
+
+    let class = matches.value_of("class").unwrap_or("ef");
+    let dscp = parse_dscp(class).expect("Invalid DSCP class: (ex: --class ef, --class af41, --class 46)");
+
+    let count = matches.value_of("count").map(|count| count.parse::<u32>().expect("Invalid count: (ex: -c 100)"));
+
+    let timeout = matches.value_of("timeout").unwrap_or("1s");
+    let timeout = humantime::parse_duration(timeout).expect("Invalid duration for timeout (ex: -W 1s, -W 400ms, -W 1m)");
+
+    let interval = matches.value_of("interval").unwrap_or("1s");
+    let interval = humantime::parse_duration(interval).expect("Invalid duration for interval (ex: -i 1s, -i 400ms, -i 1m)");
+    privilege::check_interval(interval, matches.is_present("force"));
+
+    // Separate sockets, so each keeps its own marking (and its own identifier)
+    let mut best_effort = privilege::unwrap_socket(Pinger::new(destination), destination, "Error constructing pinger");
+    let mut marked = privilege::unwrap_socket(Pinger::new(destination), destination, "Error constructing pinger");
+    marked.set_dscp(dscp).expect("Error setting DSCP");
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+        util::interrupt();
+    }).expect("Error setting Ctrl-C handler");
+
+    let name = class.to_uppercase();
+    println!("{} {} ({}) best effort vs {} (dscp {})", "DSCP".cyan(), host.bold(), destination, name.bold(), dscp);
+
+    let (mut be_group, mut marked_group) = (ProbeGroup::default(), ProbeGroup::default());
+    let (mut only_be, mut only_marked) = (0u32, 0u32);
+
+    let mut rounds = 0;
+    let mut round_due = Instant::now();
+    while running.load(Ordering::SeqCst) && count.is_none_or(|count| rounds < count) {
+        rounds += 1;
+
+        // Each on its own thread, so they go out together and neither reply sits in its
+        // socket while the other one is being handled
+        let wait_until = Instant::now() + timeout;
+        let probe = move |pinger: &mut Pinger| pinger.ping().ok()
+            .and_then(|seq| pinger.receive_pong(seq, wait_until.saturating_duration_since(Instant::now())).ok())
+            .filter(|pong| pong.mtype == ReplyType::Reply)
+            .map(|pong| pong.rtt);
+
+        let (be_rtt, marked_rtt) = thread::scope(|scope| {
+            let (be, marked) = (&mut best_effort, &mut marked);
+            let be_rtt = scope.spawn(move || probe(be));
+            let marked_rtt = scope.spawn(move || probe(marked));
+            (be_rtt.join().expect("Pinging thread panicked"), marked_rtt.join().expect("Pinging thread panicked"))
+        });
+        be_group.record(be_rtt);
+        marked_group.record(marked_rtt);
+
+        let ms = |rtt: Option<Duration>| rtt.map_or("lost".red().to_string(), stats::format_rtt);
+        print!("round={} be={} {}={}", rounds.to_string().bold(), ms(be_rtt), class, ms(marked_rtt));
+
+        match (be_rtt, marked_rtt) {
+            (Some(be), Some(marked)) => println!(" delta={}", stats::format_delta(marked, be)),
+            (Some(_), None) => { only_be += 1; println!(" {}", format!("only best effort got through, {} dropped", name).red().bold()) }
+            (None, Some(_)) => { only_marked += 1; println!(" {}", format!("only {} got through", name).yellow().bold()) }
+            (None, None) => println!(),
+        }
+
+        if running.load(Ordering::SeqCst) {
+            util::sleep_until_next(&mut round_due, interval);
+        }
+    }
+
+    println!();
+    println!("{} {} {}", "===".yellow(), "dscp comparison".cyan(), "===".yellow());
+    println!("{:12}  {:>4}  {:>8}  {:>7}  rtt min/avg/max", "class", "sent", "received", "loss");
+    for (label, group) in &[("best effort", be_group), (name.as_str(), marked_group)] {
+        println!("{:12}  {:>4}  {:>8}  {:>6}%  {}", label, group.sent, group.sent - group.lost, locale::decimal(group.loss(), 2), group.rtt.format());
+    }
+
+    let delta_loss = marked_group.loss() - be_group.loss();
+    print!("{} vs best effort: loss {:+.2}%", name, delta_loss);
+    match (marked_group.rtt.average(), be_group.rtt.average()) {
+        (Some(marked), Some(be)) => println!(", avg rtt {}", stats::format_delta(marked, be)),
+        _ => println!(),
+    }
+
+    if only_be > 0 {
+        println!("{} rounds where only best effort got through, {} looks to be dropped on the way", only_be.to_string().red().bold(), name);
+    }
+    if only_marked > 0 {
+        println!("{} rounds where only {} got through", only_marked.to_string().yellow().bold(), name);
+    }
+}
+
+/// DSCP from a class name (ef, af11-af43, cs0-cs7, be) or a plain number
+fn parse_dscp(class: &str) -> Option<u8> {
+    let class = class.to_lowercase();
+    let dscp = match class.as_str() {
+        "be" | "default" => 0,
+        "ef" => 46,
+        "va" => 44, // Voice admit
+        _ => {
+            if let Some(rest) = class.strip_prefix("af") {
+                // AFxy is class x, drop precedence y
+                let mut digits = rest.chars().map(|c| c.to_digit(10));
+                match (digits.next().flatten(), digits.next().flatten(), digits.next()) {
+                    (Some(x @ 1..=4), Some(y @ 1..=3), None) => (x * 8 + y * 2) as u8,
+                    _ => return None,
+                }
+            } else if let Some(rest) = class.strip_prefix("cs") {
+                match rest.parse::<u8>() {
+                    Ok(x) if x <= 7 => x * 8,
+                    _ => return None,
+                }
+            } else {
+                class.parse::<u8>().ok().filter(|dscp| *dscp < 64)?
+            }
+        }
+    };
+
+    Some(dscp)
+}
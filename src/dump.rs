@@ -0,0 +1,121 @@
+//! --debug-packets: every packet sent and read on an ICMP socket, hex dumped under what
+//! its headers say. -vv tells that a reply was ignored, this shows what it looked like,
+//! for when a middlebox mangles ICMP on the way and the parser stops taking the replies.
+
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::packet::{self, IcmpKind};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Dump every packet from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Dump `buf` to stderr, `what` happened to it and `peer` is who it went to or came from.
+/// `ip_header` for reads off raw IPv4 sockets, the only ones that hand the IP header over,
+/// everything else starts at the ICMP message
+pub fn packet(what: &str, peer: IpAddr, buf: &[u8], ip_header: bool, ipv6: bool) {
+    let mut out = format!("{}: {} bytes {} {}\n", what, buf.len(), if what == "sent" { "to" } else { "from" }, peer);
+    annotate(&mut out, buf, ip_header, ipv6);
+    hex(&mut out, buf);
+    // All at once, so the receiver thread's dumps don't end up in the middle of ours
+    eprint!("{}", out);
+}
+
+// What the headers say, with the offset each one starts at
+fn annotate(out: &mut String, buf: &[u8], ip_header: bool, ipv6: bool) {
+    let mut at = 0;
+    if ip_header {
+        let ip = match packet::parse_ipv4_header(buf) {
+            Ok(ip) => ip,
+            Err(e) => {
+                writeln!(out, "  {:04x}  IPv4 header: can't parse it ({:?})", 0, e).unwrap();
+                return;
+            }
+        };
+        let header = &ip.header;
+        let fragment = ((header.flags_and_5frag_offset as u16 & 0x1F) << 8 | header.rest_of_frag_offset as u16) * 8;
+        let flags = match header.flags_and_5frag_offset >> 5 {
+            0 => String::new(),
+            bits => format!(", flags{}{}{}", if bits & 0b100 != 0 { " reserved" } else { "" }, if bits & 0b010 != 0 { " DF" } else { "" },
+                if bits & 0b001 != 0 { " MF" } else { "" }),
+        };
+        writeln!(out, "  {:04x}  IPv4 header: {} bytes, tos {:#04x}, length {}, id {:#06x}{}, fragment offset {}, ttl {}, protocol {}, checksum {:#06x}, {} > {}",
+            0, ip.header_len, header.type_of_service, header.datagram_length, header.ip_identifier, flags, fragment, header.ttl,
+            header.protocol, header.checksum, Ipv4Addr::from(header.source_ip), Ipv4Addr::from(header.destination_ip)).unwrap();
+        if let Some(route) = &ip.route {
+            writeln!(out, "  {:04x}  record route: {}", packet::IPV4_HEADER_LEN, route.iter().map(Ipv4Addr::to_string).collect::<Vec<_>>().join(" ")).unwrap();
+        }
+        at = ip.header_len;
+    }
+
+    let family = if ipv6 { "ICMPv6" } else { "ICMP" };
+    let message = match packet::parse_icmp(&buf[at.min(buf.len())..], ipv6) {
+        Ok(message) => message,
+        Err(e) => {
+            writeln!(out, "  {:04x}  {}: can't parse it ({:?})", at, family, e).unwrap();
+            return;
+        }
+    };
+    let header = &message.header;
+    // The identifier and sequence are only in echoes, errors have other things there
+    let echo = match message.kind {
+        IcmpKind::EchoRequest | IcmpKind::EchoReply => format!(", identifier {}, sequence {}", header.identifier, header.sequence_num),
+        _ => String::new(),
+    };
+    writeln!(out, "  {:04x}  {}: type {} code {} ({}), checksum {:#06x}{}", at, family,
+        header.message_type, header.message_code, kind_name(message.kind), header.checksum, echo).unwrap();
+
+    let data_at = at + packet::ICMP_ERROR_HEADER_LEN;
+    match message.kind {
+        IcmpKind::EchoRequest | IcmpKind::EchoReply => {
+            writeln!(out, "  {:04x}  payload: {} bytes", data_at, message.data.len()).unwrap();
+        }
+        IcmpKind::Other => {}
+        _ => match packet::quoted_echo(message.data, ipv6) {
+            Some(quoted) => {
+                writeln!(out, "  {:04x}  quoting an echo request: identifier {}, sequence {}{}", data_at, quoted.identifier, quoted.sequence_num,
+                    packet::quoted_destination(message.data, ipv6).map(|to| format!(", to {}", to)).unwrap_or_default()).unwrap();
+            }
+            None => writeln!(out, "  {:04x}  quoting something that isn't an echo request", data_at).unwrap(),
+        },
+    }
+}
+
+fn kind_name(kind: IcmpKind) -> String {
+    match kind {
+        IcmpKind::EchoRequest => "echo request".to_string(),
+        IcmpKind::EchoReply => "echo reply".to_string(),
+        IcmpKind::TimeExceeded => "time exceeded".to_string(),
+        IcmpKind::Unreachable(_) => "destination unreachable".to_string(),
+        IcmpKind::Redirect(_, gateway) => format!("redirect to {}", gateway),
+        IcmpKind::ParameterProblem(_, pointer) => format!("parameter problem at byte {}", pointer),
+        IcmpKind::Other => "not one ring knows".to_string(),
+    }
+}
+
+// Like xxd: the offset, 16 bytes in hex and the printable ones as text
+fn hex(out: &mut String, buf: &[u8]) {
+    for (line, bytes) in buf.chunks(16).enumerate() {
+        write!(out, "  {:04x} ", line * 16).unwrap();
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match bytes.get(i) {
+                Some(byte) => write!(out, " {:02x}", byte).unwrap(),
+                None => out.push_str("   "),
+            }
+        }
+        let text: String = bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        writeln!(out, "  |{}|", text).unwrap();
+    }
+}
@@ -0,0 +1,481 @@
+//! Everything ring reports, as structured events. The human output is rendered from
+//! these, and the JSON output is just these serialized one per line.
+
+use serde::{Serialize, Serializer};
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use crate::origin::Origin;
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Probe(&'a ProbeEvent),
+    Round(&'a RoundEvent),
+    Burst(&'a BurstEvent),
+    Rollup(&'a RollupEvent),
+    Reachability(&'a ReachabilityEvent),
+    Summary(&'a SummaryEvent),
+    Sla(&'a SlaEvent),
+    Anomaly(&'a AnomalyEvent),
+    Clock(&'a ClockEvent),
+    Alert(&'a AlertEvent),
+    Icmp(&'a IcmpEvent),
+}
+
+// What actually goes out, the event plus where it was measured from (if we know)
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    event: Event<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<&'a Origin>,
+}
+
+impl<'a> Event<'a> {
+    pub fn to_json(self, origin: Option<&'a Origin>) -> String {
+        serde_json::to_string(&Record { event: self, origin }).unwrap()
+    }
+
+    /// For looking through with --filter and --derive scripts
+    pub fn to_value(self, origin: Option<&'a Origin>) -> serde_json::Value {
+        serde_json::to_value(Record { event: self, origin }).unwrap()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Reply,
+    Reset, // A TCP probe was refused, which still means the host answered
+    Timeout,
+    Late, // Answered after the timeout, within --grace. Still lost as far as the totals go
+    TimeExceeded,
+    Unreachable,
+    ParameterProblem,
+    Redirect, // Not an outcome, just a notice that arrived while waiting
+    Duplicate, // Not one either, another answer to a probe that already had one (DUP!)
+    LateReply, // Nor this, the answer to a probe that timed out and was counted lost
+    Interrupted,
+    Error,
+}
+
+impl Status {
+    /// Whether it's how a probe turned out, rather than a notice that came in alongside.
+    /// Only outcomes count towards the totals
+    pub fn is_outcome(self) -> bool {
+        !matches!(self, Status::Redirect | Status::Duplicate | Status::LateReply)
+    }
+}
+
+/// Why a probe was lost, as best we can tell
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    NoReply,          // Nothing came back at all
+    Unreachable,      // Something on the way said it can't get there
+    TimeExceeded,     // The ttl ran out on the way
+    ParameterProblem, // A router didn't like the packet
+    SendFailed,       // It never left, the kernel refused to send it
+    KernelDrop,       // The answer probably came, but our receive buffer was full
+    Interrupted,      // Still out when we quit
+    Late,             // The answer came after the timeout, within --grace or once it was given up on
+    Error,            // Anything else going wrong while waiting
+}
+
+impl Reason {
+    /// For people, ex: "no reply"
+    pub fn describe(self) -> &'static str {
+        match self {
+            Reason::NoReply => "no reply",
+            Reason::Unreachable => "unreachable",
+            Reason::TimeExceeded => "ttl exceeded",
+            Reason::ParameterProblem => "parameter problem",
+            Reason::SendFailed => "send failed",
+            Reason::KernelDrop => "dropped by our kernel",
+            Reason::Interrupted => "interrupted",
+            Reason::Late => "answered late",
+            Reason::Error => "other errors",
+        }
+    }
+}
+
+/// The outcome of a single probe
+#[derive(Serialize)]
+pub struct ProbeEvent {
+    pub host: String,
+    pub destination: IpAddr,
+    pub seq: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>, // Unique to the probe, across runs too (a sequence number isn't)
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>, // The local address it went out from, with --source
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip)]
+    pub rtt: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_mismatch: Option<i64>, // Reply size minus request size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corruption: Option<Corruption>, // The data came back different from what was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ecn: Option<&'static str>, // With --ecn, the codepoint the reply came back with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<&'static str>, // "hardware" when the rtt is from the NIC's stamps, with --hw-timestamps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<Vec<Ipv4Addr>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "phases_ms")]
+    pub phases: Vec<(&'static str, Duration)>, // Where the time went, for backends with several steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<Transport>, // What came back, for backends that aren't ICMP echo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>, // Explanation of an error, for people
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<String>, // How far off the --baseline it was, when it's way off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>, // For lost probes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_jump: Option<String>, // The clock jumped while it was out, so it's left out of the statistics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway_lossy: Option<bool>, // For lost probes with --with-gateway, whether the gateway was losing probes then too
+
+    // Running totals for the session, after this probe
+    pub sent: u32,
+    pub lost: u32,
+}
+
+impl ProbeEvent {
+    pub fn new(host: &str, destination: IpAddr, seq: u16, status: Status) -> Self {
+        ProbeEvent {
+            host: host.to_string(),
+            destination, seq, id: None, status, source: None,
+            from: None, hostname: None,
+            rtt: None, rtt_ms: None,
+            ttl: None, size: None, size_mismatch: None, corruption: None, ecn: None, timestamps: None,
+            route: None, gateway: None, phases: Vec::new(), transport: None, detail: None, anomaly: None, reason: None, clock_jump: None, gateway_lossy: None,
+            sent: 0, lost: 0,
+        }
+    }
+
+    pub fn set_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(rtt);
+        self.rtt_ms = Some(rtt.as_nanos() as f64 / 1e6);
+    }
+
+    pub fn loss(&self) -> f32 {
+        100f32 * (self.lost as f32) / (self.sent as f32)
+    }
+
+    /// Whether the probe counts the way the session's own totals do: only outcomes, and
+    /// not probes caught in a clock jump. What the exporters count by too
+    #[cfg(feature = "exporters")]
+    pub fn counts_towards_totals(&self) -> bool {
+        self.status.is_outcome() && self.clock_jump.is_none()
+    }
+}
+
+/// What answered a TCP probe: the handshake (SYN-ACK), a RST, or an ICMP error
+/// quoting our SYN, with the ports in it
+#[derive(Serialize, Clone, Debug)]
+pub struct Transport {
+    pub protocol: &'static str,
+    pub response: &'static str, // "syn-ack", "rst" or "icmp"
+    pub local_port: u16,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mss: Option<u32>, // What the handshake settled on
+}
+
+impl Transport {
+    /// For people, ex: "SYN-ACK 443 > 51234 mss=1460", or "quoting tcp 51234 > 443"
+    pub fn describe(&self) -> String {
+        let mut description = match self.response {
+            "icmp" => format!("quoting {} {} > {}", self.protocol, self.local_port, self.port),
+            response => format!("{} {} > {}", response.to_uppercase(), self.port, self.local_port),
+        };
+        if let Some(mss) = self.mss {
+            description += &format!(" mss={}", mss);
+        }
+        description
+    }
+}
+
+/// Where an echoed payload first differs from what was sent, and how many bytes do
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Corruption {
+    pub offset: usize,
+    pub expected: u8,
+    pub got: u8,
+    pub bytes: u32,
+}
+
+impl Corruption {
+    /// For people, like iputils has it, ex: "wrong data byte #20 should be 0x14 but was 0x94"
+    pub fn describe(&self) -> String {
+        let mut description = format!("wrong data byte #{} should be 0x{:02x} but was 0x{:02x}", self.offset, self.expected, self.got);
+        if self.bytes > 1 {
+            description += &format!(", {} bytes differ", self.bytes);
+        }
+        description
+    }
+}
+
+/// One `--round` worth of probes
+#[derive(Serialize)]
+pub struct RoundEvent {
+    pub host: String,
+    pub round: u32,
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+}
+
+/// Every --summary-interval, the probes done since the last one
+#[derive(Serialize)]
+pub struct RollupEvent {
+    pub host: String,
+    pub rollup: u32,
+    pub seconds: f64, // How long it covers, a little over the interval since it waits on a probe finishing
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+}
+
+/// One --burst of back to back probes, once they've all been answered or given up on
+#[derive(Serialize)]
+pub struct BurstEvent {
+    pub host: String,
+    pub burst: u32,
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub lost_at: Vec<u32>, // Which ones in the burst, from 1. A policer tends to drop the tail
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+    pub rtt_spread_ms: Option<f64>, // Max less min, a queue filling up behind the burst stretches it
+}
+
+/// How a destination did against an SLA window over the last report period
+#[derive(Serialize)]
+pub struct SlaEvent {
+    pub host: String,
+    pub window: String,
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+    pub compliant: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breaches: Vec<String>,
+}
+
+/// A --baseline period that was way off from the usual
+#[derive(Serialize)]
+pub struct AnomalyEvent {
+    pub host: String,
+    pub destination: IpAddr,
+    pub sent: u32,
+    pub lost: u32,
+    pub loss: f32,
+    pub rtt_median_ms: Option<f64>,
+    pub baseline_rtt_ms: Option<f64>,
+    pub baseline_loss: Option<f32>,
+    pub anomalies: Vec<String>,
+}
+
+/// An --alert-loss or --alert-rtt alert firing or clearing, over the probes it was judged on
+#[derive(Serialize)]
+pub struct AlertEvent {
+    pub host: String,
+    pub destination: IpAddr,
+    pub state: &'static str, // firing or cleared
+    pub sent: u32,
+    pub lost: u32,
+    pub loss: f32,
+    pub rtt_avg_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breaches: Vec<String>,
+}
+
+/// With --outages, a destination going down or coming back up
+#[derive(Serialize)]
+pub struct ReachabilityEvent {
+    pub host: String,
+    pub destination: IpAddr,
+    pub state: &'static str, // down or up
+    pub start: String, // When the outage started, RFC 3339
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>, // Once it's back up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<f64>, // How long it was down, once it's back up
+    pub lost: u32,
+}
+
+/// An outage over the run, with --outages (or --notify)
+#[derive(Serialize)]
+pub struct OutageSummary {
+    pub start: String,
+    pub end: Option<String>, // None if it was still down at the end
+    pub seconds: f64,
+    pub lost: u32,
+}
+
+/// An ICMP message `ring listen` saw arrive, whoever it was for
+#[derive(Serialize)]
+pub struct IcmpEvent {
+    pub message: &'static str, // echo_request, echo_reply, time_exceeded, unreachable, redirect or parameter_problem
+    pub from: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<IpAddr>, // Only known for IPv4
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<u16>, // For errors, those of the echo request they quote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub about: Option<IpAddr>, // For errors, where the packet that caused it was going
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>, // For redirects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    pub size: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub corrupted: bool,
+}
+
+/// The system was suspended, we were stalled, or the wall clock stepped, somewhere in
+/// the last interval
+#[derive(Serialize)]
+pub struct ClockEvent {
+    pub host: String,
+    pub jump: &'static str, // suspend, stall or step
+    pub seconds: f64,       // How long it was suspended or stalled, or how far the clock moved
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<u32>, // The segment this started, for suspends and stalls
+}
+
+/// The stats for one segment of a run that was split by suspends or stalls
+#[derive(Serialize)]
+pub struct SegmentSummary {
+    pub segment: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>, // What it started after, ex: "34m 2s suspend"
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+}
+
+/// The stats for the probes sent from one --source address
+#[derive(Serialize)]
+pub struct SourceSummary {
+    pub source: IpAddr,
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+}
+
+/// The stats for the probes of one size, with --size-sweep
+#[derive(Serialize)]
+pub struct SizeSummary {
+    pub size: usize, // Data bytes after the ICMP header, like -s
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+}
+
+/// With --with-gateway, the lost probes split by whether the gateway was losing probes too
+#[derive(Serialize)]
+pub struct GatewayLosses {
+    pub with_gateway: u32,   // Probably on our side of it, the LAN or the gateway itself
+    pub beyond_gateway: u32, // Somewhere further along
+}
+
+/// With --ecn, what the replies came back with compared to what went out
+#[derive(Serialize, Clone)]
+pub struct EcnSummary {
+    pub sent: &'static str,
+    pub kept: u32,
+    pub cleared: u32, // Came back not-ect, something on the way (or the destination) bleaches it
+    pub marked: u32,  // Came back ce, congestion somewhere
+    pub changed: u32, // Came back the other ect
+}
+
+/// Totals for a destination, at the end of the run
+#[derive(Serialize)]
+pub struct SummaryEvent {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interim: bool, // Asked for mid-run with Ctrl+\, the run carries on
+    pub host: String,
+    pub destination: IpAddr,
+    pub backend: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>, // Only known for destinations on our own subnet
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub excluded: u32, // Probes left out of the totals, they were caught in a clock jump
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub lost_reasons: BTreeMap<Reason, u32>, // How many probes were lost for each reason
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<SegmentSummary>, // Empty unless the run was split
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SourceSummary>, // Empty unless probes took turns between --source addresses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sizes: Vec<SizeSummary>, // Empty unless the probes went through sizes, with --size-sweep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway_losses: Option<GatewayLosses>, // How the losses compare to the gateway's, with --with-gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub late: Option<u32>, // Answered after the timeout, within --grace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lenient_loss: Option<f32>, // The loss counting those as answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ecn: Option<EcnSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outages: Vec<OutageSummary>, // Only with --outages (or --notify)
+    pub size_mismatches: u32,
+    pub corrupted_payloads: u32,
+    pub checksum_failures: u32,
+    pub kernel_drops: u32,
+    pub duplicates: u32,
+}
+
+// As an object of phase name to milliseconds, ex: {"dns_ms": 1.2, "connect_ms": 10.5}
+fn phases_ms<S: Serializer>(phases: &[(&'static str, Duration)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(phases.iter().map(|(name, time)| (format!("{}_ms", name), time.as_nanos() as f64 / 1e6)))
+}
+
+pub fn to_ms(time: Option<Duration>) -> Option<f64> {
+    time.map(|time| time.as_nanos() as f64 / 1e6)
+}
@@ -0,0 +1,136 @@
+use clap::ArgMatches;
+use rand::random;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::ping::{Pinger, ReplyType};
+use crate::stats::{self, ProbeGroup};
+use crate::{locale, privilege, util};
+use crate::color::*;
+
+// How far off the other flows one has to be to get called out
+const LOSS_MARGIN: f32 = 10.0; // Percentage points over the median flow's loss
+const RTT_FACTOR: f32 = 1.5;   // Average rtt against the median flow's
+const RTT_MARGIN_MS: f32 = 1.0; // And by at least this much, so LAN jitter isn't a slow path
+
+/// Ping over several flows at once, each kept on whatever path it hashes to. When a link
+/// in an ECMP or LAG group is bad, only the flows that land on it suffer, which a single
+/// flow (like plain ping) either never sees or sees all the time.
+pub fn run(matches: &ArgMatches) {
+    let host = matches.value_of("DESTINATION").unwrap();
+    let destination = util::resolve_dest(host).expect("Error resolving destination");
+
+    let flows = matches.value_of("flows").unwrap_or("8");
+    let flows = flows.parse::<usize>().ok().filter(|&flows| flows >= 2).expect("Invalid number of flows: (ex: -n 8, at least 2)");
+
+    let count = matches.value_of("count").map(|count| count.parse::<u32>().expect("Invalid count: (ex: -c 100)"));
+
+    let timeout = matches.value_of("timeout").unwrap_or("1s");
+    let timeout = humantime::parse_duration(timeout).expect("Invalid duration for timeout (ex: -W 1s, -W 400ms, -W 1m)");
+
+    let interval = matches.value_of("interval").unwrap_or("1s");
+    let interval = humantime::parse_duration(interval).expect("Invalid duration for interval (ex: -i 1s, -i 400ms, -i 1m)");
+    privilege::check_interval(interval, matches.is_present("force"));
+
+    // A socket per flow, for its own identifier. The checksum stays the same across its
+    // probes so the fields routers hash never change, and on IPv6 so does the flow label
+    let mut pingers = Vec::with_capacity(flows);
+    let mut keys = Vec::with_capacity(flows);
+    for _ in 0..flows {
+        let mut pinger = privilege::unwrap_socket(Pinger::new(destination), destination, "Error constructing pinger");
+        pinger.set_flow_sum(random::<u16>());
+        let key = if destination.is_ipv6() {
+            let label = 1 + random::<u32>() % 0x7FFFF; // Labels above that are for the kernel to hand out
+            pinger.set_flow_label(label).expect("Error setting flow label");
+            format!("label {:05x}", label)
+        } else {
+            String::new()
+        };
+        keys.push(format!("id {:04x} {}", pinger.identifier(), key).trim_end().to_string());
+        pingers.push(pinger);
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+        util::interrupt();
+    }).expect("Error setting Ctrl-C handler");
+
+    println!("{} {} ({}) over {} flows", "FLOWS".cyan(), host.bold(), destination, flows);
+
+    let mut groups = vec![ProbeGroup::default(); flows];
+    let mut rounds = 0;
+    let mut round_due = Instant::now();
+    while running.load(Ordering::SeqCst) && count.is_none_or(|count| rounds < count) {
+        rounds += 1;
+
+        // All out together, then collected against the same deadline. Replies get timed
+        // by each pinger's receiver thread, so waiting on one doesn't hold up the others
+        let wait_until = Instant::now() + timeout;
+        let sent: Vec<_> = pingers.iter_mut().map(|pinger| pinger.ping().ok()).collect();
+        let rtts: Vec<Option<Duration>> = pingers.iter().zip(&sent).map(|(pinger, seq)| {
+            let seq = (*seq)?;
+            let pong = pinger.receive_pong(seq, wait_until.saturating_duration_since(Instant::now()));
+            if pong.is_err() {
+                pinger.forget(seq);
+            }
+            pong.ok().filter(|pong| pong.mtype == ReplyType::Reply).map(|pong| pong.rtt)
+        }).collect();
+
+        print!("round={}", rounds.to_string().bold());
+        for (group, rtt) in groups.iter_mut().zip(&rtts) {
+            group.record(*rtt);
+            match rtt {
+                Some(rtt) => print!(" {}", stats::format_rtt(*rtt)),
+                None => print!(" {}", "lost".red()),
+            }
+        }
+        println!();
+
+        if running.load(Ordering::SeqCst) {
+            util::sleep_until_next(&mut round_due, interval);
+        }
+    }
+
+    println!();
+    println!("{} {} {}", "===".yellow(), "per flow".cyan(), "===".yellow());
+    println!("{:4}  {:16}  {:>4}  {:>8}  {:>7}  rtt min/avg/max", "flow", "", "sent", "received", "loss");
+    for (flow, (group, key)) in groups.iter().zip(&keys).enumerate() {
+        println!("{:4}  {:16}  {:>4}  {:>8}  {:>6}%  {}", flow + 1, key, group.sent, group.sent - group.lost,
+            locale::decimal(group.loss(), 2), group.rtt.format());
+    }
+
+    // Every flow is compared to the typical one, a lossy member only drags down its share
+    let median_loss = median(groups.iter().map(|group| group.loss()).collect()).unwrap_or(0.0);
+    let median_rtt = median(groups.iter().filter_map(|group| group.rtt.average()).map(stats::as_ms).collect());
+
+    let mut odd = 0;
+    for (flow, group) in groups.iter().enumerate() {
+        if group.lost >= 2 && group.loss() >= median_loss + LOSS_MARGIN {
+            odd += 1;
+            println!("{} flow {} lost {}% against {}% for the typical flow, its path looks lossy", "!".red().bold(), flow + 1,
+                locale::decimal(group.loss(), 2), locale::decimal(median_loss, 2));
+        }
+        if let (Some(average), Some(typical)) = (group.rtt.average().map(stats::as_ms), median_rtt) {
+            if average >= typical * RTT_FACTOR && average - typical >= RTT_MARGIN_MS {
+                odd += 1;
+                println!("{} flow {} averaged {} against {} for the typical flow, its path looks slower", "!".yellow().bold(), flow + 1,
+                    stats::format_rtt(Duration::from_secs_f32(average / 1e3)), stats::format_rtt(Duration::from_secs_f32(typical / 1e3)));
+            }
+        }
+    }
+    if odd == 0 && rounds > 0 {
+        println!("Every flow looks alike, no sign of one bad path");
+    }
+}
+
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    Some(values[values.len() / 2])
+}
@@ -0,0 +1,155 @@
+//! --with-gateway: pinging the first hop alongside the destination, so every probe lost
+//! on the way to the destination says whether the gateway was losing probes at the same
+//! time. If it was, the trouble is on our side of it (the LAN, wifi, the gateway itself),
+//! if it wasn't, it's somewhere beyond.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The gateway's recent probes kept for comparing against, plenty for any sane timeout
+const PROBES_KEPT: usize = 256;
+
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+const RTF_REJECT: u32 = 0x0200;
+
+struct Route {
+    destination: IpAddr,
+    prefix: u8,
+    gateway: Option<IpAddr>, // None for a subnet we're on
+    metric: u32,
+    interface: String,
+}
+
+/// The first hop on the way to `target`, from the main routing table, with the interface
+/// it's on (IPv6 gateways are link-local, they're no use without it)
+pub fn find(target: IpAddr) -> Result<(IpAddr, u32)> {
+    // The most specific route wins, then the lowest metric
+    let mut best: Option<Route> = None;
+    for route in routes(target.is_ipv6())? {
+        if in_prefix(target, route.destination, route.prefix) && best.as_ref().is_none_or(|best|
+            route.prefix > best.prefix || (route.prefix == best.prefix && route.metric < best.metric)) {
+            best = Some(route);
+        }
+    }
+
+    let route = best.ok_or_else(|| Error::new(ErrorKind::NotFound, "there's no route to it"))?;
+    let gateway = route.gateway.ok_or_else(|| Error::new(ErrorKind::NotFound, "it's on a local subnet, there's no gateway in between"))?;
+    let index = unsafe { libc::if_nametoindex(std::ffi::CString::new(route.interface)?.as_ptr()) };
+    Ok((gateway, index))
+}
+
+fn routes(ipv6: bool) -> Result<Vec<Route>> {
+    let hex = |field: &str| u32::from_str_radix(field, 16).ok();
+    let mut routes = Vec::new();
+
+    if ipv6 {
+        // destination, prefix, source, source prefix, next hop, metric, refcount, use, flags, interface
+        for line in fs::read_to_string("/proc/net/ipv6_route")?.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                continue;
+            }
+            let (destination, prefix, next_hop, metric, flags) = match (ipv6_hex(fields[0]), hex(fields[1]), ipv6_hex(fields[4]), hex(fields[5]), hex(fields[8])) {
+                (Some(destination), Some(prefix), Some(next_hop), Some(metric), Some(flags)) => (destination, prefix, next_hop, metric, flags),
+                _ => continue,
+            };
+            if flags & RTF_UP == 0 || flags & RTF_REJECT != 0 {
+                continue;
+            }
+            routes.push(Route {
+                destination: IpAddr::from(destination),
+                prefix: prefix as u8,
+                gateway: if flags & RTF_GATEWAY != 0 { Some(IpAddr::from(next_hop)) } else { None },
+                metric,
+                interface: fields[9].to_string(),
+            });
+        }
+    } else {
+        // interface, destination, gateway, flags, refcount, use, metric, mask, ... in host order
+        for line in fs::read_to_string("/proc/net/route")?.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 {
+                continue;
+            }
+            let (destination, gateway, flags, metric, mask) = match (hex(fields[1]), hex(fields[2]), hex(fields[3]), hex(fields[6]), hex(fields[7])) {
+                (Some(destination), Some(gateway), Some(flags), Some(metric), Some(mask)) => (destination, gateway, flags, metric, mask),
+                _ => continue,
+            };
+            if flags & RTF_UP == 0 || flags & RTF_REJECT != 0 {
+                continue;
+            }
+            routes.push(Route {
+                destination: IpAddr::from(Ipv4Addr::from(destination.to_ne_bytes())),
+                prefix: u32::from_be_bytes(mask.to_ne_bytes()).count_ones() as u8,
+                gateway: if flags & RTF_GATEWAY != 0 { Some(IpAddr::from(Ipv4Addr::from(gateway.to_ne_bytes()))) } else { None },
+                metric,
+                interface: fields[0].to_string(),
+            });
+        }
+    }
+
+    Ok(routes)
+}
+
+fn ipv6_hex(field: &str) -> Option<Ipv6Addr> {
+    u128::from_str_radix(field, 16).ok().filter(|_| field.len() == 32).map(Ipv6Addr::from)
+}
+
+fn in_prefix(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// How the gateway's been doing, kept up by its session for the others to compare against
+pub struct Watch {
+    pub address: IpAddr,
+    probes: Mutex<VecDeque<(Instant, bool)>>, // When its recent probes went out, and whether they were answered
+}
+
+impl Watch {
+    pub fn new(address: IpAddr) -> Self {
+        Watch { address, probes: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Count one of the gateway's probes, that went out at `sent_at`
+    pub fn record(&self, sent_at: Instant, answered: bool) {
+        let mut probes = self.probes.lock().unwrap();
+        probes.push_back((sent_at, answered));
+        if probes.len() > PROBES_KEPT {
+            probes.pop_front();
+        }
+    }
+
+    /// Whether the gateway was losing probes too when one sent at `sent_at` was lost, going by
+    /// its own that went out alongside (within half an interval). If that one isn't back yet
+    /// it's lost too when the other waited out the whole timeout (`overdue`), the gateway
+    /// answers way sooner than that. Otherwise it's too early to tell, so it goes by the one before
+    pub fn lossy_around(&self, sent_at: Instant, interval: Duration, overdue: bool) -> bool {
+        let probes = self.probes.lock().unwrap();
+        let from = sent_at.checked_sub(interval / 2).unwrap_or(sent_at);
+        let alongside: Vec<bool> = probes.iter().filter(|&&(sent, _)| sent >= from && sent <= sent_at + interval / 2)
+            .map(|&(_, answered)| answered).collect();
+        if !alongside.is_empty() {
+            return alongside.contains(&false);
+        }
+        if overdue {
+            return true;
+        }
+        // Nothing to go on at all at the start, so no blaming it
+        probes.iter().filter(|&&(sent, _)| sent < from).max_by_key(|&&(sent, _)| sent).is_some_and(|&(_, answered)| !answered)
+    }
+}
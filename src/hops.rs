@@ -0,0 +1,96 @@
+use clap::ArgMatches;
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::ping::{self, Pinger, PongResult, ReplyType};
+use crate::{privilege, stats, util};
+use crate::color::*;
+
+/// One probe for each ttl up to `--ttl-sweep N`, all sent at once, then whichever router
+/// answered at each one and how many hops away the destination is. A quick look at the
+/// first few hops, without the repeated probing of a full traceroute.
+pub fn run(matches: &ArgMatches) {
+    let max_ttl = matches.value_of("ttl-sweep").unwrap();
+    let max_ttl = match max_ttl.parse::<u8>() {
+        Ok(max_ttl) if max_ttl > 0 => max_ttl,
+        _ => panic!("Invalid number of hops: (ex: --ttl-sweep 5)"),
+    };
+
+    let timeout = matches.value_of("timeout").unwrap_or("5s");
+    let timeout = humantime::parse_duration(timeout).expect("Invalid duration for timeout (ex: -W 1s, -W 400ms, -W 1m)");
+
+    for host in matches.values_of("DESTINATION").unwrap() {
+        let destination = util::resolve_dest(host).expect("Error resolving destination");
+        println!("{} {} ({}), ttl 1-{}", "TTL SWEEP".cyan(), host.bold(), destination, max_ttl);
+
+        let hops = sweep(destination, max_ttl, timeout);
+
+        // Past the hop where the destination answered (or said it can't be reached) is more of the same
+        let last = hops.iter().filter(|(_, pong)| pong.mtype != ReplyType::TimeLimitExceeded).map(|(&ttl, _)| ttl).min().unwrap_or(max_ttl);
+        for ttl in 1..=last {
+            match hops.get(&ttl) {
+                Some(pong) => println!("{:>3}  {}  {}{}", ttl, describe(pong, destination), stats::format_rtt(pong.rtt),
+                    if pong.mtype == ReplyType::Reply { format!("  {}", "reached".green().bold()) } else { String::new() }),
+                None => println!("{:>3}  {}", ttl, "*".red()),
+            }
+        }
+
+        // The hop count is the smallest ttl that got there, the routers before it said so by answering time exceeded
+        let routers = hops.values().filter(|pong| pong.mtype == ReplyType::TimeLimitExceeded).count();
+        match hops.iter().filter(|(_, pong)| pong.mtype == ReplyType::Reply).map(|(&ttl, _)| ttl).min() {
+            Some(1) => println!("{} is {} hop away, nothing in between", host, "1".bold()),
+            Some(ttl) => println!("{} is {} hops away, {} of the {} routers before it answered", host, ttl.to_string().bold(), routers, ttl - 1),
+            None => println!("{} not reached within {} hops, {} routers answered", host, max_ttl, routers.to_string().bold()),
+        }
+        println!();
+    }
+}
+
+// The answer for each ttl that got one
+fn sweep(destination: IpAddr, max_ttl: u8, timeout: Duration) -> HashMap<u8, PongResult> {
+    let mut pinger = privilege::unwrap_socket(Pinger::new(destination), destination, "Error constructing pinger");
+
+    let mut ttls = HashMap::new(); // The ttl each sequence number went out with
+    for ttl in 1..=max_ttl {
+        pinger.set_ttl(ttl as u32).expect("Error setting ttl");
+        match pinger.ping() {
+            Ok(sequence_num) => { ttls.insert(sequence_num, ttl); }
+            Err(e) => eprintln!("Error sending ping with ttl {}: {}", ttl, e),
+        }
+    }
+
+    let mut hops = HashMap::new();
+    let wait_until = Instant::now() + timeout;
+    while hops.len() < ttls.len() {
+        let pong = match pinger.receive_any(wait_until.saturating_duration_since(Instant::now())) {
+            Ok(pong) => pong,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => break,
+            Err(e) => { eprintln!("Error receiving pong: {}", e); break }
+        };
+
+        // Redirects are only advice, the probe went on to get a real answer. Duplicates say nothing new
+        if let (Some(&ttl), false) = (ttls.get(&pong.sequence), matches!(pong.mtype, ReplyType::Redirect(..)) || pong.duplicate || pong.late) {
+            hops.insert(ttl, pong);
+        }
+    }
+
+    hops
+}
+
+// Who answered and how, ex: "192.0.2.1 (gateway)"
+fn describe(pong: &PongResult, destination: IpAddr) -> String {
+    let from = match &pong.hostname {
+        // Without a reverse entry the lookup just hands back the address
+        Some(hostname) if *hostname != pong.address.to_string() => format!("{} ({})", pong.address.to_string().yellow(), hostname),
+        _ => pong.address.to_string().yellow().to_string(),
+    };
+
+    match pong.mtype {
+        ReplyType::DestinationUnreachable(code) => format!("{} {}", from, ping::unreachable_reason(destination.is_ipv6(), code).red()),
+        ReplyType::ParameterProblem(..) => format!("{} {}", from, "parameter problem".red()),
+        _ => from,
+    }
+}
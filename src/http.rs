@@ -0,0 +1,353 @@
+use clap::ArgMatches;
+
+use std::io::{Result, Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+#[cfg(feature = "https")]
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::ping::{PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::session::Session;
+use crate::util;
+
+/// Times a whole HTTP request (resolve, connect, TLS, first byte of the response), so
+/// application latency can be put next to the network round trip. Every probe is a
+/// fresh connection, keeping one open would only measure the server.
+pub struct HttpProbe {
+    url: Url,
+    tls: Option<Arc<TlsConfig>>,
+    address: Option<IpAddr>, // Where to connect, instead of resolving the host every time
+    sequence: u16,
+}
+
+#[cfg(feature = "https")]
+pub type TlsConfig = rustls::ClientConfig;
+#[cfg(feature = "https")]
+pub type Tls = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+// Built without the `https` feature there's no such thing, https:// urls are refused
+#[cfg(not(feature = "https"))]
+enum TlsConfig {}
+
+struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// The sessions for `ring http`, the rest (cadence, output, statistics) is the same as pinging
+pub fn sessions(matches: &ArgMatches) -> Vec<Session> {
+    let urls: Vec<&str> = matches.values_of("URL").unwrap().collect();
+    let tagged = urls.len() > 1;
+
+    urls.into_iter().map(|url| {
+        let probe = HttpProbe::new(url).expect("Invalid url: (ex: https://example.com/)");
+        let destination = probe.resolve().expect("Error resolving destination").ip();
+        Session::new(url, destination, Box::new(probe), tagged)
+    }).collect()
+}
+
+impl HttpProbe {
+    pub fn new(url: &str) -> Result<Self> {
+        let url = Url::parse(url)?;
+        #[cfg(feature = "https")]
+        let tls = if url.https { Some(Arc::new(tls_config())) } else { None };
+        #[cfg(not(feature = "https"))]
+        let tls = if url.https {
+            return Err(Error::new(ErrorKind::InvalidInput, "https:// needs the `https` feature, which this ring was built without"));
+        } else {
+            None
+        };
+        Ok(HttpProbe { url, tls, address: None, sequence: 0 })
+    }
+
+    fn resolve(&self) -> Result<SocketAddr> {
+        let address = match self.address {
+            Some(address) => address,
+            None => util::resolve_dest(&self.url.host)?,
+        };
+        Ok(SocketAddr::new(address, self.url.port))
+    }
+
+    fn request(&self) -> String {
+        format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ring/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+            self.url.path, self.url.authority(), env!("CARGO_PKG_VERSION"))
+    }
+
+    // Makes a request on a new connection, noting how long each step took. Returns who
+    // answered and the status line of the response, and with a `body` reads that too
+    fn fetch(&self, request: &[u8], timeout: Duration, phases: &mut Vec<(&'static str, Duration)>, body: Option<&mut Vec<u8>>) -> Result<(SocketAddr, String)> {
+        let begin_time = Instant::now();
+        let deadline = begin_time + timeout;
+        let remaining = || {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) { Err(Error::new(ErrorKind::WouldBlock, "timed out")) } else { Ok(left) }
+        };
+        let mut mark = begin_time;
+        let mut phase = |name, phases: &mut Vec<(&'static str, Duration)>| {
+            let now = Instant::now();
+            phases.push((name, now - mark));
+            mark = now;
+        };
+
+        // Resolve every time, a slow resolver is part of what the user sees
+        let address = self.resolve()?;
+        phase("dns", phases);
+
+        let stream = TcpStream::connect_timeout(&address, remaining()?).map_err(timed_out)?;
+        stream.set_nodelay(true)?;
+        phase("connect", phases);
+
+        let status = match &self.tls {
+            #[cfg(not(feature = "https"))]
+            Some(config) => match **config {},
+            #[cfg(feature = "https")]
+            Some(config) => {
+                let mut tls = handshake(config.clone(), &self.url.host, stream, remaining)?;
+                phase("tls", phases);
+
+                exchange(&mut tls, request, remaining, body)?
+            }
+            None => {
+                let mut stream = stream;
+                exchange(&mut stream, request, remaining, body)?
+            }
+        };
+        phase("ttfb", phases);
+
+        Ok((address, status))
+    }
+}
+
+/// POSTs a JSON body to `url`, for webhooks. Returns the status line of the response
+pub fn post_json(url: &str, body: &str, timeout: Duration) -> Result<String> {
+    let probe = HttpProbe::new(url)?;
+    let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ring/{}\r\nContent-Type: application/json\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        probe.url.path, probe.url.authority(), env!("CARGO_PKG_VERSION"), body.len(), body);
+
+    let (_, status) = probe.fetch(request.as_bytes(), timeout, &mut Vec::new(), None)?;
+    Ok(status)
+}
+
+/// POSTs a DNS query to a DNS over HTTPS server at `url`, connecting to `address` (its
+/// name can't very well be looked up with itself). Returns the answer
+pub fn post_dns(url: &str, address: IpAddr, query: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+    let mut probe = HttpProbe::new(url)?;
+    probe.address = Some(address);
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ring/{}\r\nContent-Type: application/dns-message\r\n\
+        Accept: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        probe.url.path, probe.url.authority(), env!("CARGO_PKG_VERSION"), query.len()).into_bytes();
+    request.extend_from_slice(query);
+
+    let mut answer = Vec::new();
+    let (_, status) = probe.fetch(&request, timeout, &mut Vec::new(), Some(&mut answer))?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(Error::other(format!("{} answered {}", url, status)));
+    }
+    Ok(answer)
+}
+
+/// The host in `url`
+pub fn host(url: &str) -> Result<String> {
+    Ok(Url::parse(url)?.host)
+}
+
+impl Probe for HttpProbe {
+    fn ping(&mut self) -> Result<u16> {
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(self.sequence)
+    }
+
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
+        let begin_time = Instant::now();
+        let mut phases = Vec::with_capacity(4);
+        let (address, status) = self.fetch(self.request().as_bytes(), timeout, &mut phases, None)?;
+
+        Ok(PongResult {
+            address: address.ip(),
+            hostname: util::hostname(&address.ip()),
+
+            sequence: sequence_num,
+            ttl: None,
+            route: None,
+            size: 0,
+            rtt: begin_time.elapsed(),
+            mtype: ReplyType::Reply,
+            phases,
+            detail: Some(status),
+            transport: None,
+            corruption: None,
+            tos: None,
+            duplicate: false,
+            late: false,
+            hardware: None,
+        })
+    }
+
+    fn backend(&self) -> &'static str { "http" }
+}
+
+// Sends the request and waits for the status line, returns it (ex: "HTTP/1.1 200 OK"). With
+// a `body` it keeps reading, to the end of the response
+fn exchange<S: Read + Write + HasTcp>(stream: &mut S, request: &[u8], remaining: impl Fn() -> Result<Duration>, body: Option<&mut Vec<u8>>) -> Result<String> {
+    stream.tcp().set_write_timeout(Some(remaining()?))?;
+    stream.write_all(request).map_err(timed_out)?;
+    stream.flush().map_err(timed_out)?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    let mut read = |response: &mut Vec<u8>| -> Result<bool> {
+        stream.tcp().set_read_timeout(Some(remaining()?))?;
+        match stream.read(&mut buf).map_err(timed_out) {
+            Ok(0) => Ok(false),
+            Ok(bytes) => { response.extend_from_slice(&buf[..bytes]); Ok(true) }
+            // Plenty of servers close without a TLS close_notify, it's still the end
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && !response.is_empty() => Ok(false),
+            Err(e) => Err(e),
+        }
+    };
+    while !response.contains(&b'\n') {
+        if !read(&mut response)? {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before a response"));
+        }
+    }
+    let line = String::from_utf8_lossy(&response).lines().next().unwrap_or("").trim().to_string();
+
+    let body = match body {
+        Some(body) => body,
+        None => return Ok(line),
+    };
+    let headers_end = loop {
+        if let Some(at) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            break at + 4;
+        }
+        if !read(&mut response)? {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed in the headers"));
+        }
+    };
+    let headers = String::from_utf8_lossy(&response[..headers_end]).to_ascii_lowercase();
+    let header = |name: &str| headers.lines().find_map(|line| line.strip_prefix(name).and_then(|rest| rest.strip_prefix(':')).map(|value| value.trim().to_string()));
+    let length = header("content-length").and_then(|length| length.parse::<usize>().ok());
+    let chunked = header("transfer-encoding").is_some_and(|encoding| encoding.contains("chunked"));
+
+    // It's Connection: close, so without a length the body is whatever comes before the end
+    while length.is_none_or(|length| response.len() < headers_end + length) && read(&mut response)? {}
+    let mut rest = &response[headers_end..];
+    if chunked {
+        // Each chunk is its size in hex on a line of its own, then that many bytes, down to a 0
+        while let Some(at) = rest.windows(2).position(|window| window == b"\r\n") {
+            let size = usize::from_str_radix(String::from_utf8_lossy(&rest[..at]).split(';').next().unwrap_or("").trim(), 16)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid chunk size"))?;
+            if size == 0 || rest.len() < at + 2 + size {
+                break;
+            }
+            body.extend_from_slice(&rest[at + 2..at + 2 + size]);
+            rest = &rest[(at + 4 + size).min(rest.len())..];
+        }
+    } else {
+        body.extend_from_slice(&rest[..length.map_or(rest.len(), |length| length.min(rest.len()))]);
+    }
+    Ok(line)
+}
+
+/// TLS to `host` over `stream`, done within however long `remaining` says is left
+#[cfg(feature = "https")]
+pub fn handshake(config: Arc<TlsConfig>, host: &str, stream: TcpStream, remaining: impl Fn() -> Result<Duration>) -> Result<Tls> {
+    let name = rustls::ServerName::try_from(host).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let connection = rustls::ClientConnection::new(config, name).map_err(Error::other)?;
+    let mut tls = rustls::StreamOwned::new(connection, stream);
+
+    while tls.conn.is_handshaking() {
+        tls.sock.set_read_timeout(Some(remaining()?))?;
+        tls.conn.complete_io(&mut tls.sock).map_err(timed_out)?;
+    }
+    Ok(tls)
+}
+
+// The timeouts need setting on the socket underneath whatever's wrapping it
+trait HasTcp {
+    fn tcp(&self) -> &TcpStream;
+}
+
+impl HasTcp for TcpStream {
+    fn tcp(&self) -> &TcpStream { self }
+}
+
+#[cfg(feature = "https")]
+impl HasTcp for Tls {
+    fn tcp(&self) -> &TcpStream { &self.sock }
+}
+
+// Socket timeouts come back as a couple of different kinds, sessions only know WouldBlock
+fn timed_out(e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::TimedOut => Error::new(ErrorKind::WouldBlock, e),
+        _ => e,
+    }
+}
+
+#[cfg(feature = "https")]
+pub fn tls_config() -> rustls::ClientConfig {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+    }));
+
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+impl Url {
+    // Host and port the way they go in a url or Host header
+    fn authority(&self) -> String {
+        let default = if self.https { 443 } else { 80 };
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == default { host } else { format!("{}:{}", host, self.port) }
+    }
+
+    fn parse(url: &str) -> Result<Self> {
+        let invalid = |why| Error::new(ErrorKind::InvalidInput, why);
+
+        let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid("only http:// and https:// urls are supported"));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, "/"),
+        };
+
+        // Ipv6 literals are in brackets, since they're full of colons themselves
+        let (host, port) = if let Some(literal) = authority.strip_prefix('[') {
+            let end = literal.find(']').ok_or_else(|| invalid("unclosed [ in host"))?;
+            (&literal[..end], literal[end + 1..].strip_prefix(':'))
+        } else {
+            match authority.rfind(':') {
+                Some(at) => (&authority[..at], Some(&authority[at + 1..])),
+                None => (authority, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+            None if https => 443,
+            None => 80,
+        };
+
+        Ok(Url { https, host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
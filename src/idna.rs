@@ -0,0 +1,137 @@
+//! Internationalized domain names: bücher.example goes out to the resolver as
+//! xn--bcher-kva.example, which is all DNS knows (the resolver would otherwise get the
+//! UTF-8 bytes and either refuse them or look up some other name). Each non-ASCII label is
+//! lowercased and punycoded (RFC 3492). There's no Unicode normalization, names typed
+//! already composed (the usual way) come out right.
+
+use std::io::{Result, Error, ErrorKind};
+
+// RFC 3492's parameters for punycode
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+// The longest a label can be in DNS
+const MAX_LABEL_LEN: usize = 63;
+
+/// `name` the way DNS needs it, every label ASCII. ASCII names come back as they are
+pub fn to_ascii(name: &str) -> Result<String> {
+    if name.is_ascii() {
+        return Ok(name.to_string());
+    }
+    let labels: Vec<String> = name.split('.').map(|label| {
+        if label.is_ascii() {
+            return Some(label.to_string());
+        }
+        let label: Vec<char> = label.to_lowercase().chars().collect();
+        punycode(&label).map(|encoded| format!("xn--{}", encoded)).filter(|label| label.len() <= MAX_LABEL_LEN)
+    }).collect::<Option<_>>()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} can't be made into an ASCII name", name)))?;
+    Ok(labels.join("."))
+}
+
+// RFC 3492 section 6.3, the basic (ASCII) characters first and then how to insert the rest
+fn punycode(input: &[char]) -> Option<String> {
+    let mut output: String = input.iter().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut handled = basic;
+    while (handled as usize) < input.len() {
+        let next = input.iter().map(|&c| c as u32).filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((next - n).checked_mul(handled + 1)?)?;
+        n = next;
+        for &c in input {
+            let c = c as u32;
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias { T_MIN } else if k >= bias + T_MAX { T_MAX } else { k - bias };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u32) -> char {
+    if d < 26 { (b'a' + d as u8) as char } else { (b'0' + (d - 26) as u8) as char }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(code_points: &[u32]) -> String {
+        let input: Vec<char> = code_points.iter().map(|&c| char::from_u32(c).unwrap()).collect();
+        punycode(&input).unwrap()
+    }
+
+    // The samples from RFC 3492 section 7.1
+    #[test]
+    fn punycode_matches_the_rfc_samples() {
+        // (A) Arabic (Egyptian)
+        assert_eq!(encode(&[0x0644, 0x064A, 0x0647, 0x0645, 0x0627, 0x0628, 0x062A, 0x0643, 0x0644,
+            0x0645, 0x0648, 0x0634, 0x0639, 0x0631, 0x0628, 0x064A, 0x061F]), "egbpdaj6bu4bxfgehfvwxn");
+        // (B) Chinese (simplified)
+        assert_eq!(encode(&[0x4ED6, 0x4EEC, 0x4E3A, 0x4EC0, 0x4E48, 0x4E0D, 0x8BF4, 0x4E2D, 0x6587]), "ihqwcrb4cv8a8dqg056pqjye");
+        // (D) Czech, the case of the ASCII letters is kept
+        assert_eq!(encode(&[0x0050, 0x0072, 0x006F, 0x010D, 0x0070, 0x0072, 0x006F, 0x0073, 0x0074, 0x011B, 0x006E,
+            0x0065, 0x006D, 0x006C, 0x0075, 0x0076, 0x00ED, 0x010D, 0x0065, 0x0073, 0x006B, 0x0079]), "Proprostnemluvesky-uyb24dma41a");
+        // (L) 3<nen>B<gumi><kinpachi><sensei>
+        assert_eq!(encode(&[0x0033, 0x5E74, 0x0042, 0x7D44, 0x91D1, 0x516B, 0x5148, 0x751F]), "3B-ww4c5e180e575a65lsy2b");
+        // (O) <pafii>de<runba>
+        assert_eq!(encode(&[0x30D1, 0x30D5, 0x30A3, 0x30FC, 0x0064, 0x0065, 0x30EB, 0x30F3, 0x30D0]), "de-jg4avhby1noc0d");
+        // (S) All ASCII still gets the delimiter
+        assert_eq!(encode(&"-> $1.00 <-".chars().map(|c| c as u32).collect::<Vec<_>>()), "-> $1.00 <--");
+    }
+
+    #[test]
+    fn only_non_ascii_labels_are_encoded() {
+        assert_eq!(to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("Bücher.Example").unwrap(), "xn--bcher-kva.Example");
+        assert_eq!(to_ascii("例え.テスト").unwrap(), "xn--r8jz45g.xn--zckzah");
+        assert_eq!(to_ascii("www.example.com.").unwrap(), "www.example.com.");
+    }
+
+    #[test]
+    fn encoded_labels_have_to_fit_in_63_bytes() {
+        let label = |length| format!("{}ü.example", "a".repeat(length));
+        assert_eq!(to_ascii(&label(55)).unwrap().split('.').next().unwrap().len(), 63);
+        assert!(to_ascii(&label(56)).is_err());
+    }
+}
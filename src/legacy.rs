@@ -0,0 +1,201 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use rand::random;
+use socket2::{Socket, Domain, Protocol, SockAddr};
+
+use crate::packet::{self, ICMPEchoPacket, RouterAdvertisement};
+use crate::ping::{PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::util;
+
+/// ICMP messages from before echo was the only one anybody used. Hardly anything
+/// modern answers them, which is exactly what makes them useful for finding old or
+/// embedded stacks, and for checking a firewall really drops what it says it does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Query {
+    AddressMask,        // "What's my subnet mask?", IPv4 only
+    RouterSolicitation, // "Are you a router?", answered with an advertisement
+}
+
+impl Query {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mask" => Some(Query::AddressMask),
+            "router" => Some(Query::RouterSolicitation),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Query::AddressMask => "address mask",
+            Query::RouterSolicitation => "router solicitation",
+        }
+    }
+}
+
+pub struct LegacyProbe {
+    target: IpAddr,
+    query: Query,
+    socket: Socket,
+    sock_addr: SockAddr,
+
+    session: u16, // Address mask requests are matched like echoes, solicitations have no identifiers
+    sequence: u16,
+    sent_at: Instant,
+}
+
+impl LegacyProbe {
+    pub fn new(target: IpAddr, query: Query) -> Result<Self> {
+        if query == Query::AddressMask && target.is_ipv6() {
+            return Err(Error::new(ErrorKind::InvalidInput, "address mask requests are only for IPv4"));
+        }
+
+        let socket = if target.is_ipv6() {
+            let socket = Socket::new(Domain::ipv6(), socket2::Type::raw().cloexec(), Some(Protocol::icmpv6()))?;
+            // Routers ignore solicitations that could have been routed, same as neighbor discovery
+            socket.set_multicast_hops_v6(255)?;
+            socket.set_unicast_hops_v6(255)?;
+            socket
+        } else {
+            Socket::new(Domain::ipv4(), socket2::Type::raw().cloexec(), Some(Protocol::icmpv4()))?
+        };
+        socket.set_nonblocking(true)?;
+
+        Ok(LegacyProbe {
+            target, query, socket,
+            sock_addr: SockAddr::from(SocketAddr::new(target, 0)),
+            session: random::<u16>(),
+            sequence: 0,
+            sent_at: Instant::now(),
+        })
+    }
+
+    // The decoded answer in `buf` if it's one to our query, as the detail to show
+    fn parse(&self, buf: &[u8], from: IpAddr, sequence_num: u16) -> Option<(Option<u8>, String)> {
+        // Solicitations are often sent to the all-routers group, then anyone can answer
+        let from_target = from == self.target || self.target.is_multicast();
+
+        if self.target.is_ipv6() {
+            // The kernel checks ICMPv6 checksums, and keeps the ipv6 header to itself
+            if !from_target || buf.first() != Some(&packet::ROUTER_ADVERTISEMENT_V6) {
+                return None;
+            }
+            return RouterAdvertisement::parse_v6(buf).map(|advertisement| (None, advertisement.describe()));
+        }
+
+        let ip = packet::IPv4Header::parse(buf)?;
+        let icmp = buf.get(4 * (ip.version_and_header_len & 0x0F) as usize..)?;
+        if icmp.len() < 4 || util::get_checksum(icmp, 1) != u16::from_be_bytes([icmp[2], icmp[3]]) {
+            return None;
+        }
+
+        match self.query {
+            Query::AddressMask => {
+                let header = ICMPEchoPacket::parse(icmp)?;
+                if header.message_type != packet::ADDRESS_MASK_REPLY || header.identifier != self.session
+                   || header.sequence_num != sequence_num || icmp.len() < packet::ADDRESS_MASK_LEN {
+                    return None;
+                }
+                let mask = Ipv4Addr::new(icmp[8], icmp[9], icmp[10], icmp[11]);
+                Some((Some(ip.ttl), format!("mask {} (/{})", mask, u32::from(mask).count_ones())))
+            }
+            Query::RouterSolicitation => {
+                if !from_target || icmp[0] != packet::ROUTER_ADVERTISEMENT_V4 {
+                    return None;
+                }
+                RouterAdvertisement::parse_v4(icmp).map(|advertisement| (Some(ip.ttl), advertisement.describe()))
+            }
+        }
+    }
+}
+
+impl Probe for LegacyProbe {
+    fn ping(&mut self) -> Result<u16> {
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buf = match self.query {
+            Query::AddressMask => {
+                let mut buf = vec![0; packet::ADDRESS_MASK_LEN]; // Mask left as 0 for the reply to fill in
+                ICMPEchoPacket {
+                    message_type: packet::ADDRESS_MASK_REQUEST, message_code: 0, checksum: 0,
+                    identifier: self.session, sequence_num: self.sequence,
+                }.write(&mut buf);
+                buf
+            }
+            Query::RouterSolicitation => {
+                let mut buf = vec![0; packet::ROUTER_SOLICITATION_LEN];
+                buf[0] = if self.target.is_ipv6() { packet::ROUTER_SOLICITATION_V6 } else { packet::ROUTER_SOLICITATION_V4 };
+                buf
+            }
+        };
+
+        // The kernel fills in ICMPv6 checksums itself
+        if self.target.is_ipv4() {
+            util::set_checksum(&mut buf, 1);
+        }
+
+        self.sent_at = Instant::now();
+        self.socket.send_to(&buf, &self.sock_addr)?;
+        Ok(self.sequence)
+    }
+
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
+        let begin_time = Instant::now();
+        let mut buf = [0u8; 1500];
+
+        loop {
+            if !util::wait_readable(self.socket.as_raw_fd(), timeout.saturating_sub(begin_time.elapsed()))? {
+                return Err(Error::new(ErrorKind::WouldBlock, "timed out"));
+            }
+
+            let (bytes, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            let received_at = Instant::now();
+            let from = match from.as_std() {
+                Some(from) => from.ip(),
+                None => continue,
+            };
+
+            // Advertisements can't be told apart, any one is the answer to our latest solicitation
+            if let Some((ttl, detail)) = self.parse(&buf[..bytes], from, sequence_num) {
+                return Ok(PongResult {
+                    address: from,
+                    hostname: util::hostname(&from),
+
+                    sequence: sequence_num,
+                    ttl,
+                    route: None,
+                    size: 0,
+                    rtt: received_at.duration_since(self.sent_at),
+                    mtype: ReplyType::Reply,
+                    phases: Vec::new(),
+                    detail: Some(detail),
+                    transport: None,
+                    corruption: None,
+                    tos: None,
+                    duplicate: false,
+                    late: false,
+                    hardware: None,
+                });
+            }
+        }
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(format!("with icmp {}", self.query.name()))
+    }
+
+    fn backend(&self) -> &'static str {
+        match self.query {
+            Query::AddressMask => "icmp-mask",
+            Query::RouterSolicitation => "icmp-router",
+        }
+    }
+}
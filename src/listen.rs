@@ -0,0 +1,177 @@
+use clap::ArgMatches;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::event::{Event, IcmpEvent};
+use crate::output::{Output, Format};
+use crate::ping::{self, Heard, Listener, Overheard, ReplyType};
+use crate::{origin, privilege, util};
+use crate::color::*;
+
+// How long a listener waits before looking up again, Ctrl+C wakes it up sooner anyway
+const LISTEN_POLL: Duration = Duration::from_secs(1);
+
+/// Print every ICMP echo and error that arrives at this host, without sending anything.
+/// For finding out which pings actually make it here, from whoever is sending them
+pub fn run(matches: &ArgMatches) {
+    let hosts: Vec<IpAddr> = matches.values_of("host").into_iter().flatten()
+        .map(|host| util::resolve_dest(host).expect("Error resolving host"))
+        .collect();
+
+    let types: Vec<&str> = matches.values_of("type").map(|types| types.collect()).unwrap_or_default();
+
+    let identifier = matches.value_of("id").map(|id| id.parse::<u16>().expect("Invalid identifier: (ex: --id 4242)"));
+
+    let count = matches.value_of("count").map(|count| count.parse::<u32>().expect("Invalid count: (ex: -c 100)"));
+
+    let format: Format = matches.value_of("format").unwrap_or("human").parse().expect("Invalid format: (ex: --format json)");
+
+    // Only the families the hosts are in, or both when there aren't any
+    let mut families = vec![false, true];
+    if !hosts.is_empty() {
+        families.retain(|&ipv6| hosts.iter().any(|host| host.is_ipv6() == ipv6));
+    }
+
+    let listeners: Vec<Listener> = families.iter().filter_map(|&ipv6| {
+        let unspecified = if ipv6 { IpAddr::from(Ipv6Addr::UNSPECIFIED) } else { IpAddr::from(Ipv4Addr::UNSPECIFIED) };
+        match Listener::new(ipv6) {
+            // A host without IPv6 can still be listened to on IPv4, unless that's all that was asked for
+            Err(ref e) if ipv6 && families.len() > 1 && e.raw_os_error() == Some(libc::EAFNOSUPPORT) => None,
+            result => Some(privilege::unwrap_socket(result, unspecified, "Error opening ICMP socket")),
+        }
+    }).collect();
+
+    // The sockets are open, nothing from here on needs root
+    if let Err(e) = privilege::drop_to_invoker(false) {
+        eprintln!("{} couldn't drop privileges: {}", "Error:".red().bold(), e);
+        std::process::exit(1);
+    }
+
+    ctrlc::set_handler(util::interrupt).expect("Error setting Ctrl-C handler");
+
+    let (sender, heard) = mpsc::channel::<Overheard>();
+    let names: Vec<&str> = families.iter().map(|&ipv6| if ipv6 { "IPv6" } else { "IPv4" }).collect();
+    for mut listener in listeners {
+        let sender = sender.clone();
+        thread::spawn(move || loop {
+            match listener.receive(LISTEN_POLL) {
+                Ok(overheard) => if overheard.into_iter().map(|overheard| sender.send(overheard)).any(|sent| sent.is_err()) { return },
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => return, // Ctrl+C
+                Err(e) => eprintln!("{} {}", "Error receiving:".red().bold(), e),
+            }
+        });
+    }
+    drop(sender); // Everything's received once the listeners are all gone
+
+    let origin = origin::detect();
+    let mut out = Output::stdout();
+    if format == Format::Human {
+        writeln!(out, "{} for ICMP on {} (nothing is sent)", "LISTEN".cyan(), names.join(" and ").bold());
+    }
+
+    let (mut requests, mut replies, mut errors) = (0, 0, 0);
+    for overheard in heard {
+        if !hosts.is_empty() && ![Some(overheard.from), overheard.to, overheard.about].iter().any(|address| address.is_some_and(|address| hosts.contains(&address))) {
+            continue;
+        }
+        if identifier.is_some() && overheard.identifier != identifier {
+            continue;
+        }
+        let kind = match overheard.heard {
+            Heard::EchoRequest => "request",
+            Heard::EchoReply => "reply",
+            Heard::Error(_) => "error",
+        };
+        if !types.is_empty() && !types.contains(&kind) {
+            continue;
+        }
+
+        match overheard.heard {
+            Heard::EchoRequest => requests += 1,
+            Heard::EchoReply => replies += 1,
+            Heard::Error(_) => errors += 1,
+        }
+
+        let event = event(overheard);
+        match format {
+            Format::Json => writeln!(out, "{}", Event::Icmp(&event).to_json(origin.as_ref())),
+            Format::Human => print_event(&mut out, &event),
+        }
+
+        if count.is_some_and(|count| requests + replies + errors >= count) {
+            break;
+        }
+    }
+
+    if format == Format::Human {
+        writeln!(out);
+        writeln!(out, "{} {} {}", "===".yellow(), "listen statistics".cyan(), "===".yellow());
+        writeln!(out, "{} echo requests, {} echo replies, {} errors", requests.to_string().bold(), replies.to_string().bold(), errors.to_string().bold());
+    }
+}
+
+fn event(overheard: Overheard) -> IcmpEvent {
+    let ipv6 = overheard.from.is_ipv6();
+    let (message, detail, gateway) = match overheard.heard {
+        Heard::EchoRequest => ("echo_request", None, None),
+        Heard::EchoReply => ("echo_reply", None, None),
+        Heard::Error(ReplyType::TimeLimitExceeded) => ("time_exceeded", Some("Time to live exceeded".to_string()), None),
+        Heard::Error(ReplyType::DestinationUnreachable(code)) => ("unreachable", Some(ping::unreachable_reason(ipv6, code).to_string()), None),
+        Heard::Error(ReplyType::ParameterProblem(code, pointer)) =>
+            ("parameter_problem", Some(format!("{}: pointer = {}", ping::parameter_problem_reason(ipv6, code), pointer)), None),
+        Heard::Error(ReplyType::Redirect(code, gateway)) => ("redirect", Some(ping::redirect_reason(ipv6, code).to_string()), Some(gateway)),
+        Heard::Error(_) => ("error", None, None), // Nothing else comes off the wire
+    };
+
+    IcmpEvent {
+        message,
+        from: overheard.from,
+        to: overheard.to,
+        identifier: overheard.identifier,
+        seq: overheard.sequence,
+        about: overheard.about,
+        gateway,
+        ttl: overheard.ttl,
+        size: overheard.size,
+        detail,
+        corrupted: overheard.corrupted,
+    }
+}
+
+// ex: "192.0.2.7 > 192.0.2.1: echo request id=4242 icmp_seq=3 ttl=64 size=64"
+fn print_event(out: &mut Output, event: &IcmpEvent) {
+    write!(out, "{}", event.from.to_string().yellow());
+    if let Some(to) = event.to {
+        write!(out, " > {}", to);
+    }
+    write!(out, ": ");
+
+    let detail = event.detail.as_deref().unwrap_or("");
+    match event.message {
+        "echo_request" => write!(out, "{}", "echo request".cyan()),
+        "echo_reply" => write!(out, "{}", "echo reply".green()),
+        "redirect" => write!(out, "{} (New nexthop: {})", detail.yellow(), event.gateway.map(|g| g.to_string()).unwrap_or_default()),
+        _ => write!(out, "{}", detail.red()),
+    }
+
+    if let Some(about) = event.about {
+        write!(out, " for {}", about);
+    }
+    if let Some(identifier) = event.identifier {
+        write!(out, " id={}", identifier);
+    }
+    if let Some(seq) = event.seq {
+        write!(out, " icmp_seq={}", seq.to_string().bold());
+    }
+    if let Some(ttl) = event.ttl {
+        write!(out, " ttl={}", ttl);
+    }
+    write!(out, " size={}", event.size);
+    if event.corrupted {
+        write!(out, " {}", "(bad checksum)".red());
+    }
+    writeln!(out);
+}
@@ -0,0 +1,41 @@
+//! Decimal separators in the human output follow the locale (LC_NUMERIC), so "0,52ms"
+//! reads right wherever the logs end up. --ascii keeps plain dots, and JSON always
+//! has them, it's for programs.
+
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+static DECIMAL_POINT: OnceLock<String> = OnceLock::new();
+
+/// Looks up the separator once at startup, before any threads care about the locale
+pub fn init(ascii: bool) {
+    let point = if ascii { ".".to_string() } else { decimal_point().unwrap_or_else(|| ".".to_string()) };
+    let _ = DECIMAL_POINT.set(point);
+}
+
+/// `value` with `places` decimals, ex: decimal(0.5234, 2) is "0,52" in a german locale
+pub fn decimal(value: impl Into<f64>, places: usize) -> String {
+    let text = format!("{:.*}", places, value.into());
+    match DECIMAL_POINT.get().map(String::as_str) {
+        Some(".") | None => text,
+        Some(point) => text.replacen('.', point, 1),
+    }
+}
+
+// The C library knows the separator, but only while LC_NUMERIC is set, so it's put back
+// to "C" afterwards like every program starts out
+fn decimal_point() -> Option<String> {
+    unsafe {
+        if libc::setlocale(libc::LC_NUMERIC, b"\0".as_ptr() as *const libc::c_char).is_null() {
+            return None; // Not installed, or not set
+        }
+        let conventions = libc::localeconv();
+        let point = if conventions.is_null() || (*conventions).decimal_point.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr((*conventions).decimal_point).to_string_lossy().into_owned()).filter(|point| !point.is_empty())
+        };
+        libc::setlocale(libc::LC_NUMERIC, b"C\0".as_ptr() as *const libc::c_char);
+        point
+    }
+}
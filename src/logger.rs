@@ -0,0 +1,44 @@
+//! Where the `log` macros go. Warnings always show, as "Warning: ..." like they always
+//! have. -v adds what ring's setting up under the hood (sockets and the options set on
+//! them, lookups and where they went), -vv every reply it decided wasn't for it and why,
+//! and -vvv every read off a socket.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::color::*;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("{} {}", "Error:".red().bold(), record.args()),
+            Level::Warn => eprintln!("{} {}", "Warning:".yellow().bold(), record.args()),
+            // Which part of ring it's from, ping, dns and so on
+            level => {
+                let module = record.module_path().unwrap_or_default().trim_start_matches("ring::");
+                eprintln!("{} {}", format!("[{} {}]", level.to_string().to_lowercase(), module).cyan(), record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Start logging, `verbosity` is how many -v were given
+pub fn init(verbosity: u64) {
+    let _ = log::set_logger(&Logger);
+    log::set_max_level(match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    });
+}
@@ -0,0 +1,45 @@
+//! Measurements of ring itself, so regressions in the tool don't get mistaken for
+//! the network getting slower.
+
+#[cfg(feature = "count-allocations")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "count-allocations")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "count-allocations")]
+struct CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "count-allocations")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made so far, if ring was built with the counting allocator
+#[cfg(feature = "count-allocations")]
+pub fn allocations() -> Option<usize> {
+    Some(ALLOCATIONS.load(Ordering::Relaxed))
+}
+
+#[cfg(not(feature = "count-allocations"))]
+pub fn allocations() -> Option<usize> {
+    None
+}
@@ -0,0 +1,248 @@
+use clap::ArgMatches;
+use rand::random;
+use socket2::{Socket, Domain, Protocol, SockAddr};
+
+use std::collections::HashMap;
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::packet::{self, ICMPEchoPacket};
+use crate::stats::{self, RttStats};
+use crate::{privilege, util};
+use crate::color::*;
+
+// Room for the echo header and a little payload, like ping's smallest
+const REQUEST_LEN: usize = 16;
+
+/// Ping a multicast group and collect the answers from every member, rather than
+/// taking the first one like a normal ping would. ff02::1 (or 224.0.0.1, where hosts
+/// still answer it) is a quick list of who's alive on a segment.
+pub fn run(matches: &ArgMatches) {
+    let group: IpAddr = matches.value_of("GROUP").unwrap().parse().expect("Invalid group: (ex: ff02::1, 224.0.0.1)");
+    if !group.is_multicast() {
+        eprintln!("{} {} isn't a multicast group", "Error:".red().bold(), group);
+        std::process::exit(1);
+    }
+
+    let interface = matches.value_of("interface").map(|name| match unsafe { libc::if_nametoindex(std::ffi::CString::new(name).unwrap().as_ptr()) } {
+        0 => panic!("No such interface: {}", name),
+        index => (name, index),
+    });
+
+    let hops = matches.value_of("hops").unwrap_or("1");
+    let hops = hops.parse::<u32>().ok().filter(|hops| (1..=255).contains(hops)).expect("Invalid hop limit: (ex: --hops 1, up to 255)");
+
+    let count = matches.value_of("count").unwrap_or("3");
+    let count = count.parse::<u32>().expect("Invalid count: (ex: -c 3)");
+
+    let timeout = matches.value_of("timeout").unwrap_or("1s");
+    let timeout = humantime::parse_duration(timeout).expect("Invalid duration for timeout (ex: -W 1s, -W 400ms, -W 1m)");
+
+    let interval = matches.value_of("interval").unwrap_or("1s");
+    let interval = humantime::parse_duration(interval).expect("Invalid duration for interval (ex: -i 1s, -i 400ms, -i 1m)");
+    privilege::check_interval(interval, matches.is_present("force"));
+
+    let mut probe = privilege::unwrap_socket(GroupProbe::new(group, interface.map(|(_, index)| index), hops), group, "Error opening ICMP socket");
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+        util::interrupt();
+    }).expect("Error setting Ctrl-C handler");
+
+    let on = interface.map(|(name, _)| format!(" on {}", name)).unwrap_or_default();
+    println!("{} {}{}, hop limit {}", "MULTICAST".cyan(), group.to_string().bold(), on, hops);
+
+    let mut members: HashMap<IpAddr, Member> = HashMap::new();
+    let mut rounds = 0;
+    let mut round_due = Instant::now();
+    while running.load(Ordering::SeqCst) && rounds < count {
+        rounds += 1;
+        if let Err(e) = probe.send(rounds as u16) {
+            eprintln!("{} {}", "Error sending:".red().bold(), e);
+            break;
+        }
+
+        let mut answered = 0;
+        let wait_until = Instant::now() + timeout;
+        loop {
+            let (from, rtt) = match probe.receive(rounds as u16, wait_until.saturating_duration_since(Instant::now())) {
+                Ok(Some(answer)) => answer,
+                Ok(None) => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => break, // Ctrl+C
+                Err(e) => {
+                    eprintln!("{} {}", "Error receiving:".red().bold(), e);
+                    break;
+                }
+            };
+
+            let member = members.entry(from).or_insert(Member { rtt: RttStats::default(), first_round: rounds, last_round: 0 });
+            // A member answering the same request twice only counts once
+            if member.last_round == rounds {
+                continue;
+            }
+            member.last_round = rounds;
+            member.rtt.record(rtt);
+            answered += 1;
+            if member.rtt.count == 1 {
+                println!("{} answered seq={} rtt={}", from.to_string().yellow(), rounds, stats::format_rtt(rtt));
+            }
+        }
+        println!("round={} {} members answered", rounds.to_string().bold(), answered);
+
+        if running.load(Ordering::SeqCst) && rounds < count {
+            util::sleep_until_next(&mut round_due, interval);
+        }
+    }
+
+    let mut members: Vec<_> = members.into_iter().collect();
+    members.sort_by_key(|(address, _)| *address);
+
+    println!();
+    println!("{} {} {}", "===".yellow(), "multicast statistics".cyan(), "===".yellow());
+    for (address, member) in &members {
+        // Out of the requests since it was first heard, it may have only just joined
+        let asked = rounds - member.first_round + 1;
+        println!("{} answered {}/{} rtt min/avg/max={}{}", address.to_string().yellow(), member.rtt.count, asked, member.rtt.format(),
+            util::hostname(address).filter(|name| *name != address.to_string()).map(|name| format!(" ({})", name)).unwrap_or_default());
+    }
+    println!("{} members answered {} requests", members.len().to_string().bold(), rounds);
+}
+
+// Someone who answered, with their times and which requests they answered
+struct Member {
+    rtt: RttStats,
+    first_round: u32,
+    last_round: u32,
+}
+
+// An echo socket for the group. Unlike a Pinger, everything answering our requests is
+// taken in, whoever it's from
+struct GroupProbe {
+    socket: Socket,
+    sock_addr: SockAddr,
+    ipv6: bool,
+    datagram: bool,
+    session: u16,
+    sent_at: Instant,
+}
+
+impl GroupProbe {
+    fn new(group: IpAddr, interface: Option<u32>, hops: u32) -> Result<Self> {
+        let ipv6 = group.is_ipv6();
+        let (domain, protocol) = if ipv6 { (Domain::ipv6(), Protocol::icmpv6()) } else { (Domain::ipv4(), Protocol::icmpv4()) };
+
+        // Datagram if we're allowed one, like Pinger::new
+        let (socket, datagram) = match Socket::new(domain, socket2::Type::dgram().cloexec(), Some(protocol)) {
+            Ok(socket) => (socket, true),
+            Err(_) => (Socket::new(domain, socket2::Type::raw().cloexec(), Some(protocol))?, false),
+        };
+        socket.set_nonblocking(true)?;
+
+        let session = if datagram {
+            // The kernel hands out (and sticks to) the identifier, it's the port we're bound to
+            let unspecified = if ipv6 { IpAddr::from(Ipv6Addr::UNSPECIFIED) } else { IpAddr::from(Ipv4Addr::UNSPECIFIED) };
+            socket.bind(&SockAddr::from(SocketAddr::new(unspecified, 0)))?;
+            socket.local_addr()?.as_std().map(|local| local.port()).ok_or_else(|| Error::other("no local address for the socket"))?
+        } else {
+            random::<u16>()
+        };
+
+        let sock_addr = match group {
+            IpAddr::V6(group) => {
+                socket.set_multicast_hops_v6(hops)?;
+                if let Some(index) = interface {
+                    socket.set_multicast_if_v6(index)?;
+                }
+                // Link-local groups like ff02::1 need to be told which link
+                SockAddr::from(SocketAddrV6::new(group, 0, 0, interface.unwrap_or(0)))
+            }
+            IpAddr::V4(group) => {
+                socket.set_multicast_ttl_v4(hops)?;
+                if let Some(index) = interface {
+                    let request = libc::ip_mreqn {
+                        imr_multiaddr: libc::in_addr { s_addr: 0 },
+                        imr_address: libc::in_addr { s_addr: 0 },
+                        imr_ifindex: index as libc::c_int,
+                    };
+                    let set = unsafe {
+                        libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &request as *const _ as *const libc::c_void,
+                            std::mem::size_of::<libc::ip_mreqn>() as libc::socklen_t)
+                    };
+                    if set != 0 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+                SockAddr::from(SocketAddr::new(IpAddr::V4(group), 0))
+            }
+        };
+
+        Ok(GroupProbe { socket, sock_addr, ipv6, datagram, session, sent_at: Instant::now() })
+    }
+
+    fn send(&mut self, sequence: u16) -> Result<()> {
+        let mut buf = [0u8; REQUEST_LEN];
+        ICMPEchoPacket {
+            message_type: if self.ipv6 { packet::ECHO_REQUEST_V6 } else { packet::ECHO_REQUEST_V4 },
+            message_code: 0, checksum: 0,
+            identifier: self.session, sequence_num: sequence,
+        }.write(&mut buf);
+        // The kernel fills in ICMPv6 checksums itself
+        if !self.ipv6 {
+            util::set_checksum(&mut buf, 1);
+        }
+
+        self.sent_at = Instant::now();
+        self.socket.send_to(&buf, &self.sock_addr)?;
+        Ok(())
+    }
+
+    // The next member to answer request `sequence` and how long it took, None once `timeout` is up
+    fn receive(&self, sequence: u16, timeout: Duration) -> Result<Option<(IpAddr, Duration)>> {
+        let begin_time = Instant::now();
+        let mut buf = [0u8; 1500];
+
+        loop {
+            if !util::wait_readable(self.socket.as_raw_fd(), timeout.saturating_sub(begin_time.elapsed()))? {
+                return Ok(None);
+            }
+
+            let (bytes, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            let received_at = Instant::now();
+            let from = match from.as_std() {
+                Some(from) => from.ip(),
+                None => continue,
+            };
+
+            // Only raw IPv4 sockets hand over the IP header
+            let icmp = if self.ipv6 || self.datagram {
+                &buf[..bytes]
+            } else {
+                match packet::parse_ipv4_header(&buf[..bytes]) {
+                    Ok(ip) => &buf[ip.header_len..bytes],
+                    Err(_) => continue,
+                }
+            };
+            if !self.ipv6 && (icmp.len() < 4 || util::get_checksum(icmp, 1) != u16::from_be_bytes([icmp[2], icmp[3]])) {
+                continue;
+            }
+
+            let reply = if self.ipv6 { packet::ECHO_REPLY_V6 } else { packet::ECHO_REPLY_V4 };
+            match ICMPEchoPacket::parse(icmp) {
+                Some(header) if header.message_type == reply && header.identifier == self.session && header.sequence_num == sequence => {
+                    return Ok(Some((from, received_at.duration_since(self.sent_at))));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
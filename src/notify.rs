@@ -0,0 +1,30 @@
+//! --notify: a desktop notification when a destination stops answering, and another
+//! when it's back, for ring left running in a terminal nobody's looking at. Goes
+//! through notify-send (libnotify), which knows how to reach whatever notification
+//! daemon the desktop runs. When it counts as down or back up is up to reachability.rs.
+
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Pop up a notification, without waiting for it. Warns once if there's no notify-send
+pub fn send(summary: String, body: String, urgent: bool) {
+    thread::spawn(move || {
+        let shown = Command::new("notify-send")
+            .args(["--app-name", "ring", "--urgency", if urgent { "critical" } else { "normal" }])
+            .arg(&summary).arg(&body)
+            .stdin(Stdio::null()).stdout(Stdio::null())
+            .status();
+
+        let failure = match shown {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("notify-send failed ({})", status),
+            Err(e) => format!("couldn't run notify-send: {}", e),
+        };
+        if !WARNED.swap(true, Ordering::SeqCst) {
+            log::warn!("{}, no desktop notifications", failure);
+        }
+    });
+}
@@ -0,0 +1,87 @@
+//! Where ring is running from. Results gathered from a fleet of containers can't be
+//! told apart without it, so when we can tell we're in a container or VM, the JSON
+//! output carries these details along with every event.
+
+use serde::Serialize;
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Debug)]
+pub struct Origin {
+    pub hostname: String,
+    pub environment: &'static str, // docker, podman, kubernetes, lxc, containerd or vm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hypervisor: Option<String>,
+}
+
+/// Looks at the usual hints (marker files, cgroups, DMI), None when it looks like bare metal
+pub fn detect() -> Option<Origin> {
+    let cgroups = read("/proc/self/cgroup").unwrap_or_default();
+    let mountinfo = read("/proc/self/mountinfo").unwrap_or_default();
+
+    let container = if Path::new("/run/.containerenv").exists() {
+        Some("podman")
+    } else if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() || cgroups.contains("kubepods") {
+        Some("kubernetes")
+    } else if Path::new("/.dockerenv").exists() || cgroups.contains("/docker") {
+        Some("docker")
+    } else if cgroups.contains("/lxc") || std::env::var("container").is_ok_and(|c| c == "lxc") {
+        Some("lxc")
+    } else if cgroups.contains("containerd") {
+        Some("containerd")
+    } else {
+        None
+    };
+
+    let hypervisor = hypervisor();
+    let environment = container.or_else(|| hypervisor.as_ref().map(|_| "vm"))?;
+
+    Some(Origin {
+        hostname: read("/proc/sys/kernel/hostname").unwrap_or_default(),
+        environment,
+        // cgroup v1 has the id in our own cgroup, on v2 that's just "/" so look through the mounts too
+        container_id: container.and_then(|_| container_id(&cgroups).or_else(|| container_id(&mountinfo))),
+        network_mode: container.and_then(|_| network_mode()),
+        hypervisor,
+    })
+}
+
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+// Container runtimes name things after the 64 hex digit id, it's the first run of those we find
+fn container_id(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_hexdigit())
+        .find(|word| word.len() == 64)
+        .map(|id| id[..12].to_string()) // The short form, like `docker ps` shows
+}
+
+fn network_mode() -> Option<&'static str> {
+    let interfaces: Vec<String> = fs::read_dir("/sys/class/net").ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+
+    // Seeing the host's bridges means we're sharing its network namespace
+    if interfaces.iter().any(|name| name == "docker0" || name == "cni0" || name.starts_with("br-")) {
+        Some("host")
+    } else if interfaces.iter().all(|name| name == "lo") {
+        Some("none")
+    } else {
+        Some("isolated")
+    }
+}
+
+fn hypervisor() -> Option<String> {
+    const KNOWN: &[&str] = &["QEMU", "KVM", "VMware", "VirtualBox", "Xen", "Amazon EC2", "Google Compute Engine", "Microsoft Corporation", "Parallels", "Bochs"];
+
+    ["/sys/class/dmi/id/sys_vendor", "/sys/class/dmi/id/product_name"].iter()
+        .filter_map(|path| read(path))
+        .find(|value| KNOWN.iter().any(|known| value.contains(known)))
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write, BufWriter, Stdout};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How often block-buffered output gets pushed out even if the buffer isn't full,
+/// so someone tailing a pipe still sees progress
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const BUFFER_SIZE: usize = 64 * 1024;
+/// Rounds held back waiting on a destination that's fallen behind before they go out without it
+const ROUNDS_HELD: usize = 100;
+
+/// Buffered stdout shared by all the output formats. Terminals get line buffering
+/// so each reply shows up as it happens, pipes and files get block buffering with a
+/// periodic flush, so high-rate runs aren't bottlenecked on a syscall per line.
+pub struct Output {
+    writer: BufWriter<Stdout>,
+    line_buffered: bool,
+    last_flush: Instant,
+
+    flushes: u64,         // Each is one write to stdout
+    time_spent: Duration, // Total time spent formatting and writing
+
+    rounds: Option<Rounds>, // With --group-by round
+    writing: Option<usize>, // The destination whose lines these are, see `lock_for`
+}
+
+/// How concurrent destinations' lines are ordered
+#[derive(Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    Target, // As they happen, every destination's in its own order
+    Round,  // Every destination's lines for a probe together, once they're all in
+}
+
+// Lines held back for --group-by round, each round has a buffer per destination
+struct Rounds {
+    first: u32,       // The round at the front of `held`, the ones before it are out
+    probes: Vec<u32>, // How many probes each destination is done with, u32::MAX once it's stopped
+    held: VecDeque<Vec<Vec<u8>>>,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        let line_buffered = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+
+        Output {
+            writer: BufWriter::with_capacity(BUFFER_SIZE, io::stdout()),
+            line_buffered,
+            last_flush: Instant::now(),
+            flushes: 0,
+            time_spent: Duration::default(),
+            rounds: None,
+            writing: None,
+        }
+    }
+
+    /// Hold lines back so each probe's from all `targets` destinations go out together, in
+    /// the order the destinations are in. Lines have to be written through `lock_for`
+    pub fn group_by_round(&mut self, targets: usize) {
+        self.rounds = Some(Rounds { first: 0, probes: vec![0; targets], held: VecDeque::new() });
+    }
+
+    /// Lets the `write!` and `writeln!` macros be used directly. Like `print!` this
+    /// panics if stdout is gone, there's nothing useful left to do at that point.
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        let start = Instant::now();
+        if let (Some(rounds), Some(target)) = (&mut self.rounds, self.writing) {
+            if let Some(buf) = rounds.buffer(target) {
+                buf.write_fmt(args).expect("Error writing to stdout");
+                self.time_spent += start.elapsed();
+                return;
+            }
+        }
+        self.writer.write_fmt(args).expect("Error writing to stdout");
+
+        // Only ever flush whole lines, a half written line is no use to anyone reading
+        let line_done = self.writer.buffer().ends_with(b"\n");
+        if line_done && (self.line_buffered || self.last_flush.elapsed() >= FLUSH_INTERVAL) {
+            self.flush();
+        }
+
+        self.time_spent += start.elapsed();
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().expect("Error writing to stdout");
+        self.last_flush = Instant::now();
+        self.flushes += 1;
+    }
+
+    // `target` moved on to its next round, or stopped altogether. Whatever rounds every
+    // destination is done with can go out
+    fn advance(&mut self, target: usize, stopped: bool) {
+        let rounds = match &mut self.rounds {
+            Some(rounds) => rounds,
+            None => return,
+        };
+        rounds.probes[target] = if stopped { u32::MAX } else { rounds.probes[target].saturating_add(1) };
+
+        let mut out = Vec::new();
+        while let Some(round) = rounds.held.front() {
+            let everyone = rounds.probes.iter().all(|&probes| probes > rounds.first);
+            if !everyone && rounds.held.len() <= ROUNDS_HELD {
+                break;
+            }
+            out.extend(round.iter().flatten());
+            rounds.held.pop_front();
+            rounds.first += 1;
+        }
+        if rounds.held.is_empty() {
+            // Nothing's held up, so there's nothing the next round has to wait for either
+            rounds.first = rounds.probes.iter().copied().min().unwrap_or(0);
+        }
+
+        if !out.is_empty() {
+            self.writer.write_all(&out).expect("Error writing to stdout");
+            if self.line_buffered || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+                self.flush();
+            }
+        }
+    }
+
+    pub fn flushes(&self) -> u64 {
+        self.flushes
+    }
+
+    pub fn time_spent(&self) -> Duration {
+        self.time_spent
+    }
+}
+
+impl Rounds {
+    // Where `target`'s lines go for its current round, None if that's gone already (it fell
+    // too far behind) and they'd better just go out
+    fn buffer(&mut self, target: usize) -> Option<&mut Vec<u8>> {
+        let round = self.probes[target].checked_sub(self.first)? as usize;
+        let targets = self.probes.len();
+        while self.held.len() <= round {
+            self.held.push_back(vec![Vec::new(); targets]);
+        }
+        Some(&mut self.held[round][target])
+    }
+}
+
+/// The output locked for writing one destination's lines
+pub struct Locked<'a> {
+    output: MutexGuard<'a, Output>,
+    target: usize,
+}
+
+/// Lock the output to write `target`'s lines, which is what --group-by round sorts them by
+pub fn lock_for(out: &Mutex<Output>, target: usize) -> Locked<'_> {
+    let mut output = out.lock().unwrap();
+    output.writing = Some(target);
+    Locked { output, target }
+}
+
+impl Locked<'_> {
+    /// Everything about the destination's latest probe is written
+    pub fn probe_done(&mut self) {
+        self.output.advance(self.target, false);
+    }
+
+    /// The destination won't write anything more
+    pub fn stopped(&mut self) {
+        self.output.advance(self.target, true);
+    }
+}
+
+impl Deref for Locked<'_> {
+    type Target = Output;
+
+    fn deref(&self) -> &Output {
+        &self.output
+    }
+}
+
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut Output {
+        &mut self.output
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        self.output.writing = None;
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Human,
+    Json, // One JSON object per line
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "target" => Ok(GroupBy::Target),
+            "round" => Ok(GroupBy::Round),
+            _ => Err(format!("unknown grouping '{}'", s)),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
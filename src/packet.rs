@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ICMPEchoPacket {
@@ -9,6 +10,29 @@ pub struct ICMPEchoPacket {
     pub sequence_num: u16,
 }
 
+impl ICMPEchoPacket {
+    /// Reads the header at the start of `buf` in place, None if it's too short
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..ICMP_ECHO_HEADER_LEN)?;
+        Some(ICMPEchoPacket {
+            message_type: buf[0],
+            message_code: buf[1],
+            checksum: u16::from_be_bytes([buf[2], buf[3]]),
+            identifier: u16::from_be_bytes([buf[4], buf[5]]),
+            sequence_num: u16::from_be_bytes([buf[6], buf[7]]),
+        })
+    }
+
+    /// The write side of `parse`, `buf` has to have room for the header
+    pub fn write(&self, buf: &mut [u8]) {
+        buf[0] = self.message_type;
+        buf[1] = self.message_code;
+        buf[2..4].copy_from_slice(&self.checksum.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.identifier.to_be_bytes());
+        buf[6..8].copy_from_slice(&self.sequence_num.to_be_bytes());
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct IPv4Header {
     pub version_and_header_len: u8,
@@ -23,3 +47,623 @@ pub struct IPv4Header {
     pub source_ip: u32,
     pub destination_ip: u32,
 }
+
+// Link-layer probes (--arp), for when everything above it is firewalled
+
+#[derive(Serialize, Deserialize)]
+pub struct EthernetHeader {
+    pub destination: [u8; 6],
+    pub source: [u8; 6],
+    pub ethertype: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArpPacket {
+    pub hardware_type: u16,
+    pub protocol_type: u16,
+    pub hardware_len: u8,
+    pub protocol_len: u8,
+    pub operation: u16,
+    pub sender_mac: [u8; 6],
+    pub sender_ip: [u8; 4],
+    pub target_mac: [u8; 6],
+    pub target_ip: [u8; 4],
+}
+
+// Neighbor solicitations and advertisements share this layout, followed by options
+#[derive(Serialize, Deserialize)]
+pub struct NeighborMessage {
+    pub message_type: u8,
+    pub message_code: u8,
+    pub checksum: u16,
+    pub flags: u32,
+    pub target: [u8; 16],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkLayerOption {
+    pub option_type: u8,
+    pub len: u8, // In units of 8 bytes
+    pub address: [u8; 6],
+}
+
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERNET_HEADER_LEN: usize = 14;
+pub const ARP_PACKET_LEN: usize = 28;
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+pub const NEIGHBOR_SOLICITATION: u8 = 135;
+pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+pub const NEIGHBOR_MESSAGE_LEN: usize = 24;
+pub const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+pub const OPTION_TARGET_LINK_ADDRESS: u8 = 2;
+
+/// "Who has `target_ip`?" broadcast from us
+pub fn arp_request(mac: [u8; 6], ip: Ipv4Addr, target_ip: Ipv4Addr) -> (EthernetHeader, ArpPacket) {
+    let header = EthernetHeader { destination: [0xff; 6], source: mac, ethertype: ETHERTYPE_ARP };
+    let arp = ArpPacket {
+        hardware_type: 1, // Ethernet
+        protocol_type: 0x0800, // IPv4
+        hardware_len: 6, protocol_len: 4,
+        operation: ARP_REQUEST,
+        sender_mac: mac, sender_ip: ip.octets(),
+        target_mac: [0; 6], target_ip: target_ip.octets(),
+    };
+    (header, arp)
+}
+
+/// Asks `target` for its link-layer address, the kernel fills in the checksum
+pub fn neighbor_solicitation(mac: [u8; 6], target: Ipv6Addr) -> (NeighborMessage, LinkLayerOption) {
+    let message = NeighborMessage {
+        message_type: NEIGHBOR_SOLICITATION, message_code: 0,
+        checksum: 0, flags: 0,
+        target: target.octets(),
+    };
+    (message, LinkLayerOption { option_type: OPTION_SOURCE_LINK_ADDRESS, len: 1, address: mac })
+}
+
+/// Where the solicitation for `target` goes, ff02::1:ffXX:XXXX with its last 24 bits
+pub fn solicited_node_address(target: Ipv6Addr) -> Ipv6Addr {
+    let o = target.octets();
+    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | o[13] as u16, (o[14] as u16) << 8 | o[15] as u16)
+}
+
+/// The link-layer address in a neighbor advertisement's options, if it has one
+pub fn target_link_address(mut options: &[u8]) -> Option<[u8; 6]> {
+    while options.len() >= 8 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            break;
+        }
+
+        if options[0] == OPTION_TARGET_LINK_ADDRESS && len >= 8 {
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&options[2..8]);
+            return Some(mac);
+        }
+        options = &options[len..];
+    }
+    None
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// The other way around, ex: "02:42:ac:11:00:02"
+pub fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let bytes: Vec<u8> = text.trim().split(':').map(|b| u8::from_str_radix(b, 16).ok()).collect::<Option<_>>()?;
+    let mut mac = [0; 6];
+    if bytes.len() != mac.len() {
+        return None;
+    }
+    mac.copy_from_slice(&bytes);
+    Some(mac)
+}
+
+impl IPv4Header {
+    /// Reads the fixed part of the header at the start of `buf` in place, None if it's too short
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..IPV4_HEADER_LEN)?;
+        let word = |at: usize| u16::from_be_bytes([buf[at], buf[at + 1]]);
+        let long = |at: usize| u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        Some(IPv4Header {
+            version_and_header_len: buf[0],
+            type_of_service: buf[1],
+            datagram_length: word(2),
+            ip_identifier: word(4),
+            flags_and_5frag_offset: buf[6],
+            rest_of_frag_offset: buf[7],
+            ttl: buf[8],
+            protocol: buf[9],
+            checksum: word(10),
+            source_ip: long(12),
+            destination_ip: long(16),
+        })
+    }
+}
+
+pub const ICMP_ECHO_HEADER_LEN: usize = 8;
+pub const IPV4_HEADER_LEN: usize = 20; // Without any options
+pub const IPV4_MAX_OPTIONS_LEN: usize = 40;
+pub const IPV6_HEADER_LEN: usize = 40;
+pub const ICMP_ERROR_HEADER_LEN: usize = 8; // type, code, checksum, and 4 unused bytes
+pub const IPV4_PROTOCOL_ICMP: u8 = 1;
+pub const IPV6_NEXT_HEADER_ICMPV6: u8 = 58;
+
+// IPv6 extension headers, which can come between the fixed header and what it carries
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_AUTHENTICATION: u8 = 51;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+const IPV6_MOBILITY: u8 = 135;
+
+pub const ECHO_REQUEST_V4: u8 = 8;
+pub const ECHO_REQUEST_V6: u8 = 128;
+pub const ECHO_REPLY_V4: u8 = 0;
+pub const ECHO_REPLY_V6: u8 = 129;
+pub const TIMEOUT_V4: u8 = 11;
+pub const TIMEOUT_V6: u8 = 3;
+pub const UNREACHABLE_V4: u8 = 3;
+pub const UNREACHABLE_V6: u8 = 1;
+pub const REDIRECT_V4: u8 = 5;
+pub const REDIRECT_V6: u8 = 137;
+pub const PARAMETER_PROBLEM_V4: u8 = 12;
+pub const PARAMETER_PROBLEM_V6: u8 = 4;
+
+// Parsing what comes off the wire. Everything here takes bytes and hands back what's in
+// them, nothing's trusted and nothing panics, however short or mangled the buffer is
+
+/// Why a packet couldn't be parsed
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    Truncated, // Shorter than its headers say it should be
+    Malformed, // Header fields that don't add up
+}
+
+/// An IPv4 header, with how much of the buffer the packet takes up
+pub struct Ipv4Packet {
+    pub header: IPv4Header,
+    pub header_len: usize, // Options included, where the payload starts
+    pub total_len: usize,  // The datagram length, or what there is of it in the buffer
+    pub route: Option<Vec<Ipv4Addr>>, // From the Record Route option, if there is one
+}
+
+/// The IPv4 header at the start of `buf`. Errors if it's too mangled to tell where the
+/// payload starts, a datagram length past the end of the buffer is cut down to it
+pub fn parse_ipv4_header(buf: &[u8]) -> Result<Ipv4Packet, ParseError> {
+    let header = IPv4Header::parse(buf).ok_or(ParseError::Truncated)?;
+
+    // The header length is in 32 bit words
+    let header_len = 4 * (header.version_and_header_len & 0x0F) as usize;
+    if header_len < IPV4_HEADER_LEN || (header.datagram_length as usize) < header_len {
+        return Err(ParseError::Malformed);
+    }
+    let options = buf.get(IPV4_HEADER_LEN..header_len).ok_or(ParseError::Truncated)?;
+    let route = parse_ipv4_options(options).into_iter().find_map(|option| match option {
+        IPv4Option::RecordRoute(route) => Some(route),
+        _ => None,
+    });
+
+    Ok(Ipv4Packet { total_len: std::cmp::min(header.datagram_length as usize, buf.len()), header, header_len, route })
+}
+
+/// What an ICMP message is, for the ones we understand
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IcmpKind {
+    EchoRequest,
+    EchoReply,
+    TimeExceeded,
+    Unreachable(u8),           // The code, which says why
+    Redirect(u8, IpAddr),      // The code, and the gateway (IPv4) or target (IPv6) to use instead
+    ParameterProblem(u8, u32), // The code, and the offset of the offending octet
+    Other,
+}
+
+/// An ICMP (or ICMPv6) message, `data` is everything after the first 8 bytes: the echoed
+/// payload for echoes, and for errors the start of the packet that caused them
+pub struct IcmpMessage<'a> {
+    pub header: ICMPEchoPacket, // The identifier and sequence only mean anything for echoes
+    pub kind: IcmpKind,
+    pub data: &'a [u8],
+}
+
+/// The ICMP message at the start of `buf`, with no IP header in front of it
+pub fn parse_icmp(buf: &[u8], ipv6: bool) -> Result<IcmpMessage<'_>, ParseError> {
+    let header = ICMPEchoPacket::parse(buf).ok_or(ParseError::Truncated)?;
+    let data = &buf[ICMP_ERROR_HEADER_LEN..];
+
+    // The 4 bytes after the checksum (identifier and sequence for echoes) hold
+    // extra information for some of the error messages
+    let rest_of_header = (header.identifier as u32) << 16 | header.sequence_num as u32;
+    let code = header.message_code;
+
+    let kind = match (ipv6, header.message_type) {
+        (false, ECHO_REQUEST_V4) | (true, ECHO_REQUEST_V6) => IcmpKind::EchoRequest,
+        (false, ECHO_REPLY_V4) | (true, ECHO_REPLY_V6) => IcmpKind::EchoReply,
+        (false, TIMEOUT_V4) | (true, TIMEOUT_V6) => IcmpKind::TimeExceeded,
+        (false, UNREACHABLE_V4) | (true, UNREACHABLE_V6) => IcmpKind::Unreachable(code),
+        (false, REDIRECT_V4) => IcmpKind::Redirect(code, IpAddr::from(Ipv4Addr::from(rest_of_header))),
+        (true, REDIRECT_V6) => {
+            // ICMPv6 redirects carry the better first hop (target) and the destination
+            // it applies to, instead of the rest_of_header
+            redirected_destination(data).ok_or(ParseError::Truncated)?;
+            let mut target = [0; 16];
+            target.copy_from_slice(&data[..16]);
+            IcmpKind::Redirect(code, IpAddr::from(Ipv6Addr::from(target)))
+        }
+        // Only the first octet is the pointer for IPv4, the rest is unused
+        (false, PARAMETER_PROBLEM_V4) => IcmpKind::ParameterProblem(code, rest_of_header >> 24),
+        (true, PARAMETER_PROBLEM_V6) => IcmpKind::ParameterProblem(code, rest_of_header),
+        _ => IcmpKind::Other,
+    };
+
+    Ok(IcmpMessage { header, kind, data })
+}
+
+/// Which destination an ICMPv6 redirect is for, it comes after the target
+pub fn redirected_destination(data: &[u8]) -> Option<IpAddr> {
+    let mut redirected = [0; 16];
+    redirected.copy_from_slice(data.get(16..32)?);
+    Some(IpAddr::from(Ipv6Addr::from(redirected)))
+}
+
+/// Walks the extension headers of the IPv6 packet at the start of `data` to what it's
+/// carrying: the protocol, and where it starts. None if the chain runs past the end of
+/// `data` (quoted packets get cut short), or leads to something we can't look into, like
+/// ESP or a fragment that isn't the first
+pub fn ipv6_upper_layer(data: &[u8]) -> Option<(u8, usize)> {
+    let mut next_header = *data.get(6)?;
+    let mut offset = IPV6_HEADER_LEN;
+    if data.len() < offset {
+        return None;
+    }
+
+    loop {
+        let header = data.get(offset..offset + 8);
+        let len = match next_header {
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS | IPV6_MOBILITY => (header?[1] as usize + 1) * 8,
+            IPV6_AUTHENTICATION => (header?[1] as usize + 2) * 4,
+            IPV6_FRAGMENT => {
+                // Only the first fragment has the start of what's carried
+                let header = header?;
+                if u16::from_be_bytes([header[2], header[3]]) >> 3 != 0 {
+                    return None;
+                }
+                8
+            }
+            _ => return Some((next_header, offset)),
+        };
+        next_header = header?[0];
+        offset += len;
+    }
+}
+
+/// The echo request header an ICMP error quotes back, `data` starting at the quoted IP header
+pub fn quoted_echo(data: &[u8], ipv6: bool) -> Option<ICMPEchoPacket> {
+    let icmp_offset = if ipv6 {
+        match ipv6_upper_layer(data)? {
+            (IPV6_NEXT_HEADER_ICMPV6, offset) => offset,
+            _ => return None,
+        }
+    } else {
+        let ip_packet = IPv4Header::parse(data)?;
+        if ip_packet.protocol != IPV4_PROTOCOL_ICMP { return None };
+        quoted_ipv4_header_len(data)?
+    };
+
+    ICMPEchoPacket::parse(data.get(icmp_offset..)?)
+}
+
+// How long the quoted IPv4 header at the start of `data` says it is, None when that's
+// shorter than a header can be (what it carries would start inside it)
+fn quoted_ipv4_header_len(data: &[u8]) -> Option<usize> {
+    let header_len = 4 * (*data.first()? & 0x0F) as usize;
+    if header_len < IPV4_HEADER_LEN { None } else { Some(header_len) }
+}
+
+/// Where the packet an ICMP error quotes was going, `data` starts at its IP header
+pub fn quoted_destination(data: &[u8], ipv6: bool) -> Option<IpAddr> {
+    if ipv6 {
+        let mut destination = [0; 16];
+        destination.copy_from_slice(data.get(24..IPV6_HEADER_LEN)?);
+        Some(IpAddr::from(Ipv6Addr::from(destination)))
+    } else {
+        IPv4Header::parse(data).map(|ip_packet| IpAddr::from(Ipv4Addr::from(ip_packet.destination_ip)))
+    }
+}
+
+/// The protocol and ports of the TCP or UDP packet an ICMP error quotes. Every error quotes
+/// at least the first 8 bytes after the IP header, the ports are the first 4 of them
+pub fn quoted_ports(data: &[u8], ipv6: bool) -> Option<(u8, u16, u16)> {
+    let (protocol, offset) = if ipv6 {
+        ipv6_upper_layer(data)?
+    } else {
+        (*data.get(9)?, quoted_ipv4_header_len(data)?)
+    };
+    if protocol != libc::IPPROTO_TCP as u8 && protocol != libc::IPPROTO_UDP as u8 {
+        return None;
+    }
+    let ports = data.get(offset..offset + 4)?;
+    Some((protocol, u16::from_be_bytes([ports[0], ports[1]]), u16::from_be_bytes([ports[2], ports[3]])))
+}
+
+const IPOPT_END: u8 = 0;
+const IPOPT_NOP: u8 = 1;
+const IPOPT_RR: u8 = 7;
+
+pub enum IPv4Option {
+    RecordRoute(Vec<Ipv4Addr>),
+    Unknown,
+}
+
+// The ECN codepoints, in the bottom 2 bits of the TOS / traffic class byte (RFC 3168)
+pub const ECN_MASK: u8 = 0b11;
+pub const ECN_NOT_ECT: u8 = 0b00;
+pub const ECN_ECT1: u8 = 0b01;
+pub const ECN_ECT0: u8 = 0b10;
+pub const ECN_CE: u8 = 0b11;
+
+/// ECN codepoint by name, ex: "ect0"
+pub fn parse_ecn(name: &str) -> Option<u8> {
+    match name {
+        "not-ect" => Some(ECN_NOT_ECT),
+        "ect1" => Some(ECN_ECT1),
+        "ect0" => Some(ECN_ECT0),
+        "ce" => Some(ECN_CE),
+        _ => None,
+    }
+}
+
+/// The name of the ECN codepoint in a TOS / traffic class byte
+pub fn ecn_name(tos: u8) -> &'static str {
+    match tos & ECN_MASK {
+        ECN_NOT_ECT => "not-ect",
+        ECN_ECT1 => "ect1",
+        ECN_ECT0 => "ect0",
+        _ => "ce",
+    }
+}
+
+/// An empty Record Route option, using all the space allowed for options.
+/// Routers fill in the slots as the packet travels.
+pub fn record_route_option() -> [u8; IPV4_MAX_OPTIONS_LEN] {
+    let mut option = [0; IPV4_MAX_OPTIONS_LEN];
+    option[0] = IPOPT_RR;
+    option[1] = (IPV4_MAX_OPTIONS_LEN - 1) as u8; // Leave room for a trailing end-of-options
+    option[2] = 4; // Pointer (1-indexed) to the first free slot
+    option
+}
+
+/// Walk the variable length options that follow the fixed part of an IPv4 header.
+/// Malformed options end the walk, anything parsed up to that point is kept.
+pub fn parse_ipv4_options(mut data: &[u8]) -> Vec<IPv4Option> {
+    let mut options = Vec::new();
+
+    while let Some(&kind) = data.first() {
+        match kind {
+            IPOPT_END => break,
+            IPOPT_NOP => { data = &data[1..]; continue }
+            _ => {}
+        }
+
+        let len = match data.get(1) {
+            Some(&len) if len >= 2 && len as usize <= data.len() => len as usize,
+            _ => break,
+        };
+
+        let option = &data[..len];
+        options.push(match kind {
+            IPOPT_RR if len >= 3 => {
+                // The pointer says how much of the option has been filled in so far
+                let filled = std::cmp::min(option[2] as usize, len + 1).saturating_sub(4);
+                let route = option[3..3 + filled].chunks_exact(4)
+                    .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+                    .collect();
+                IPv4Option::RecordRoute(route)
+            }
+
+            _ => IPv4Option::Unknown,
+        });
+
+        data = &data[len..];
+    }
+
+    options
+}
+
+// Legacy ICMP queries (--icmp-type), mostly still answered by old and embedded stacks
+
+pub const ADDRESS_MASK_REQUEST: u8 = 17;
+pub const ADDRESS_MASK_REPLY: u8 = 18;
+pub const ADDRESS_MASK_LEN: usize = 12; // The echo style header, then the mask
+pub const ROUTER_ADVERTISEMENT_V4: u8 = 9;
+pub const ROUTER_SOLICITATION_V4: u8 = 10;
+pub const ROUTER_SOLICITATION_V6: u8 = 133;
+pub const ROUTER_ADVERTISEMENT_V6: u8 = 134;
+pub const ROUTER_SOLICITATION_LEN: usize = 8; // type, code, checksum, 4 reserved bytes
+
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_MTU: u8 = 5;
+
+/// The interesting parts of a router advertisement, from either protocol
+pub struct RouterAdvertisement {
+    pub lifetime: u16, // Seconds the sender should be used as a default router, 0 means it shouldn't
+    pub routers: Vec<(Ipv4Addr, i32)>, // IPv4 only, addresses with their preference
+    pub hop_limit: Option<u8>, // IPv6 only, what hosts should use (0 is unspecified)
+    pub managed: bool, // IPv6 M flag, addresses come from DHCPv6
+    pub other: bool,   // IPv6 O flag, other configuration does
+    pub prefixes: Vec<(Ipv6Addr, u8)>,
+    pub mtu: Option<u32>,
+}
+
+impl RouterAdvertisement {
+    /// Decodes an ICMPv4 (RFC 1256) advertisement, `buf` starting at the ICMP header
+    pub fn parse_v4(buf: &[u8]) -> Option<Self> {
+        let header = buf.get(..8)?;
+        let (count, entry_words) = (header[4] as usize, header[5] as usize);
+        let lifetime = u16::from_be_bytes([header[6], header[7]]);
+
+        // Entries are at least an address and a preference, anything extra is skipped
+        let entry_len = std::cmp::max(entry_words, 2) * 4;
+        let routers = buf[8..].chunks_exact(entry_len).take(count)
+            .map(|e| (Ipv4Addr::new(e[0], e[1], e[2], e[3]), i32::from_be_bytes([e[4], e[5], e[6], e[7]])))
+            .collect();
+
+        Some(RouterAdvertisement {
+            lifetime, routers,
+            hop_limit: None, managed: false, other: false, prefixes: Vec::new(), mtu: None,
+        })
+    }
+
+    /// Decodes an ICMPv6 (RFC 4861) advertisement, `buf` starting at the ICMP header
+    pub fn parse_v6(buf: &[u8]) -> Option<Self> {
+        let header = buf.get(..16)?;
+        let mut advertisement = RouterAdvertisement {
+            lifetime: u16::from_be_bytes([header[6], header[7]]),
+            routers: Vec::new(),
+            hop_limit: Some(header[4]),
+            managed: header[5] & 0x80 != 0,
+            other: header[5] & 0x40 != 0,
+            prefixes: Vec::new(),
+            mtu: None,
+        };
+
+        let mut options = &buf[16..];
+        while options.len() >= 8 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                break;
+            }
+
+            let option = &options[..len];
+            match option[0] {
+                OPTION_PREFIX_INFORMATION if len >= 32 => {
+                    let mut prefix = [0; 16];
+                    prefix.copy_from_slice(&option[16..32]);
+                    advertisement.prefixes.push((Ipv6Addr::from(prefix), option[2]));
+                }
+                OPTION_MTU => advertisement.mtu = Some(u32::from_be_bytes([option[4], option[5], option[6], option[7]])),
+                _ => {}
+            }
+            options = &options[len..];
+        }
+
+        Some(advertisement)
+    }
+
+    /// One line for people, ex: "lifetime 1800s, 192.0.2.1 (pref 0)"
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("lifetime {}s", self.lifetime)];
+        parts.extend(self.routers.iter().map(|(address, preference)| format!("{} (pref {})", address, preference)));
+
+        if let Some(hop_limit) = self.hop_limit.filter(|&hops| hops != 0) {
+            parts.push(format!("hop limit {}", hop_limit));
+        }
+        if self.managed { parts.push("managed".to_string()) }
+        if self.other { parts.push("other config".to_string()) }
+        parts.extend(self.prefixes.iter().map(|(prefix, len)| format!("prefix {}/{}", prefix, len)));
+        if let Some(mtu) = self.mtu {
+            parts.push(format!("mtu {}", mtu));
+        }
+
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP: u8 = libc::IPPROTO_TCP as u8;
+
+    // An IPv4 header `header_len` bytes long (options are NOPs), and `payload` after it
+    fn ipv4(header_len: usize, protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let [lh, ll] = ((header_len.max(IPV4_HEADER_LEN) + payload.len()) as u16).to_be_bytes();
+        let mut packet = vec![0x40 | (header_len / 4) as u8, 0, lh, ll, 0, 0, 0, 0, 64, protocol, 0, 0, 192, 0, 2, 1, 192, 0, 2, 2];
+        packet.resize(header_len.max(IPV4_HEADER_LEN), IPOPT_NOP);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    // An echo request with identifier 0x1234 and `sequence`, as an error would quote it
+    fn echo_request(sequence: u16) -> Vec<u8> {
+        let [sh, sl] = sequence.to_be_bytes();
+        vec![ECHO_REQUEST_V4, 0, 0, 0, 0x12, 0x34, sh, sl]
+    }
+
+    #[test]
+    fn ipv4_headers_take_their_options_into_account() {
+        let packet = parse_ipv4_header(&ipv4(24, IPV4_PROTOCOL_ICMP, &[1, 2, 3])).unwrap();
+        assert_eq!((packet.header_len, packet.total_len), (24, 27));
+        assert_eq!(packet.header.protocol, IPV4_PROTOCOL_ICMP);
+        assert!(packet.route.is_none());
+    }
+
+    #[test]
+    fn ipv4_headers_that_dont_add_up_are_refused() {
+        let packet = ipv4(20, IPV4_PROTOCOL_ICMP, &[0; 8]);
+        // Shorter than the fixed header, or than the options it says there are
+        for length in 0..IPV4_HEADER_LEN {
+            assert_eq!(parse_ipv4_header(&packet[..length]).err(), Some(ParseError::Truncated));
+        }
+        assert_eq!(parse_ipv4_header(&ipv4(24, IPV4_PROTOCOL_ICMP, &[])[..22]).err(), Some(ParseError::Truncated));
+
+        let mut bad_ihl = packet.clone();
+        bad_ihl[0] = 0x44;
+        assert_eq!(parse_ipv4_header(&bad_ihl).err(), Some(ParseError::Malformed));
+        let mut short_datagram = packet;
+        short_datagram[2..4].copy_from_slice(&19u16.to_be_bytes());
+        assert_eq!(parse_ipv4_header(&short_datagram).err(), Some(ParseError::Malformed));
+    }
+
+    #[test]
+    fn icmp_messages_say_what_they_are() {
+        let message = parse_icmp(&[TIMEOUT_V4, 0, 0, 0, 0, 0, 0, 0, 0x45], false).unwrap();
+        assert_eq!((message.kind, message.data), (IcmpKind::TimeExceeded, &[0x45][..]));
+        assert_eq!(parse_icmp(&[REDIRECT_V4, 1, 0, 0, 192, 0, 2, 9], false).unwrap().kind,
+            IcmpKind::Redirect(1, IpAddr::from([192, 0, 2, 9])));
+        assert_eq!(parse_icmp(&[PARAMETER_PROBLEM_V4, 0, 0, 0, 12, 0, 0, 0], false).unwrap().kind, IcmpKind::ParameterProblem(0, 12));
+        assert_eq!(parse_icmp(&[ECHO_REPLY_V6, 0, 0, 0, 0, 1, 0, 2], true).unwrap().header.sequence_num, 2);
+    }
+
+    #[test]
+    fn truncated_icmp_messages_are_refused() {
+        let message = [ECHO_REPLY_V4, 0, 0, 0, 0x12, 0x34, 0, 1];
+        for length in 0..ICMP_ECHO_HEADER_LEN {
+            assert_eq!(parse_icmp(&message[..length], false).err(), Some(ParseError::Truncated));
+        }
+        // A redirect for IPv6 needs its target and destination
+        assert_eq!(parse_icmp(&[REDIRECT_V6, 0, 0, 0, 0, 0, 0, 0, 0xfe, 0x80], true).err(), Some(ParseError::Truncated));
+    }
+
+    #[test]
+    fn quoted_echoes_are_found_past_the_quoted_header() {
+        let quoted = ipv4(24, IPV4_PROTOCOL_ICMP, &echo_request(7));
+        let echo = quoted_echo(&quoted, false).unwrap();
+        assert_eq!((echo.identifier, echo.sequence_num), (0x1234, 7));
+        assert!(quoted_echo(&quoted[..quoted.len() - 1], false).is_none());
+        assert!(quoted_echo(&ipv4(20, TCP, &echo_request(7)), false).is_none());
+    }
+
+    #[test]
+    fn quoted_headers_too_short_to_be_one_are_refused() {
+        for ihl in 0..5 {
+            let mut quoted = ipv4(20, IPV4_PROTOCOL_ICMP, &echo_request(7));
+            quoted[0] = 0x40 | ihl;
+            assert!(quoted_echo(&quoted, false).is_none(), "ihl {}", ihl);
+            quoted[9] = TCP;
+            assert!(quoted_ports(&quoted, false).is_none(), "ihl {}", ihl);
+        }
+    }
+
+    #[test]
+    fn quoted_ports_come_from_tcp_and_udp_only() {
+        let quoted = ipv4(20, TCP, &[0xC8, 0x00, 0x01, 0xBB, 0, 0, 0, 0]);
+        assert_eq!(quoted_ports(&quoted, false), Some((TCP, 51200, 443)));
+        assert!(quoted_ports(&quoted[..23], false).is_none());
+        assert!(quoted_ports(&ipv4(20, IPV4_PROTOCOL_ICMP, &echo_request(7)), false).is_none());
+        assert!(quoted_ports(&[], false).is_none());
+    }
+}
@@ -1,25 +1,40 @@
 use std::io::{Result, Error, ErrorKind};
-use std::net::{IpAddr, SocketAddr};
-use std::time::{Instant, Duration};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::{Instant, Duration, SystemTime};
 use std::ops::Add;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::os::unix::io::AsRawFd;
 
 use rand::random;
 
 use socket2::{Socket, Domain, Protocol, SockAddr};
-use dns_lookup::{lookup_addr};
 
-use crate::{packet, util};
+use crate::{dump, packet, util};
+use crate::packet::{IcmpKind, ECHO_REQUEST_V4, ECHO_REQUEST_V6, TIMEOUT_V4, TIMEOUT_V6,
+    UNREACHABLE_V4, UNREACHABLE_V6, PARAMETER_PROBLEM_V4, PARAMETER_PROBLEM_V6};
+use crate::event::{Corruption, Transport};
+use crate::trigger::Waker;
 
 struct GenericIPHeader {
     datagram_length: u16,
     data_offset: u8,
     ttl: Option<u8>,
+    tos: Option<u8>, // TOS (traffic class for IPv6), when there's a header or ancillary data for it
+    route: Option<Vec<Ipv4Addr>>, // From the Record Route option, if present
 }
 
 #[derive(PartialEq)]
 pub enum ReplyType {
     Reply,
     TimeLimitExceeded,
+    DestinationUnreachable(u8), // Holds the ICMP code, which says *why* it was unreachable
+    Redirect(u8, IpAddr),       // ICMP code, and the gateway (or target for ipv6) we should use instead
+    ParameterProblem(u8, u32),  // ICMP code, and the offset of the offending octet
+    Reset,                      // Connection refused by a TCP probe, the host is there but the port isn't open
 }
 
 pub struct PongResult {
@@ -28,51 +43,254 @@ pub struct PongResult {
 
     pub sequence: u16,
     pub ttl: Option<u8>,
+    pub route: Option<Vec<Ipv4Addr>>,
     pub size: u16,
     pub rtt: Duration,
     pub mtype: ReplyType,
+
+    // Breakdown of the rtt for backends that go through several steps (dns, connect, ...)
+    pub phases: Vec<(&'static str, Duration)>,
+    pub detail: Option<String>,
+    pub transport: Option<Transport>, // What came back, for backends that aren't ICMP echo
+    pub corruption: Option<Corruption>, // The echoed payload wasn't what was sent
+    pub tos: Option<u8>, // The reply's TOS / traffic class byte, after set_ecn
+    pub duplicate: bool, // Another answer to a probe that already had one
+    pub late: bool,      // An answer to a probe that was given up on (see Pinger::forget)
+    pub hardware: Option<HardwareStamps>, // With set_hardware_timestamps, when the NIC timed it
+}
+
+/// When the network card sent a probe and took in its answer, by its own clock. That
+/// isn't the system's, only the difference between the two means anything
+#[derive(Clone, Copy, Debug)]
+pub struct HardwareStamps {
+    pub sent: Duration,
+    pub received: Duration,
 }
 
 pub struct Pinger {
     address: IpAddr,
-    socket: Socket,
+    icmp: Arc<IcmpSocket>, // Ours alone, or one every target shares (see IcmpSocket::shared)
+    owns_socket: bool,     // The first pinger on it, which counts what can only be counted per socket
     sock_addr: SockAddr,
-    coder: bincode::Config,
+
+    // Reused for every packet so the hot path doesn't touch the allocator
+    send_buf: Vec<u8>,
 
     session: u16,  // Used as 'identifier' word to match echo requests/replies
     sequence: u16, // Used as 'sequence number' word to match echo requests/replies
+    payload_size: usize, // Data bytes sent after the echo header
+    random_payload: bool, // Fresh random data in every probe, instead of the same pattern
+    flow_sum: Option<u16>, // With set_flow_sum, what every probe's ICMP message adds up to
+    probe_id: Option<u64>, // Goes at the start of the payload of the next probe, if it fits
+    source: Option<IpAddr>, // With set_source, the local address probes go out from
+
+    // Everything received is handled on the socket's own thread, which drains it the whole
+    // time so nothing sits in the kernel's queue while we're busy (or asleep). It starts
+    // with the first ping, so it picks up the cpu pinning and priority of the session thread
+    shared: Arc<Shared>,
+    pongs: Option<mpsc::Receiver<Result<PongResult>>>,
+}
+
+// What the receiver thread and the pinger both get at
+struct Shared {
+    address: IpAddr,
+    session: u16,
+    datagram: bool, // An unprivileged ICMP socket rather than a raw one, see Pinger::new
+    crowded: bool,  // Other pingers are on the same socket, so answers have to be from our address
+    latest: AtomicU16, // Sequence of the latest echo request out
+    epoch: Instant,    // What the send times stamped in the payloads count from
+
+    in_flight: Mutex<HashMap<u16, Outstanding>>, // Probes not answered (or given up on) yet
+    answered: Mutex<HashMap<u16, Outstanding>>,  // And the ones that were, for spotting duplicates. At most one per sequence number
+    given_up: Mutex<HashMap<u16, Outstanding>>,  // Forgotten before they were, in case the answer's only late
+    payload: Mutex<(usize, bool)>, // Data bytes in each probe, and whether the last two are for set_flow_sum
+    stopped: AtomicBool,                     // The pinger is gone, the receiver should follow
+
+    processing: Mutex<ProcessingStats>, // Time spent in userspace handling received packets
+    checksum_failures: AtomicU32,       // Packets dropped because they were corrupted on the way
+    syscalls: AtomicU64,                // Socket calls made, to keep an eye on our own overhead
+}
+
+// What's kept of a probe until it's answered, to check the reply against
+#[derive(Clone, Copy)]
+struct Outstanding {
+    sent_at: Instant,
+    id: Option<u64>,   // From set_probe_id, when it fit
+    seed: Option<u64>, // What its random data was made from, with set_random_payload
+    hardware_sent: Option<Duration>, // When the NIC says it went out, with set_hardware_timestamps
+}
+
+/// How long the receive path spends handling packets once they are out of the socket
+#[derive(Clone, Copy, Default)]
+pub struct ProcessingStats {
+    pub packets: u32,
+    pub total: Duration,
+    pub max: Duration,
 }
 
-const ECHO_REQUEST_V4: u8 = 8;
-const ECHO_REQUEST_V6: u8 = 128;
-const ECHO_REPLY_V4: u8 = 0;
-const ECHO_REPLY_V6: u8 = 129;
-const TIMEOUT_V4: u8 = 11;
-const TIMEOUT_V6: u8 = 3;
+impl ProcessingStats {
+    fn record(&mut self, time: Duration) {
+        self.packets += 1;
+        self.total += time;
+        self.max = std::cmp::max(self.max, time);
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.packets == 0 { Duration::default() } else { self.total / self.packets }
+    }
+}
+
+const RECV_BUF_LEN: usize = 4096;
+
+/// Human readable explanation for a Redirect code
+pub fn redirect_reason(ipv6: bool, code: u8) -> &'static str {
+    if ipv6 {
+        return "Redirect"; // ICMPv6 has no redirect codes
+    }
+
+    match code {
+        0 => "Redirect Network",
+        1 => "Redirect Host",
+        2 => "Redirect Type of Service and Network",
+        3 => "Redirect Type of Service and Host",
+        _ => "Redirect",
+    }
+}
+
+/// Human readable explanation for a Parameter Problem code
+pub fn parameter_problem_reason(ipv6: bool, code: u8) -> &'static str {
+    if ipv6 {
+        match code {
+            0 => "Erroneous header field",
+            1 => "Unrecognized Next Header type",
+            2 => "Unrecognized IPv6 option",
+            _ => "Parameter problem",
+        }
+    } else {
+        match code {
+            0 => "Parameter problem",
+            1 => "Missing a required option",
+            2 => "Bad length",
+            _ => "Parameter problem",
+        }
+    }
+}
+
+/// Human readable explanation for a Destination Unreachable code
+pub fn unreachable_reason(ipv6: bool, code: u8) -> &'static str {
+    if ipv6 {
+        match code {
+            0 => "No Route to Destination",
+            1 => "Administratively Prohibited",
+            2 => "Beyond Scope of Source Address",
+            3 => "Address Unreachable",
+            4 => "Port Unreachable",
+            5 => "Source Address Failed Policy",
+            6 => "Reject Route to Destination",
+            _ => "Destination Unreachable",
+        }
+    } else {
+        match code {
+            0 => "Destination Net Unreachable",
+            1 => "Destination Host Unreachable",
+            2 => "Destination Protocol Unreachable",
+            3 => "Destination Port Unreachable",
+            4 => "Fragmentation Needed and DF Set",
+            5 => "Source Route Failed",
+            6 => "Destination Net Unknown",
+            7 => "Destination Host Unknown",
+            9 | 10 => "Destination Administratively Prohibited",
+            11 => "Destination Net Unreachable for Type of Service",
+            12 => "Destination Host Unreachable for Type of Service",
+            13 => "Communication Administratively Prohibited",
+            14 => "Host Precedence Violation",
+            15 => "Precedence Cutoff in Effect",
+            _ => "Destination Unreachable",
+        }
+    }
+}
 
 impl Pinger {
+    /// Pings with an ICMP datagram socket, which doesn't need root or CAP_NET_RAW if
+    /// net.ipv4.ping_group_range includes one of our groups, and a raw socket otherwise
     pub fn new(address: IpAddr) -> Result<Self> {
-        // First obtain the raw socket
-        let domain = if address.is_ipv6() { Domain::ipv6() } else { Domain::ipv4() };
-        let protocol = if address.is_ipv6() { Protocol::icmpv6() } else { Protocol::icmpv4() };
-        let stype = socket2::Type::raw().cloexec();
-        let socket = Socket::new(domain, stype, Some(protocol))?;
+        Pinger::with_type(address, true).or_else(|_| Pinger::new_raw(address))
+    }
 
-        let sock_address = SocketAddr::from((address, 0));
+    /// Always a raw socket, for what datagram sockets can't do (like seeing IP options)
+    pub fn new_raw(address: IpAddr) -> Result<Self> {
+        Pinger::with_type(address, false)
+    }
+
+    fn with_type(address: IpAddr, datagram: bool) -> Result<Self> {
+        Pinger::on(address, Arc::new(IcmpSocket::open(address.is_ipv6(), datagram)?), false)
+    }
+
+    /// Ping `address` over a socket other pingers use too, instead of one of its own
+    pub fn on_shared(address: IpAddr, icmp: &Arc<IcmpSocket>) -> Result<Self> {
+        if address.is_ipv6() != icmp.ipv6 {
+            return Err(Error::new(ErrorKind::InvalidInput, "the shared socket is for the other address family"));
+        }
+        Pinger::on(address, icmp.clone(), true)
+    }
+
+    fn on(address: IpAddr, icmp: Arc<IcmpSocket>, crowded: bool) -> Result<Self> {
+        // The kernel picks the identifier for datagram sockets (and rewrites it in everything
+        // we send), so on a shared one every pinger has the same and only the addresses differ
+        let session = icmp.identifier.unwrap_or_else(random::<u16>);
+        let datagram = icmp.identifier.is_some();
+
+        let shared = Arc::new(Shared {
+            address, session, datagram, crowded,
+            latest: AtomicU16::new(0),
+            epoch: Instant::now(),
+            in_flight: Mutex::new(HashMap::with_capacity(64)),
+            answered: Mutex::new(HashMap::with_capacity(64)),
+            given_up: Mutex::new(HashMap::with_capacity(64)),
+            payload: Mutex::new((0, false)),
+            stopped: AtomicBool::new(false),
+            processing: Mutex::new(ProcessingStats::default()),
+            checksum_failures: AtomicU32::new(0),
+            syscalls: AtomicU64::new(0),
+        });
 
-        let mut coder = bincode::config();
-        coder.big_endian(); // ICMP Packet Header uses big endian
-        
         Ok(Pinger {
             address,
-            socket, coder,
-            sock_addr: SockAddr::from(sock_address),
-            session: random::<u16>(), sequence: 0 
+            owns_socket: icmp.pingers.fetch_add(1, Ordering::SeqCst) == 0,
+            icmp,
+            send_buf: vec![0; packet::ICMP_ECHO_HEADER_LEN],
+            sock_addr: SockAddr::from(SocketAddr::from((address, 0))),
+            session, sequence: 0,
+            payload_size: 0, random_payload: false, flow_sum: None, probe_id: None, source: None,
+            shared, pongs: None,
         })
     }
 
+    // Hands back another way into the channel answers come in on, for `waker`
+    fn start_receiver(&mut self) -> Result<mpsc::Sender<Result<PongResult>>> {
+        let (sender, pongs) = mpsc::channel();
+        self.icmp.join(self.shared.clone(), sender.clone())?;
+        self.pongs = Some(pongs);
+        Ok(sender)
+    }
+
+    /// Something to call to cut a `receive_any` short, it returns as if it had timed out.
+    /// Has to be taken before the first ping, and then Ctrl+C doesn't end the wait by
+    /// itself anymore, whatever holds on to it has to wake it for that too
+    pub fn waker(&mut self) -> Result<Waker> {
+        if self.pongs.is_some() {
+            return Err(Error::other("already receiving"));
+        }
+        let sender = self.start_receiver()?;
+        Ok(Box::new(move || { let _ = sender.send(Err(Error::new(ErrorKind::WouldBlock, "woken up"))); }))
+    }
+
     // Sends out a ping, returns the icmp_seq (sequence num) used
     pub fn ping(&mut self) -> Result<u16> {
+        if self.pongs.is_none() {
+            self.start_receiver()?;
+        }
+
         self.sequence = self.sequence.wrapping_add(1); // Each new ping updates the sequence
         let pack = packet::ICMPEchoPacket {
             message_type: if self.address.is_ipv6() { ECHO_REQUEST_V6 } else { ECHO_REQUEST_V4 },
@@ -82,97 +300,1696 @@ impl Pinger {
             sequence_num: self.sequence,
         };
 
-        let mut payload = self.coder.serialize(&pack).unwrap();
-        let payload = payload.as_mut_slice(); // Socket Interface expects a slice, not a vec
-        util::set_checksum(payload, 1);
+        // The payload after the header was filled in by set_payload_size, and only changes
+        // where the probe's id goes, so a capture can be matched up with the output. Unless
+        // it's random, then all of it does
+        pack.write(&mut self.send_buf);
+        let room = self.payload_size.saturating_sub(if self.flow_sum.is_some() { 2 } else { 0 });
+        let id = self.probe_id.take().filter(|_| room >= 8);
+        let seed = if self.random_payload { Some(random::<u64>()) } else { None };
+        let at = packet::ICMP_ECHO_HEADER_LEN;
+        if seed.is_some() {
+            for (i, byte) in self.send_buf[at..at + room].iter_mut().enumerate() {
+                *byte = payload_byte(seed, i);
+            }
+        }
+        if room >= 8 {
+            // Without an id it's back to the pattern, the last probe's could still be there
+            let bytes = id.map_or_else(|| [0, 1, 2, 3, 4, 5, 6, 7].map(|i| payload_byte(seed, i)), u64::to_be_bytes);
+            self.send_buf[at..at + 8].copy_from_slice(&bytes);
+        }
+        // Then when it went out (like iputils does), the reply brings it back to time it by
+        let sent_at = Instant::now();
+        if room >= 16 {
+            let at = packet::ICMP_ECHO_HEADER_LEN + 8;
+            let stamp = sent_at.duration_since(self.shared.epoch).as_nanos() as u64;
+            self.send_buf[at..at + 8].copy_from_slice(&stamp.to_be_bytes());
+        }
+        if let Some(sum) = self.flow_sum {
+            // The last two payload bytes make up for the sequence number changing
+            let at = self.send_buf.len() - 2;
+            self.send_buf[at..at + 2].copy_from_slice(&[0, 0]);
+            let mut compensation = sum as u32 + util::get_checksum(&self.send_buf, 1) as u32;
+            compensation = (compensation >> 16) + (compensation & 0xFFFF);
+            self.send_buf[at..at + 2].copy_from_slice(&(compensation as u16).to_be_bytes());
+        }
+        util::set_checksum(&mut self.send_buf, 1);
 
-        self.socket.send_to(payload, &self.sock_addr).and(Ok(self.sequence))
+        // Noted down before sending, the receiver could see the reply before send_to even returns
+        self.shared.latest.store(self.sequence, Ordering::SeqCst);
+        self.shared.in_flight.lock().unwrap().insert(self.sequence, Outstanding { sent_at, id, seed, hardware_sent: None });
+        // From the last time round, after wrapping
+        self.shared.answered.lock().unwrap().remove(&self.sequence);
+        self.shared.given_up.lock().unwrap().remove(&self.sequence);
+        if dump::enabled() {
+            dump::packet("sent", self.address, &self.send_buf, false, self.address.is_ipv6());
+        }
+        self.shared.count_syscalls(1);
+        let mut sent = self.icmp.transport.send(&self.send_buf, &self.sock_addr, self.source);
+        if self.shared.crowded && sent.as_ref().is_err_and(|e| self.shared.datagram && reported_icmp_error(e)) {
+            // It went to us instead of who it's about, and didn't send. Only once, so try again
+            self.shared.count_syscalls(1);
+            sent = self.icmp.transport.send(&self.send_buf, &self.sock_addr, self.source);
+        }
+        if let Err(e) = sent {
+            self.forget(self.sequence);
+            return Err(e);
+        }
+        self.icmp.sent(self.address, self.sequence);
+        Ok(self.sequence)
     }
 
+    /// Wait for the answer to probe `sequence_num` only, anything for the other probes
+    /// still out (and duplicates or late answers) is skipped
     pub fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
-        let begin_time = Instant::now();
-        let end_time = begin_time.add(timeout);
-
+        let end_time = Instant::now().add(timeout);
         loop {
-            let relative_timeout = end_time.duration_since(Instant::now());
+            match self.receive_any(end_time.saturating_duration_since(Instant::now())) {
+                Ok(pong) if pong.sequence != sequence_num || pong.duplicate || pong.late => continue,
+                Err(e) => {
+                    self.forget(sequence_num); // Nobody's going to wait on it again
+                    return Err(e);
+                }
+                pong => return pong,
+            }
+        }
+    }
 
-            let mut buf = [0; 4096]; // We want the buffer to be fresh every time
-            self.socket.set_read_timeout(Some(relative_timeout))?;
-            let (_bytes, from) = self.socket.recv_from(&mut buf[..])?;
+    /// Wait for the answer to whichever probe still out comes back first, so one slow
+    /// reply doesn't hold up the rest. A probe stays outstanding until it's answered
+    /// or given up on with `forget`. Echo replies to probes that were already answered
+    /// come through too, marked as duplicates (like ping's DUP!), and so do replies to
+    /// probes given up on, marked late
+    pub fn receive_any(&self, timeout: Duration) -> Result<PongResult> {
+        let pongs = match &self.pongs {
+            Some(pongs) => pongs,
+            None => { // Nothing's been sent, so nothing's coming
+                util::sleep(timeout);
+                return Err(Error::new(ErrorKind::WouldBlock, "timed out"));
+            }
+        };
 
-            let header = if self.address.is_ipv6() {
-                // The socket doesn't put the header into our buffer
-                // so unfortunately we cannot extract the ttl (or hop_limit as it's called in ipv6)
+        let mut pong = match pongs.recv_timeout(timeout) {
+            Ok(pong) => pong?,
+            Err(RecvTimeoutError::Timeout) => return Err(Error::new(ErrorKind::WouldBlock, "timed out")),
+            // The receiver only stops for Ctrl+C
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::Interrupted, "interrupted")),
+        };
 
-                GenericIPHeader {
-                    datagram_length: 8,
-                    data_offset: 0,
-                    ttl: None
-                }
+        pong.hostname = util::hostname(&pong.address); // Slow, so kept off the receiver thread
+        Ok(pong)
+    }
+
+    /// Stop waiting on a probe. A reply to it turning up after all comes back marked late
+    pub fn forget(&self, sequence_num: u16) {
+        if let Some(probe) = self.shared.in_flight.lock().unwrap().remove(&sequence_num) {
+            self.shared.given_up.lock().unwrap().insert(sequence_num, probe);
+        }
+    }
+
+    /// The identifier word in our echoes
+    pub fn identifier(&self) -> u16 {
+        self.session
+    }
+
+    /// Sends, plus the socket's reads for the pinger that owns it
+    pub fn syscalls(&self) -> u64 {
+        let reads = if self.owns_socket { self.icmp.syscalls.load(Ordering::Relaxed) } else { 0 };
+        self.shared.syscalls.load(Ordering::Relaxed) + reads
+    }
+
+    pub fn checksum_failures(&self) -> u32 {
+        self.shared.checksum_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn processing_stats(&self) -> ProcessingStats {
+        *self.shared.processing.lock().unwrap()
+    }
+
+    /// How many data bytes to put after the echo header
+    pub fn set_payload_size(&mut self, size: usize) {
+        self.payload_size = size;
+        self.send_buf.resize(packet::ICMP_ECHO_HEADER_LEN + size, 0);
+        for (i, byte) in self.send_buf[packet::ICMP_ECHO_HEADER_LEN..].iter_mut().enumerate() {
+            *byte = i as u8; // Same incrementing pattern every time
+        }
+        *self.shared.payload.lock().unwrap() = (size, self.flow_sum.is_some());
+    }
+
+    /// Send fresh random data in every probe instead of the same pattern, compression and
+    /// dedup on the way can't do anything with it. It's still checked when it comes back
+    pub fn set_random_payload(&mut self) {
+        self.random_payload = true;
+    }
+
+    /// Put `id` in the payload of the next probe, when it has room for it (8 bytes)
+    pub fn set_probe_id(&mut self, id: u64) {
+        self.probe_id = Some(id);
+    }
+
+    /// Send the next probes from this local address, instead of whichever the route picks
+    pub fn set_source(&mut self, source: IpAddr) {
+        self.source = Some(source);
+    }
+
+    /// Keep the ICMP checksum the same for every probe, by having the payload make up for
+    /// the sequence number. Routers balancing over several paths hash the first bytes after
+    /// the IP header as if they were ports, and for ICMP that's the type, code and checksum,
+    /// so this keeps every probe on one path. Different sums for different pingers (and
+    /// their different identifiers) put them on different ones, or at least give them a chance
+    pub fn set_flow_sum(&mut self, sum: u16) {
+        if self.payload_size < 2 {
+            self.set_payload_size(2);
+        }
+        self.flow_sum = Some(sum);
+        self.shared.payload.lock().unwrap().1 = true;
+    }
+
+    /// Send with this flow label (ipv6 only, 20 bits), which routers hash for the same reason.
+    /// The kernel hands labels out, so it's leased for this socket first
+    pub fn set_flow_label(&mut self, label: u32) -> Result<()> {
+        let ip = match self.address {
+            IpAddr::V6(ip) => ip,
+            IpAddr::V4(_) => return Err(Error::new(ErrorKind::InvalidInput, "flow labels are only for IPv6")),
+        };
+
+        // struct in6_flowlabel_req, libc doesn't have it
+        #[repr(C)]
+        struct FlowLabelRequest { dst: libc::in6_addr, label: u32, action: u8, share: u8, flags: u16, expires: u16, linger: u16, pad: u32 }
+        let request = FlowLabelRequest {
+            dst: libc::in6_addr { s6_addr: ip.octets() },
+            label: (label & 0xFFFFF).to_be(),
+            action: 0, // IPV6_FL_A_GET
+            share: 1,  // IPV6_FL_S_EXCL, just this socket
+            flags: 1,  // IPV6_FL_F_CREATE
+            expires: 0, linger: 0, pad: 0,
+        };
+
+        let fd = self.icmp.transport.socket()?.as_raw_fd();
+        let ret = unsafe {
+            libc::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_FLOWLABEL_MGR,
+                &request as *const FlowLabelRequest as *const libc::c_void, std::mem::size_of::<FlowLabelRequest>() as libc::socklen_t)
+        };
+        if ret != 0 {
+            return Err(Error::last_os_error());
+        }
+        set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_FLOWINFO_SEND, 1)?;
+
+        // The flow info goes in the destination address, in network order
+        self.sock_addr = SockAddr::from(SocketAddrV6::new(ip, 0, (label & 0xFFFFF).to_be(), 0));
+        Ok(())
+    }
+
+    /// Which interface an IPv6 link-local destination is on, it can't be reached without
+    pub fn set_scope_id(&mut self, scope_id: u32) {
+        if let Some(address) = self.sock_addr.as_inet6() {
+            self.sock_addr = SockAddr::from(SocketAddrV6::new(*address.ip(), 0, address.flowinfo(), scope_id));
+        }
+    }
+
+    /// Size of the ICMP message we send, the replies should be exactly the same
+    pub fn request_size(&self) -> usize {
+        packet::ICMP_ECHO_HEADER_LEN + self.payload_size
+    }
+
+    /// Grow the kernel's receive queue for this socket, returns the size the kernel actually
+    /// settled on (it doubles the request for bookkeeping, and caps it at net.core.rmem_max)
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> Result<usize> {
+        let socket = self.icmp.transport.socket()?;
+        socket.set_recv_buffer_size(size)?;
+        let actual = socket.recv_buffer_size()?;
+        log::info!("set SO_RCVBUF to {} on socket {}, the kernel made it {}", size, socket.as_raw_fd(), actual);
+        Ok(actual)
+    }
+
+    /// Packets the kernel had to drop because our receive queue was full. These never
+    /// made it to us, so they look exactly like network loss unless reported separately.
+    /// A shared socket's go to the pinger that owns it, they could be anyone's
+    pub fn kernel_drops(&self) -> Result<u32> {
+        if !self.owns_socket {
+            return Ok(0);
+        }
+        let mut meminfo = [0u32; libc::SK_MEMINFO_DROPS as usize + 1];
+        let mut len = std::mem::size_of_val(&meminfo) as libc::socklen_t;
+
+        let ret = unsafe {
+            libc::getsockopt(self.icmp.transport.socket()?.as_raw_fd(), libc::SOL_SOCKET, libc::SO_MEMINFO,
+                meminfo.as_mut_ptr() as *mut libc::c_void, &mut len)
+        };
+
+        if ret != 0 { Err(Error::last_os_error()) } else { Ok(meminfo[libc::SK_MEMINFO_DROPS as usize]) }
+    }
+
+    /// Ask routers to record their address in outgoing packets (IPv4 only)
+    pub fn set_record_route(&mut self) -> Result<()> {
+        if self.address.is_ipv6() {
+            return Err(Error::new(ErrorKind::InvalidInput, "record route is only available for IPv4"));
+        }
+        if self.shared.datagram {
+            return Err(Error::new(ErrorKind::InvalidInput, "record route needs a raw socket, the route comes back in the IP header"));
+        }
+
+        let option = packet::record_route_option();
+        let ret = unsafe {
+            libc::setsockopt(self.icmp.transport.socket()?.as_raw_fd(), libc::IPPROTO_IP, libc::IP_OPTIONS,
+                option.as_ptr() as *const libc::c_void, option.len() as libc::socklen_t)
+        };
+
+        if ret != 0 { Err(Error::last_os_error()) } else { Ok(()) }
+    }
+
+    /// Mark outgoing packets with a DSCP class (the top 6 bits of the TOS / traffic class byte)
+    pub fn set_dscp(&mut self, dscp: u8) -> Result<()> {
+        self.set_tos((dscp as libc::c_int) << 2)
+    }
+
+    /// Send with this ECN codepoint (the bottom 2 bits of the TOS / traffic class byte), and
+    /// read the byte off the replies to see what came back
+    pub fn set_ecn(&mut self, codepoint: u8) -> Result<()> {
+        self.set_tos((codepoint & packet::ECN_MASK) as libc::c_int)?;
+        let fd = self.icmp.transport.socket()?.as_raw_fd();
+        if self.address.is_ipv6() {
+            set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)
+        } else {
+            set_int_option(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)
+        }
+    }
+
+    fn set_tos(&mut self, tos: libc::c_int) -> Result<()> {
+        let fd = self.icmp.transport.socket()?.as_raw_fd();
+        if self.address.is_ipv6() {
+            set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)
+        } else {
+            set_int_option(fd, libc::IPPROTO_IP, libc::IP_TOS, tos)
+        }
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        // The hop limit, for ipv6
+        let socket = self.icmp.transport.socket()?;
+        log::info!("set {} to {} on socket {}", if self.address.is_ipv6() { "IPV6_UNICAST_HOPS" } else { "IP_TTL" }, ttl, socket.as_raw_fd());
+        if self.address.is_ipv6() { socket.set_unicast_hops_v6(ttl) } else { socket.set_ttl(ttl) }
+    }
+
+    /// Path MTU discovery like ping -M: "do" sets DF (IPv6 never fragments on the way
+    /// anyway, this stops us fragmenting locally), "want" only fragments once the kernel's
+    /// learned the path needs it, "dont" never sets DF, and "probe" sets DF but ignores
+    /// whatever path MTU the kernel learned, so big probes keep going out to be refused
+    pub fn set_pmtu_discovery(&mut self, mode: &str) -> Result<()> {
+        let ipv6 = self.address.is_ipv6();
+        let value = match (mode, ipv6) {
+            ("do", false) => libc::IP_PMTUDISC_DO,
+            ("want", false) => libc::IP_PMTUDISC_WANT,
+            ("dont", false) => libc::IP_PMTUDISC_DONT,
+            ("probe", false) => libc::IP_PMTUDISC_PROBE,
+            ("do", true) => libc::IPV6_PMTUDISC_DO,
+            ("want", true) => libc::IPV6_PMTUDISC_WANT,
+            ("dont", true) => libc::IPV6_PMTUDISC_DONT,
+            ("probe", true) => libc::IPV6_PMTUDISC_PROBE,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("unknown path MTU discovery mode {:?}", mode))),
+        };
+        let fd = self.icmp.transport.socket()?.as_raw_fd();
+        if ipv6 {
+            set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, value)
+        } else {
+            set_int_option(fd, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, value)
+        }
+    }
+
+    /// Tag everything we send with a firewall mark, for `ip rule fwmark` and friends to route
+    /// by. Needs CAP_NET_ADMIN, and has to happen before privileges are dropped
+    pub fn set_mark(&mut self, mark: u32) -> Result<()> {
+        set_int_option(self.icmp.transport.socket()?.as_raw_fd(), libc::SOL_SOCKET, libc::SO_MARK, mark as libc::c_int)
+    }
+
+    /// Have `interface`'s network card timestamp every probe going out and every packet
+    /// coming in, and time replies by those. Software timing jitters by more than a LAN rtt
+    /// on a busy box. Turning it on for the card needs CAP_NET_ADMIN, and a card (and
+    /// driver) that can, `ethtool -T` tells. Replies the card didn't stamp are timed as usual
+    pub fn set_hardware_timestamps(&mut self, interface: &str) -> Result<()> {
+        let fd = self.icmp.transport.socket()?.as_raw_fd();
+        if interface.len() >= libc::IFNAMSIZ {
+            return Err(Error::new(ErrorKind::InvalidInput, "interface name too long"));
+        }
+
+        let mut config = libc::hwtstamp_config { flags: 0, tx_type: libc::HWTSTAMP_TX_ON as libc::c_int, rx_filter: libc::HWTSTAMP_FILTER_ALL as libc::c_int };
+        let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (to, &from) in request.ifr_name.iter_mut().zip(interface.as_bytes()) {
+            *to = from as libc::c_char;
+        }
+        request.ifr_ifru.ifru_data = &mut config as *mut _ as *mut libc::c_char;
+        if unsafe { libc::ioctl(fd, libc::SIOCSHWTSTAMP as _, &mut request) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        log::info!("{} timestamps everything in hardware now (SIOCSHWTSTAMP)", interface);
+
+        // Transmit stamps come back on the error queue, just the stamp and which send it was
+        let flags = libc::SOF_TIMESTAMPING_TX_HARDWARE | libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+            | libc::SOF_TIMESTAMPING_OPT_ID | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+        set_int_option(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, flags as libc::c_int)?;
+        self.icmp.hardware.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// What an IcmpSocket needs from whatever's under it. Normally that's a real socket,
+/// tests put one that hands out canned packets there instead, so pinging can be tried
+/// out without root or a network
+trait IcmpTransport: Send + Sync {
+    /// Send `buf` to `to`, from `source` if there is one
+    fn send(&self, buf: &[u8], to: &SockAddr, source: Option<IpAddr>) -> Result<usize>;
+
+    /// Wait up to `timeout` for something to read (or Ctrl+C, that's an error)
+    fn wait_readable(&self, timeout: Duration) -> Result<bool>;
+
+    /// Read one packet, or with `errors` one ICMP error off the error queue
+    fn receive(&self, buf: &mut [u8], errors: bool) -> Result<Received>;
+
+    /// The socket, for setting options on. Only real sockets have any
+    fn socket(&self) -> Result<&Socket> {
+        Err(Error::new(ErrorKind::Unsupported, "not a socket"))
+    }
+}
+
+impl IcmpTransport for Socket {
+    // send_to, but from the source address if there is one. That's given with each
+    // packet instead of bound, so it can change from one probe to the next
+    fn send(&self, buf: &[u8], to: &SockAddr, source: Option<IpAddr>) -> Result<usize> {
+        let source = match source {
+            Some(source) => source,
+            None => return self.send_to(buf, to),
+        };
+
+        let mut control = [0u64; 8]; // u64s to keep the header aligned
+        let mut iov = libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() };
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_name = to.as_ptr() as *mut libc::c_void;
+        message.msg_namelen = to.len();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+
+        let bytes = unsafe {
+            let (level, name, size) = match source {
+                IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO, std::mem::size_of::<libc::in_pktinfo>()),
+                IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, std::mem::size_of::<libc::in6_pktinfo>()),
+            };
+            message.msg_controllen = libc::CMSG_SPACE(size as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&message);
+            (*cmsg).cmsg_level = level;
+            (*cmsg).cmsg_type = name;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size as u32) as _;
+
+            // No interface, the route picks it like it would with a bound socket
+            match source {
+                IpAddr::V4(source) => std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr { s_addr: u32::from(source).to_be() },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                }),
+                IpAddr::V6(source) => std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo, libc::in6_pktinfo {
+                    ipi6_addr: libc::in6_addr { s6_addr: source.octets() },
+                    ipi6_ifindex: 0,
+                }),
+            }
+            libc::sendmsg(self.as_raw_fd(), &message, 0)
+        };
+        if bytes < 0 { Err(Error::last_os_error()) } else { Ok(bytes as usize) }
+    }
+
+    fn wait_readable(&self, timeout: Duration) -> Result<bool> {
+        util::wait_readable(self.as_raw_fd(), timeout)
+    }
+
+    fn receive(&self, buf: &mut [u8], errors: bool) -> Result<Received> {
+        recv_msg(self, buf, if errors { libc::MSG_ERRQUEUE } else { 0 })
+    }
+
+    fn socket(&self) -> Result<&Socket> {
+        Ok(self)
+    }
+}
+
+// How often the receiver looks up from the socket to check if its pingers are still around
+const RECEIVER_POLL: Duration = Duration::from_millis(100);
+
+/// An ICMP socket and the thread reading it, handing every answer to the pinger it's for.
+/// Usually each pinger opens one of its own, which can have its own ttl, TOS and so on,
+/// and the kernel only has to look at the identifier to know whose reply it is. With a
+/// shared one (--socket-strategy shared) thousands of targets only take up one fd per
+/// family, but its options go for all of them, and sorting out the replies is up to us.
+pub struct IcmpSocket {
+    transport: Box<dyn IcmpTransport>,
+    ipv6: bool,
+    identifier: Option<u16>, // For datagram sockets, the kernel's. Raw ones let every pinger pick its own
+    pingers: AtomicU32,      // How many ever went on it
+    syscalls: AtomicU64,     // Reads, for the pinger that owns it to count
+    members: Mutex<Members>,
+    hardware: AtomicBool,    // Timestamping in hardware, see Pinger::set_hardware_timestamps
+    transmits: Mutex<Transmits>,
+}
+
+// Which probe each send on the socket was, for matching up the NIC's transmit stamps. The
+// kernel numbers them, counting from 0 when timestamping was turned on
+#[derive(Default)]
+struct Transmits {
+    next: u32,
+    probes: HashMap<u32, (IpAddr, u16)>,
+}
+
+// Transmit stamps that never came are forgotten after this many more sends
+const TRANSMITS_KEPT: u32 = 4096;
+
+// A pinger on a socket, and where its answers go
+type Member = (Arc<Shared>, mpsc::Sender<Result<PongResult>>);
+
+// The pingers reading off a socket, by the address they ping
+#[derive(Default)]
+struct Members {
+    pingers: HashMap<IpAddr, Vec<Member>>,
+    receiving: bool, // The thread is running
+}
+
+impl IcmpSocket {
+    /// A socket for every pinger in the family to share, datagram if we're allowed one
+    /// and raw otherwise (or always, with `raw`)
+    pub fn shared(ipv6: bool, raw: bool) -> Result<Self> {
+        if raw { IcmpSocket::open(ipv6, false) } else { IcmpSocket::open(ipv6, true).or_else(|_| IcmpSocket::open(ipv6, false)) }
+    }
+
+    fn open(ipv6: bool, datagram: bool) -> Result<Self> {
+        let domain = if ipv6 { Domain::ipv6() } else { Domain::ipv4() };
+        let protocol = if ipv6 { Protocol::icmpv6() } else { Protocol::icmpv4() };
+        let stype = if datagram { socket2::Type::dgram() } else { socket2::Type::raw() }.cloexec();
+        let socket = Socket::new(domain, stype, Some(protocol))?;
+        socket.set_nonblocking(true)?; // Receiving waits with poll, see receive_loop
+        // When the kernel got each packet, see Received::arrived. Without it times go by when we read them
+        let _ = set_int_option(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1);
+
+        let identifier = if datagram {
+            // The identifier is the "port" the socket ends up bound to
+            let unspecified = if ipv6 { IpAddr::from(Ipv6Addr::UNSPECIFIED) } else { IpAddr::from(Ipv4Addr::UNSPECIFIED) };
+            socket.bind(&SockAddr::from(SocketAddr::new(unspecified, 0)))?;
+            let identifier = socket.local_addr()?.as_std().map(|local| local.port())
+                .ok_or_else(|| Error::other("no local address for the socket"))?;
+
+            // There's no IP header to read the ttl from, and ICMP errors only come through
+            // the error queue, so ask for both as ancillary data
+            let fd = socket.as_raw_fd();
+            if ipv6 {
+                set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+                set_int_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
             } else {
-                let ip_packet = match self.coder.deserialize::<packet::IPv4Header>(&buf) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        return Err(Error::new(ErrorKind::InvalidData, e.to_string()));
+                set_int_option(fd, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?;
+                set_int_option(fd, libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+            }
+            Some(identifier)
+        } else {
+            None
+        };
+
+        log::info!("opened a {} ICMPv{} socket ({}){}", if datagram { "datagram" } else { "raw" }, if ipv6 { 6 } else { 4 }, socket.as_raw_fd(),
+            identifier.map(|identifier| format!(", the kernel gave it identifier {}", identifier)).unwrap_or_default());
+        Ok(IcmpSocket::on_transport(Box::new(socket), ipv6, identifier))
+    }
+
+    fn on_transport(transport: Box<dyn IcmpTransport>, ipv6: bool, identifier: Option<u16>) -> Self {
+        IcmpSocket {
+            transport, ipv6, identifier,
+            pingers: AtomicU32::new(0),
+            syscalls: AtomicU64::new(0),
+            members: Mutex::new(Members::default()),
+            hardware: AtomicBool::new(false),
+            transmits: Mutex::new(Transmits::default()),
+        }
+    }
+
+    // Start handing a pinger its answers, with the thread reading the socket started if it isn't yet
+    fn join(self: &Arc<Self>, shared: Arc<Shared>, sender: mpsc::Sender<Result<PongResult>>) -> Result<()> {
+        let mut members = self.members.lock().unwrap();
+        members.pingers.entry(shared.address).or_default().push((shared, sender));
+        if !members.receiving {
+            let icmp = self.clone();
+            thread::Builder::new().name("ring-receiver".to_string())
+                .spawn(move || icmp.receive_loop())?;
+            members.receiving = true;
+        }
+        Ok(())
+    }
+
+    // Whether anyone's still reading, after letting go of the pingers that are gone
+    fn keep_receiving(&self) -> bool {
+        let mut members = self.members.lock().unwrap();
+        members.pingers.retain(|_, pingers| {
+            pingers.retain(|(shared, _)| !shared.stopped.load(Ordering::SeqCst));
+            !pingers.is_empty()
+        });
+        members.receiving = !members.pingers.is_empty();
+        members.receiving
+    }
+
+    // Runs on the receiver thread until the pingers are all dropped or Ctrl+C, sending every
+    // answer to an outstanding probe back as soon as it arrives
+    fn receive_loop(&self) {
+        // Only the bytes received are ever looked at, so there's no need to clear it between reads
+        let mut buf = vec![0; RECV_BUF_LEN];
+        let datagram = self.identifier.is_some();
+
+        while self.keep_receiving() {
+            // The socket is non-blocking, so wait for something to arrive (or Ctrl+C) first
+            self.count_syscalls(1);
+            match self.transport.wait_readable(RECEIVER_POLL) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => { // Hanging up tells the pingers we were interrupted
+                    let mut members = self.members.lock().unwrap();
+                    members.pingers.clear();
+                    members.receiving = false;
+                    return;
+                }
+            }
+
+            if datagram || self.hardware.load(Ordering::Relaxed) {
+                // Errors wake up poll too, and keep on waking it until they're read. So do transmit stamps
+                self.count_syscalls(1);
+                match self.transport.receive(&mut buf, true) {
+                    Ok(received) if received.error.is_some_and(|(origin, ..)| origin == libc::SO_EE_ORIGIN_TIMESTAMPING) => self.transmitted(&received),
+                    Ok(received) => {
+                        if dump::enabled() {
+                            dump::packet("read off the error queue", received.from, &buf[..received.bytes], false, self.ipv6);
+                        }
+                        self.dispatch(&buf[..received.bytes], &received, received.arrived(), true)
                     }
-                };
-
-                // Get the 'header length' portion of the u8, which is encoded as u8/4 (bits/32)
-                let data_offset = 4 * (ip_packet.version_and_header_len & 0x0F); 
-            
-                GenericIPHeader { 
-                    datagram_length: ip_packet.datagram_length,
-                    data_offset,
-                    ttl: Some(ip_packet.ttl),
+                    Err(_) => {}
                 }
+            }
+
+            self.count_syscalls(1);
+            match self.transport.receive(&mut buf, false) {
+                Ok(received) => {
+                    log::trace!("read {} bytes from {}", received.bytes, received.from);
+                    if dump::enabled() {
+                        // Only raw IPv4 sockets hand over the IP header
+                        dump::packet("read", received.from, &buf[..received.bytes], !datagram && !self.ipv6, self.ipv6);
+                    }
+                    self.dispatch(&buf[..received.bytes], &received, received.arrived(), false)
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {} // Someone else got to it
+                // The error queue's copy of an ICMP error is the one that says whose probe it was about
+                Err(ref e) if datagram && self.pingers.load(Ordering::SeqCst) > 1 && reported_icmp_error(e) => {}
+                Err(e) => for (_, pongs) in self.members.lock().unwrap().pingers.values().flatten() {
+                    let _ = pongs.send(Err(Error::new(e.kind(), e.to_string())));
+                },
+            }
+        }
+    }
+
+    // Hand what was read to the pinger it answers, if it's anyone's. Whoever pings the address
+    // it came from is asked first, that's every reply. Errors from routers on the way could be
+    // for anyone
+    fn dispatch(&self, buf: &[u8], received: &Received, received_at: Instant, error: bool) {
+        let read_at = Instant::now(); // Processing starts now, however long it sat in the queue
+        let members = self.members.lock().unwrap();
+        let mut candidates = members.pingers.get(&received.from).into_iter().flatten()
+            .chain(members.pingers.iter().filter(|&(&address, _)| address != received.from).flat_map(|(_, pingers)| pingers));
+
+        let answered = candidates.find_map(|(shared, pongs)| {
+            let pong = if error {
+                shared.process_error(buf, received, received_at)
+            } else {
+                let pong = shared.process_packet(buf, received, received_at);
+                shared.processing.lock().unwrap().record(read_at.elapsed());
+                pong
             };
+            pong.map(|pong| (shared, pongs, pong))
+        });
 
-            // The IMCP portion will be located after the IP Header
-            let icmp_packet = &buf[header.data_offset as usize..];
-            let icmp_packet = match self.coder.deserialize::<packet::ICMPEchoPacket>(icmp_packet) {
-                Ok(p) => p,
-                Err(e) => {
-                    return Err(Error::new(ErrorKind::InvalidData, e.to_string()));
+        if let Some((shared, pongs, pong)) = answered {
+            if pongs.send(Ok(pong)).is_err() {
+                shared.stopped.store(true, Ordering::SeqCst); // Nobody's asking anymore
+            }
+        }
+    }
+
+    fn count_syscalls(&self, n: u64) {
+        self.syscalls.fetch_add(n, Ordering::Relaxed);
+    }
+
+    // A probe to `address` went out, which needs noting down if its transmit stamp's coming
+    fn sent(&self, address: IpAddr, sequence: u16) {
+        if !self.hardware.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut transmits = self.transmits.lock().unwrap();
+        let key = transmits.next;
+        transmits.next = key.wrapping_add(1);
+        transmits.probes.insert(key, (address, sequence));
+        if transmits.probes.len() as u32 > TRANSMITS_KEPT {
+            transmits.probes.retain(|&sent, _| key.wrapping_sub(sent) < TRANSMITS_KEPT);
+        }
+    }
+
+    // The NIC's stamp for a probe that went out, kept with the probe until its answer
+    fn transmitted(&self, received: &Received) {
+        let (probe, stamp) = match (received.transmit, received.hardware) {
+            (Some(key), Some(stamp)) => match self.transmits.lock().unwrap().probes.remove(&key) {
+                Some(probe) => (probe, stamp),
+                None => return,
+            },
+            _ => return,
+        };
+
+        let (address, sequence) = probe;
+        let members = self.members.lock().unwrap();
+        for (shared, _) in members.pingers.get(&address).into_iter().flatten() {
+            if let Some(probe) = shared.in_flight.lock().unwrap().get_mut(&sequence) {
+                probe.hardware_sent = Some(stamp);
+                return;
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Look at everything in a received buffer for a reply we're waiting on. None means
+    /// nothing in it was for us and it should be skipped.
+    fn process_packet(&self, buf: &[u8], received: &Received, received_at: Instant) -> Option<PongResult> {
+        let mut remaining = buf;
+        let mut matched = None;
+        let wanted = |sequence| [&self.in_flight, &self.answered, &self.given_up].iter().any(|probes| probes.lock().unwrap().contains_key(&sequence));
+
+        // A read normally holds exactly one packet, but don't count on it
+        while !remaining.is_empty() {
+            let (length, parsed) = if self.datagram {
+                // Exactly one message, and the kernel has already taken the IP header off
+                let header = GenericIPHeader { datagram_length: remaining.len() as u16, data_offset: 0, ttl: received.ttl, tos: received.tos, route: None };
+                (remaining.len(), classify(remaining, &header, self.address, self.session, &wanted))
+            } else {
+                parse_packet(remaining, self.address, self.session, &wanted, received.tos)
+            };
+            let packet = &remaining[..length];
+            remaining = &remaining[length..];
+
+            match parsed {
+                Parsed::Ignored(why) => log::debug!("ignored {} bytes from {} for {}: {}", length, received.from, self.address, why),
+                Parsed::Corrupted => {
+                    log::debug!("dropped {} bytes from {} for {}: bad checksum", length, received.from, self.address);
+                    self.checksum_failures.fetch_add(1, Ordering::Relaxed);
                 }
+                // On a shared datagram socket everyone has the same identifier, the address is what says it's ours
+                Parsed::Matched(ref reply) if self.crowded && reply.mtype == ReplyType::Reply && received.from != self.address => {}
+                Parsed::Matched(reply) => { matched.get_or_insert((reply, packet)); }
+            }
+        }
+
+        let (reply, packet) = matched?;
+        let payload = reply.payload_at.and_then(|at| packet.get(at..));
+        self.pong(reply, payload, received.from, received_at, received.hardware)
+    }
+
+    /// An ICMP error from a datagram socket's error queue. What's read is our own echo
+    /// request that caused it, the details of the error come along as ancillary data
+    fn process_error(&self, buf: &[u8], received: &Received, received_at: Instant) -> Option<PongResult> {
+        let (origin, message_type, code, info) = received.error?;
+        let ipv6 = self.address.is_ipv6();
+        if origin != if ipv6 { libc::SO_EE_ORIGIN_ICMP6 } else { libc::SO_EE_ORIGIN_ICMP } {
+            return None; // Something local, like the packet being too big to send
+        }
+
+        if self.crowded && received.to != Some(self.address) {
+            return None; // Another pinger's on this socket, it was one of theirs
+        }
+        let original = packet::ICMPEchoPacket::parse(buf)?;
+        if !self.in_flight.lock().unwrap().contains_key(&original.sequence_num) {
+            return None;
+        }
+
+        let mtype = match (ipv6, message_type) {
+            (false, TIMEOUT_V4) | (true, TIMEOUT_V6) => ReplyType::TimeLimitExceeded,
+            (false, UNREACHABLE_V4) | (true, UNREACHABLE_V6) => ReplyType::DestinationUnreachable(code),
+            (false, PARAMETER_PROBLEM_V4) | (true, PARAMETER_PROBLEM_V6) => ReplyType::ParameterProblem(code, info),
+            _ => return None,
+        };
+
+        let reply = ParsedReply { sequence: Some(original.sequence_num), mtype, ttl: received.ttl, tos: None, route: None, size: 0, payload_at: None };
+        self.pong(reply, None, received.from, received_at, None)
+    }
+
+    // Turns a reply to one of our probes into its result, None if it's not outstanding anymore.
+    // `payload` is what an echo reply brought back of ours, `hardware` when the NIC took it in
+    fn pong(&self, reply: ParsedReply, payload: Option<&[u8]>, from: IpAddr, received_at: Instant, hardware: Option<Duration>) -> Option<PongResult> {
+        // ICMPv6 redirects, put down to the latest probe
+        let sequence = reply.sequence.unwrap_or_else(|| self.latest.load(Ordering::SeqCst));
+
+        // Redirects are only advice, the probe is still out there waiting on its real answer
+        let probe = match reply.mtype {
+            ReplyType::Redirect(_, _) => self.in_flight.lock().unwrap().get(&sequence).copied(),
+            _ => self.in_flight.lock().unwrap().remove(&sequence),
+        };
+        // Another echo reply to one that's been answered is a duplicate, often from a layer 2
+        // loop. One to a probe given up on is late, and answers it after all
+        let (probe, duplicate, late) = match probe {
+            None if reply.mtype != ReplyType::Reply => return None,
+            None => match self.given_up.lock().unwrap().remove(&sequence) {
+                Some(probe) => (probe, false, true),
+                None => (self.answered.lock().unwrap().get(&sequence).copied()?, true, false),
+            },
+            Some(probe) => (probe, false, false),
+        };
+        if reply.mtype == ReplyType::Reply && !duplicate {
+            self.answered.lock().unwrap().insert(sequence, probe);
+        }
+        let corruption = payload.and_then(|payload| self.corruption(payload, &probe));
+        // The copy of the send time that came back is the one to go by, as long as it makes
+        // sense. Our own note is the fallback, when the payload had no room or got mangled
+        let stamp = payload.and_then(|payload| payload.get(8..16)).map(|stamp| stamp.iter().fold(0, |stamp, &byte| stamp << 8 | byte as u64));
+        let sent_at = stamp.map(|stamp| self.epoch + Duration::from_nanos(stamp))
+            .filter(|&stamped| stamped <= received_at && stamped + Duration::from_secs(1) >= probe.sent_at && stamped <= probe.sent_at + Duration::from_secs(1))
+            .unwrap_or(probe.sent_at);
+        // Both ends stamped by the card beat anything measured up here
+        let hardware = probe.hardware_sent.zip(hardware).filter(|(sent, received)| received > sent)
+            .map(|(sent, received)| HardwareStamps { sent, received });
+
+        // It was! Construct a Pong Result
+        Some(PongResult {
+            address: from,
+            hostname: None, // Filled in by the pinger, it's slow
+
+            sequence, // Errors quote this in the embedded packet instead of the header
+            ttl: reply.ttl,
+            route: reply.route,
+            size: reply.size,
+            rtt: hardware.map_or_else(|| received_at.duration_since(sent_at), |stamps| stamps.received - stamps.sent),
+            mtype: reply.mtype,
+            phases: Vec::new(), detail: None, transport: None, corruption,
+            tos: reply.tos,
+            duplicate, late, hardware,
+        })
+    }
+
+    // Where an echoed payload first differs from what went out: the incrementing pattern (or
+    // random data), with the probe's id, send time and flow bytes wherever Pinger::ping put them
+    fn corruption(&self, payload: &[u8], probe: &Outstanding) -> Option<Corruption> {
+        let (size, flow) = *self.payload.lock().unwrap();
+        // The size can change from probe to probe (--size-sweep), this one's came back with it
+        let size = std::cmp::min(size, payload.len());
+        let room = size.saturating_sub(if flow { 2 } else { 0 });
+        let stamp = if room >= 16 { Some((probe.sent_at.duration_since(self.epoch).as_nanos() as u64).to_be_bytes()) } else { None };
+
+        let mut corruption: Option<Corruption> = None;
+        for (at, &got) in payload.iter().enumerate().take(size) {
+            let expected = match (at, probe.id.map(u64::to_be_bytes), stamp) {
+                _ if flow && at + 2 >= size => continue, // Whatever makes the checksum come out the same
+                (0..=7, Some(id), _) => id[at],
+                (8..=15, _, Some(stamp)) => stamp[at - 8],
+                _ => payload_byte(probe.seed, at),
             };
+            if got != expected {
+                corruption.get_or_insert(Corruption { offset: at, expected, got, bytes: 0 }).bytes += 1;
+            }
+        }
+        corruption
+    }
 
-            // Make sure that this is the right type of packet
-            let mtype: ReplyType;
-            if self.address.is_ipv6() {
-                match icmp_packet.message_type {
-                    ECHO_REPLY_V6 => { mtype = ReplyType::Reply }
-                    TIMEOUT_V6    => { mtype = ReplyType::TimeLimitExceeded }
-                    _ => continue
+    fn count_syscalls(&self, n: u64) {
+        self.syscalls.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+// Byte `at` of the data in a probe: the incrementing pattern, or with a seed the random data
+// made from it. Counted off from the seed (splitmix64), so the receiver can work it out again
+fn payload_byte(seed: Option<u64>, at: usize) -> u8 {
+    let seed = match seed {
+        Some(seed) => seed,
+        None => return at as u8,
+    };
+    let mut z = seed.wrapping_add((at as u64 / 8 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).to_le_bytes()[at % 8]
+}
+
+/// A read off the socket, with what recvmsg told us about it
+struct Received {
+    bytes: usize,
+    from: IpAddr,
+    to: Option<IpAddr>, // For errors, where the probe that caused it was going
+    ttl: Option<u8>,
+    tos: Option<u8>,
+    error: Option<(u8, u8, u8, u32)>, // From the error queue: origin, ICMP type, code and info
+    stamp: Option<SystemTime>, // When the kernel took it in, with SO_TIMESTAMPNS
+    hardware: Option<Duration>, // When the NIC did, or sent it for transmit stamps. See Pinger::set_hardware_timestamps
+    transmit: Option<u32>,      // For transmit stamps, which send on the socket it was
+}
+
+impl Received {
+    // When it came in. That's the kernel's timestamp if there is one, under load a reply can
+    // sit in the socket's queue for a while before the receiver gets scheduled to read it.
+    // Otherwise (or if the clock jumped meanwhile) it's now, as it's read
+    fn arrived(&self) -> Instant {
+        let now = Instant::now();
+        self.stamp.and_then(|stamp| SystemTime::now().duration_since(stamp).ok())
+            .filter(|&queued| queued < Duration::from_secs(1))
+            .and_then(|queued| now.checked_sub(queued))
+            .unwrap_or(now)
+    }
+}
+
+// recvmsg, for the ancillary data datagram sockets pass the ttl and ICMP errors along
+// in. For errors `from` is whoever sent the error, and `to` the destination
+fn recv_msg(socket: &Socket, buf: &mut [u8], flags: libc::c_int) -> Result<Received> {
+    let mut address: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut control = [0u64; 64]; // u64s to keep the headers aligned
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_name = &mut address as *mut _ as *mut libc::c_void;
+    message.msg_namelen = std::mem::size_of_val(&address) as libc::socklen_t;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let bytes = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, flags) };
+    if bytes < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut received = Received {
+        bytes: bytes as usize,
+        from: sockaddr_ip(&address).unwrap_or(IpAddr::from(Ipv4Addr::UNSPECIFIED)),
+        to: None, ttl: None, tos: None, error: None, stamp: None, hardware: None, transmit: None,
+    };
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&message);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    received.ttl = Some(std::ptr::read_unaligned(data as *const libc::c_int) as u8);
                 }
-            } else {
-                match icmp_packet.message_type {
-                    ECHO_REPLY_V4 => { mtype = ReplyType::Reply }
-                    TIMEOUT_V4    => { mtype = ReplyType::TimeLimitExceeded }
-                    _ => continue
+                // Only with set_ecn. A byte for IPv4, an int for IPv6
+                (libc::IPPROTO_IP, libc::IP_TOS) => received.tos = Some(*data),
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    received.tos = Some(std::ptr::read_unaligned(data as *const libc::c_int) as u8);
+                }
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                    let stamp = std::ptr::read_unaligned(data as *const libc::timespec);
+                    received.stamp = Some(SystemTime::UNIX_EPOCH + Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32));
+                }
+                // Software, legacy and raw hardware stamps, only the last is asked for
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                    let stamp = std::ptr::read_unaligned((data as *const libc::timespec).add(2));
+                    if stamp.tv_sec != 0 || stamp.tv_nsec != 0 {
+                        received.hardware = Some(Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32));
+                    }
+                }
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                    let error = std::ptr::read_unaligned(data as *const libc::sock_extended_err);
+                    received.error = Some((error.ee_origin, error.ee_type, error.ee_code, error.ee_info));
+                    if error.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                        received.transmit = Some(error.ee_data);
+                    }
+
+                    // The router (or host) that sent the error comes right after, the name
+                    // is the address the probe was going to
+                    received.to = Some(received.from);
+                    let offender = std::ptr::read_unaligned(libc::SO_EE_OFFENDER(data as *const libc::sock_extended_err) as *const libc::sockaddr_storage);
+                    if let Some(from) = sockaddr_ip(&offender) {
+                        received.from = from;
+                    }
                 }
+                _ => {}
             }
+            cmsg = libc::CMSG_NXTHDR(&message, cmsg);
+        }
+    }
+
+    Ok(received)
+}
 
-            if mtype == ReplyType::Reply {
-                // Check that this is the packet that we were looking for
-                if icmp_packet.identifier != self.session { continue };
-                if icmp_packet.sequence_num != sequence_num { continue };
+// Datagram sockets also hand each ICMP error back as the errno of the next send or recv,
+// whichever pinger on the socket makes it
+fn reported_icmp_error(e: &Error) -> bool {
+    [libc::EHOSTUNREACH, libc::ENETUNREACH, libc::ECONNREFUSED, libc::EMSGSIZE, libc::EPROTO].contains(&e.raw_os_error().unwrap_or(0))
+}
+
+fn sockaddr_ip(address: &libc::sockaddr_storage) -> Option<IpAddr> {
+    match address.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let address = unsafe { &*(address as *const _ as *const libc::sockaddr_in) };
+            Some(IpAddr::from(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr))))
+        }
+        libc::AF_INET6 => {
+            let address = unsafe { &*(address as *const _ as *const libc::sockaddr_in6) };
+            Some(IpAddr::from(Ipv6Addr::from(address.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+fn set_int_option(fd: libc::c_int, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(fd, level, name, &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    log::info!("set {} to {} on socket {}", option_name(level, name), value, fd);
+    Ok(())
+}
+
+// For -v, the options set_int_option sets
+fn option_name(level: libc::c_int, name: libc::c_int) -> String {
+    let known = match (level, name) {
+        (libc::SOL_SOCKET, libc::SO_MARK) => "SO_MARK",
+        (libc::SOL_SOCKET, libc::SO_TIMESTAMPNS) => "SO_TIMESTAMPNS",
+        (libc::SOL_SOCKET, libc::SO_TIMESTAMPING) => "SO_TIMESTAMPING",
+        (libc::IPPROTO_IP, libc::IP_TOS) => "IP_TOS",
+        (libc::IPPROTO_IP, libc::IP_RECVTOS) => "IP_RECVTOS",
+        (libc::IPPROTO_IP, libc::IP_RECVTTL) => "IP_RECVTTL",
+        (libc::IPPROTO_IP, libc::IP_RECVERR) => "IP_RECVERR",
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER) => "IP_MTU_DISCOVER",
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => "IPV6_TCLASS",
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS) => "IPV6_RECVTCLASS",
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT) => "IPV6_RECVHOPLIMIT",
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => "IPV6_RECVERR",
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER) => "IPV6_MTU_DISCOVER",
+        (libc::IPPROTO_IPV6, libc::IPV6_FLOWINFO_SEND) => "IPV6_FLOWINFO_SEND",
+        _ => return format!("option {}/{}", level, name),
+    };
+    known.to_string()
+}
+
+/// What a single packet out of the socket turned out to be
+enum Parsed {
+    Ignored(&'static str), // Not for us, or too mangled to tell. Why, for -vv
+    Corrupted, // Failed a checksum
+    Matched(ParsedReply),
+}
+
+struct ParsedReply {
+    sequence: Option<u16>, // Which probe it answers, when the packet says
+    mtype: ReplyType,
+    ttl: Option<u8>,
+    tos: Option<u8>,
+    route: Option<Vec<Ipv4Addr>>,
+    size: u16,
+    payload_at: Option<usize>, // For echo replies, where the data we sent starts in the packet
+}
+
+/// Parse the packet at the start of `buf`, checking if it answers one of the probes of
+/// `session` sent to `address` (the sequence numbers `wanted` accepts). Returns how many bytes the packet took up along with the result,
+/// so the caller can move on to anything after it. Never fails, anything that can't be
+/// parsed is ignored. `tos` is the traffic class from the ancillary data, for IPv6.
+fn parse_packet(buf: &[u8], address: IpAddr, session: u16, wanted: &dyn Fn(u16) -> bool, tos: Option<u8>) -> (usize, Parsed) {
+    let header = if address.is_ipv6() {
+        // The socket doesn't put the header into our buffer
+        // so unfortunately we cannot extract the ttl (or hop_limit as it's called in ipv6)
+
+        GenericIPHeader {
+            datagram_length: buf.len() as u16,
+            data_offset: 0,
+            ttl: None,
+            tos,
+            route: None,
+        }
+    } else {
+        match ipv4_header(buf) {
+            Some((header, _)) => header,
+            None => return (buf.len(), Parsed::Ignored("no IPv4 header to tell where it ends")),
+        }
+    };
+
+    let length = header.datagram_length as usize;
+    (length, classify(&buf[..length], &header, address, session, wanted))
+}
+
+/// The IPv4 header at the start of `buf`, None if it's too mangled to tell where the
+/// packet ends
+fn ipv4_header(buf: &[u8]) -> Option<(GenericIPHeader, packet::IPv4Header)> {
+    let ip_packet = packet::parse_ipv4_header(buf).ok()?;
+    let header = GenericIPHeader {
+        datagram_length: ip_packet.total_len as u16,
+        data_offset: ip_packet.header_len as u8,
+        ttl: Some(ip_packet.header.ttl),
+        tos: Some(ip_packet.header.type_of_service),
+        route: ip_packet.route,
+    };
+    Some((header, ip_packet.header))
+}
+
+fn classify(buf: &[u8], header: &GenericIPHeader, address: IpAddr, session: u16, wanted: &dyn Fn(u16) -> bool) -> Parsed {
+    if !checksums_valid(buf, header, address) {
+        return Parsed::Corrupted;
+    }
+
+    // The IMCP portion will be located after the IP Header
+    let message = match buf.get(header.data_offset as usize..).map(|icmp| packet::parse_icmp(icmp, address.is_ipv6())) {
+        Some(Ok(message)) => message,
+        _ => return Parsed::Ignored("not an ICMP message"),
+    };
+    let (icmp_packet, icmp_data) = (&message.header, message.data);
+
+    // Make sure that this is the right type of packet
+    let mtype = match reply_type(message.kind) {
+        Some(mtype) => mtype,
+        None => return Parsed::Ignored("not a reply or an error (our own request, looped back?)"),
+    };
+
+    let sequence = match mtype {
+        ReplyType::Reply => {
+            // Check that this is a packet that we were looking for
+            if icmp_packet.identifier != session { return Parsed::Ignored("someone else's identifier, another ping's reply") };
+            if !wanted(icmp_packet.sequence_num) { return Parsed::Ignored("sequence number of no probe we've sent lately") };
+            Some(icmp_packet.sequence_num)
+        }
+
+        // ICMPv6 redirects don't carry our packet, just the destination they're for
+        ReplyType::Redirect(_, _) if address.is_ipv6() => {
+            if packet::redirected_destination(icmp_data) != Some(address) { return Parsed::Ignored("redirect for another destination") };
+            None
+        }
+
+        _ => {
+            // Errors carry a copy of the packet that caused them, make sure it was ours and
+            // not one belonging to another ping running on this host
+            match packet::quoted_echo(icmp_data, address.is_ipv6()) {
+                Some(original) if original.identifier == session
+                               && wanted(original.sequence_num) => Some(original.sequence_num),
+                _ => return Parsed::Ignored("error about someone else's packet")
+            }
+        }
+    };
+
+    // Replies echo our payload back, send time and all
+    let payload_at = match mtype {
+        ReplyType::Reply => Some(header.data_offset as usize + packet::ICMP_ECHO_HEADER_LEN),
+        _ => None,
+    };
+
+    Parsed::Matched(ParsedReply {
+        sequence,
+        mtype,
+        ttl: header.ttl,
+        tos: header.tos,
+        route: header.route.clone(),
+        size: header.datagram_length - header.data_offset as u16,
+        payload_at,
+    })
+}
+
+/// The replies and errors we understand, None for anything else (echo requests included)
+fn reply_type(kind: IcmpKind) -> Option<ReplyType> {
+    Some(match kind {
+        IcmpKind::EchoReply => ReplyType::Reply,
+        IcmpKind::TimeExceeded => ReplyType::TimeLimitExceeded,
+        IcmpKind::Unreachable(code) => ReplyType::DestinationUnreachable(code),
+        IcmpKind::Redirect(code, gateway) => ReplyType::Redirect(code, gateway),
+        IcmpKind::ParameterProblem(code, pointer) => ReplyType::ParameterProblem(code, pointer),
+        IcmpKind::EchoRequest | IcmpKind::Other => return None,
+    })
+}
+
+/// What an overheard ICMP message was
+#[derive(PartialEq)]
+pub enum Heard {
+    EchoRequest,
+    EchoReply,
+    Error(ReplyType), // Redirects too
+}
+
+/// An ICMP message that arrived at this host, whoever it was for
+pub struct Overheard {
+    pub heard: Heard,
+    pub from: IpAddr,
+    pub to: Option<IpAddr>,      // Only known for IPv4, where we get the header
+    pub identifier: Option<u16>, // For errors, the ones in the echo request they quote (if they quote one)
+    pub sequence: Option<u16>,
+    pub about: Option<IpAddr>,   // For errors, where the packet that caused it was going
+    pub quoted: Option<(u8, u16, u16)>, // For errors about TCP or UDP, its protocol, source and destination ports
+    pub ttl: Option<u8>,
+    pub size: u16,
+    pub corrupted: bool, // Failed a checksum, what's in it may not mean much
+}
+
+/// A raw ICMP socket that only ever receives, for `ring listen`. It sees every ICMP message
+/// the host gets, echo requests and other pings' replies included
+pub struct Listener {
+    socket: Socket,
+    ipv6: bool,
+    buf: Vec<u8>,
+}
+
+impl Listener {
+    pub fn new(ipv6: bool) -> Result<Self> {
+        let (domain, protocol) = if ipv6 { (Domain::ipv6(), Protocol::icmpv6()) } else { (Domain::ipv4(), Protocol::icmpv4()) };
+        let socket = Socket::new(domain, socket2::Type::raw().cloexec(), Some(protocol))?;
+        socket.set_nonblocking(true)?;
+        if ipv6 {
+            // There's no header to read the hop limit from
+            set_int_option(socket.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+        }
+        Ok(Listener { socket, ipv6, buf: vec![0; RECV_BUF_LEN] })
+    }
+
+    /// Waits up to `timeout` for something to arrive, and decodes it. Empty if nothing did,
+    /// or it was nothing we understand
+    pub fn receive(&mut self, timeout: Duration) -> Result<Vec<Overheard>> {
+        if !util::wait_readable(self.socket.as_raw_fd(), timeout)? {
+            return Ok(Vec::new());
+        }
+        let received = match recv_msg(&self.socket, &mut self.buf, 0) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(overhear(&self.buf[..received.bytes], received.from, received.ttl, self.ipv6))
+    }
+}
+
+/// Everything in a read off a raw socket, decoded with the same parsing as our own replies
+/// but without caring who it was for. `hop_limit` is from the ancillary data, for IPv6
+fn overhear(buf: &[u8], from: IpAddr, hop_limit: Option<u8>, ipv6: bool) -> Vec<Overheard> {
+    let mut heard = Vec::new();
+    let mut remaining = buf;
+
+    while !remaining.is_empty() {
+        let (header, to) = if ipv6 {
+            (GenericIPHeader { datagram_length: remaining.len() as u16, data_offset: 0, ttl: hop_limit, tos: None, route: None }, None)
+        } else {
+            match ipv4_header(remaining) {
+                Some((header, ip_packet)) => (header, Some(IpAddr::from(Ipv4Addr::from(ip_packet.destination_ip)))),
+                None => break,
             }
+        };
+        let length = header.datagram_length as usize;
+        if let Some(mut overheard) = decode(&remaining[..length], &header, from, ipv6) {
+            overheard.to = to;
+            heard.push(overheard);
+        }
+        remaining = &remaining[length..];
+    }
 
-            // It was! Construct a Pong Result
-            return Ok(PongResult {
-                address: self.address,
-                hostname: lookup_addr(&from.as_std().unwrap().ip()).ok(),
-            
-                sequence: icmp_packet.sequence_num,
-                ttl: header.ttl,
-                size: header.datagram_length - header.data_offset as u16,
-                rtt: Instant::now().duration_since(begin_time),
-                mtype,
-            })
+    heard
+}
+
+fn decode(buf: &[u8], header: &GenericIPHeader, from: IpAddr, ipv6: bool) -> Option<Overheard> {
+    // checksums_valid only needs an address to know which family it's looking at
+    let family = if ipv6 { IpAddr::from(Ipv6Addr::UNSPECIFIED) } else { IpAddr::from(Ipv4Addr::UNSPECIFIED) };
+    let message = packet::parse_icmp(buf.get(header.data_offset as usize..)?, ipv6).ok()?;
+    let (icmp_packet, icmp_data) = (&message.header, message.data);
+
+    let heard = match message.kind {
+        IcmpKind::EchoRequest => Heard::EchoRequest,
+        kind => match reply_type(kind)? {
+            ReplyType::Reply => Heard::EchoReply,
+            mtype => Heard::Error(mtype),
+        },
+    };
+
+    let mut overheard = Overheard {
+        heard, from, to: None,
+        identifier: None, sequence: None, about: None, quoted: None,
+        ttl: header.ttl,
+        size: header.datagram_length - header.data_offset as u16,
+        corrupted: !checksums_valid(buf, header, family),
+    };
+
+    match overheard.heard {
+        Heard::EchoRequest | Heard::EchoReply => {
+            overheard.identifier = Some(icmp_packet.identifier);
+            overheard.sequence = Some(icmp_packet.sequence_num);
+        }
+        Heard::Error(ReplyType::Redirect(_, _)) if ipv6 => overheard.about = packet::redirected_destination(icmp_data),
+        Heard::Error(_) => {
+            overheard.about = packet::quoted_destination(icmp_data, ipv6);
+            overheard.quoted = packet::quoted_ports(icmp_data, ipv6);
+            if let Some(original) = packet::quoted_echo(icmp_data, ipv6) {
+                overheard.identifier = Some(original.identifier);
+                overheard.sequence = Some(original.sequence_num);
+            }
         }
     }
 
-    pub fn set_ttl(&mut self, ttl: u32) -> Result<()> {
-        self.socket.set_ttl(ttl)
+    Some(overheard)
+}
+
+/// Check the IPv4 header and ICMP checksums of a received packet
+fn checksums_valid(buf: &[u8], header: &GenericIPHeader, address: IpAddr) -> bool {
+    let data_offset = header.data_offset as usize;
+
+    if address.is_ipv6() {
+        // The kernel always verifies ICMPv6 checksums (they cover a pseudo-header
+        // we never see) and drops bad packets before they reach us
+        return true;
+    }
+
+    // Datagram sockets don't hand over the IP header, the kernel already checked it
+    let ip_header = match buf.get(..data_offset) {
+        Some(h) if h.len() >= packet::IPV4_HEADER_LEN || data_offset == 0 => h,
+        _ => return false,
+    };
+
+    // The header checksum is the 6th word. When there are options the local stack may
+    // have filled them in (record route) after it checked the checksum, so skip those.
+    if ip_header.len() == packet::IPV4_HEADER_LEN
+       && util::get_checksum(ip_header, 5) != u16::from_be_bytes([ip_header[10], ip_header[11]]) {
+        return false;
+    }
+
+    match buf.get(data_offset..) {
+        Some(icmp) if icmp.len() >= 4 => util::get_checksum(icmp, 1) == u16::from_be_bytes([icmp[2], icmp[3]]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{ECHO_REPLY_V4, IPV4_PROTOCOL_ICMP, IPV6_NEXT_HEADER_ICMPV6};
+
+    const SESSION: u16 = 0xBEEF;
+    const DESTINATION: [u8; 4] = [192, 0, 2, 7];
+
+    fn icmp(message_type: u8, code: u8, rest_of_header: [u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![message_type, code, 0, 0];
+        packet.extend_from_slice(&rest_of_header);
+        packet.extend_from_slice(data);
+        util::set_checksum(&mut packet, 1);
+        packet
+    }
+
+    fn echo(message_type: u8, sequence: u16) -> Vec<u8> {
+        let [ih, il] = SESSION.to_be_bytes();
+        let [sh, sl] = sequence.to_be_bytes();
+        icmp(message_type, 0, [ih, il, sh, sl], &[])
+    }
+
+    fn ipv4(source: [u8; 4], destination: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let [lh, ll] = (20 + payload.len() as u16).to_be_bytes();
+        let mut packet = vec![0x45, 0, lh, ll, 0, 0, 0, 0, 64, IPV4_PROTOCOL_ICMP, 0, 0];
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&destination);
+        util::set_checksum(&mut packet, 5);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn parse_all(buf: &[u8], sequence: u16) -> Vec<Parsed> {
+        let address = IpAddr::from(DESTINATION);
+        let mut remaining = buf;
+        let mut results = Vec::new();
+
+        while !remaining.is_empty() {
+            let (length, parsed) = parse_packet(remaining, address, SESSION, &|seq| seq == sequence, None);
+            assert!(length > 0 && length <= remaining.len());
+            remaining = &remaining[length..];
+            results.push(parsed);
+        }
+
+        results
+    }
+
+    // A pinger's receiving side, with probe `sequence` out. `crowded` for one on a shared socket
+    fn receiver(datagram: bool, crowded: bool, sequence: u16) -> Shared {
+        let shared = Shared {
+            address: IpAddr::from(DESTINATION), session: SESSION, datagram, crowded,
+            latest: AtomicU16::new(sequence),
+            epoch: Instant::now(),
+            in_flight: Mutex::new(HashMap::new()),
+            answered: Mutex::new(HashMap::new()),
+            given_up: Mutex::new(HashMap::new()),
+            payload: Mutex::new((0, false)),
+            stopped: AtomicBool::new(false),
+            processing: Mutex::new(ProcessingStats::default()),
+            checksum_failures: AtomicU32::new(0),
+            syscalls: AtomicU64::new(0),
+        };
+        shared.in_flight.lock().unwrap().insert(sequence, Outstanding { sent_at: Instant::now(), id: None, seed: None, hardware_sent: None });
+        shared
+    }
+
+    fn received(buf: &[u8], from: [u8; 4], to: Option<[u8; 4]>, error: Option<(u8, u8, u8, u32)>) -> Received {
+        Received { bytes: buf.len(), from: IpAddr::from(from), to: to.map(IpAddr::from), ttl: None, tos: None, error, stamp: None, hardware: None, transmit: None }
+    }
+
+    fn matched(parsed: &Parsed) -> Option<&ParsedReply> {
+        match parsed {
+            Parsed::Matched(reply) => Some(reply),
+            _ => None,
+        }
+    }
+
+    // A packet waiting to be read, and what recvmsg would say about it
+    type Queued = (Vec<u8>, Received);
+
+    // Stands in for a datagram socket: everything sent is kept, and what's queued up is what gets read
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+        incoming: Arc<Mutex<std::collections::VecDeque<Queued>>>,
+    }
+
+    impl MockTransport {
+        // The destination echoing the nth request that went out
+        fn answer(&self, nth: usize) {
+            let mut reply = self.sent.lock().unwrap()[nth].clone();
+            reply[0] = ECHO_REPLY_V4;
+            reply[2..4].copy_from_slice(&[0, 0]);
+            util::set_checksum(&mut reply, 1);
+            let received = received(&reply, DESTINATION, None, None);
+            self.incoming.lock().unwrap().push_back((reply, received));
+        }
+    }
+
+    impl IcmpTransport for MockTransport {
+        fn send(&self, buf: &[u8], _to: &SockAddr, _source: Option<IpAddr>) -> Result<usize> {
+            self.sent.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn wait_readable(&self, timeout: Duration) -> Result<bool> {
+            if !self.incoming.lock().unwrap().is_empty() {
+                return Ok(true);
+            }
+            thread::sleep(std::cmp::min(timeout, Duration::from_millis(1)));
+            Ok(false)
+        }
+
+        fn receive(&self, buf: &mut [u8], errors: bool) -> Result<Received> {
+            let mut incoming = self.incoming.lock().unwrap();
+            match incoming.front() {
+                Some((_, received)) if received.error.is_some() == errors => {
+                    let (packet, received) = incoming.pop_front().unwrap();
+                    buf[..packet.len()].copy_from_slice(&packet);
+                    Ok(received)
+                }
+                _ => Err(Error::new(ErrorKind::WouldBlock, "nothing queued")),
+            }
+        }
+    }
+
+    fn mock_pinger() -> (Pinger, MockTransport) {
+        util::set_numeric(); // No reverse lookups for the made up destination
+        let transport = MockTransport::default();
+        let icmp = IcmpSocket::on_transport(Box::new(transport.clone()), false, Some(SESSION));
+        (Pinger::on(IpAddr::from(DESTINATION), Arc::new(icmp), false).unwrap(), transport)
+    }
+
+    #[test]
+    fn echo_reply_matches() {
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 3));
+        let results = parse_all(&buf, 3);
+
+        assert_eq!(results.len(), 1);
+        let reply = matched(&results[0]).expect("reply should match");
+        assert!(reply.mtype == ReplyType::Reply);
+        assert_eq!(reply.ttl, Some(64));
+        assert_eq!(reply.size, 8);
+    }
+
+    #[test]
+    fn reply_ecn_is_read_from_the_header() {
+        let mut buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 3));
+        buf[1] = packet::ECN_CE;
+        buf[10..12].copy_from_slice(&[0, 0]);
+        util::set_checksum(&mut buf[..20], 5);
+        let results = parse_all(&buf, 3);
+
+        let reply = matched(&results[0]).expect("reply should match");
+        assert_eq!(reply.tos.map(packet::ecn_name), Some("ce"));
+    }
+
+    #[test]
+    fn other_sequence_is_ignored() {
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 4));
+        assert!(matched(&parse_all(&buf, 3)[0]).is_none());
+    }
+
+    #[test]
+    fn empty_read_is_ignored() {
+        assert!(parse_all(&[], 1).is_empty());
+    }
+
+    #[test]
+    fn truncated_packets_are_ignored() {
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 1));
+
+        for length in 1..buf.len() {
+            for parsed in parse_all(&buf[..length], 1) {
+                assert!(matched(&parsed).is_none(), "matched a packet truncated to {} bytes", length);
+            }
+        }
+    }
+
+    #[test]
+    fn parsing_never_panics_on_cut_off_packets() {
+        let ours = ipv4([192, 0, 2, 1], DESTINATION, &echo(ECHO_REQUEST_V4, 5));
+        let error = ipv4([203, 0, 113, 1], [192, 0, 2, 1], &icmp(TIMEOUT_V4, 0, [0; 4], &ours));
+        for ipv6 in [false, true] {
+            for end in 0..=error.len() {
+                let _ = packet::parse_ipv4_header(&error[..end]);
+                let _ = packet::parse_icmp(&error[..end], ipv6).map(|message| (packet::quoted_echo(message.data, ipv6),
+                    packet::quoted_destination(message.data, ipv6), packet::quoted_ports(message.data, ipv6)));
+            }
+        }
+        for end in 1..error.len() {
+            assert!(matched(&parse_all(&error[..end], 5)[0]).is_none());
+        }
+        assert_eq!(packet::parse_ipv4_header(&error[..10]).err(), Some(packet::ParseError::Truncated));
+    }
+
+    #[test]
+    fn quoted_ipv6_probes_are_found_past_extension_headers() {
+        // Hop-by-hop options, then the first fragment of our echo request
+        let mut ours = vec![0x60, 0, 0, 0, 0, 24, 0, 64];
+        ours.extend_from_slice(&[0; 32]);
+        ours.extend_from_slice(&[44, 0, 1, 4, 0, 0, 0, 0]);
+        ours.extend_from_slice(&[IPV6_NEXT_HEADER_ICMPV6, 0, 0, 1, 0, 0, 0, 7]);
+        ours.extend_from_slice(&echo(ECHO_REQUEST_V6, 5));
+
+        let quoted = packet::quoted_echo(&ours, true).unwrap();
+        assert_eq!((quoted.identifier, quoted.sequence_num), (SESSION, 5));
+
+        // A later fragment doesn't start with the ICMPv6 header
+        ours[50..52].copy_from_slice(&[0, 8]);
+        assert!(packet::quoted_echo(&ours, true).is_none());
+        assert!(packet::quoted_echo(&ours[..44], true).is_none());
+    }
+
+    #[test]
+    fn coalesced_packets_are_all_parsed() {
+        // Our own request looped back, followed by the reply in the same read
+        let mut buf = ipv4([192, 0, 2, 1], DESTINATION, &echo(ECHO_REQUEST_V4, 9));
+        buf.extend(ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 9)));
+
+        let results = parse_all(&buf, 9);
+        assert_eq!(results.len(), 2);
+        assert!(matched(&results[0]).is_none());
+        assert!(matched(&results[1]).is_some());
+    }
+
+    #[test]
+    fn corrupted_packets_are_flagged() {
+        let mut buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 2));
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+
+        assert!(matches!(parse_all(&buf, 2)[0], Parsed::Corrupted));
+    }
+
+    #[test]
+    fn time_exceeded_must_quote_our_probe() {
+        let router = [198, 51, 100, 1];
+        let ours = ipv4([192, 0, 2, 1], DESTINATION, &echo(ECHO_REQUEST_V4, 5));
+
+        let buf = ipv4(router, [192, 0, 2, 1], &icmp(TIMEOUT_V4, 0, [0; 4], &ours));
+        let results = parse_all(&buf, 5);
+        assert!(matched(&results[0]).is_some_and(|r| r.mtype == ReplyType::TimeLimitExceeded));
+
+        // Same error, but for someone else's probe
+        let mut theirs = ours.clone();
+        theirs[24] ^= 0xFF; // Flip part of the identifier
+        let buf = ipv4(router, [192, 0, 2, 1], &icmp(TIMEOUT_V4, 0, [0; 4], &theirs));
+        assert!(matched(&parse_all(&buf, 5)[0]).is_none());
+    }
+
+    #[test]
+    fn shared_socket_replies_must_come_from_the_destination() {
+        // Someone else's reply with our identifier and sequence, like on a shared datagram socket
+        let elsewhere = [192, 0, 2, 8];
+        let buf = ipv4(elsewhere, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 3));
+        let from_elsewhere = received(&buf, elsewhere, None, None);
+
+        // Alone on the socket the identifier is enough, the address doesn't matter
+        assert!(receiver(false, false, 3).process_packet(&buf, &from_elsewhere, Instant::now()).is_some());
+        assert!(receiver(false, true, 3).process_packet(&buf, &from_elsewhere, Instant::now()).is_none());
+
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &echo(ECHO_REPLY_V4, 3));
+        assert!(receiver(false, true, 3).process_packet(&buf, &received(&buf, DESTINATION, None, None), Instant::now()).is_some());
+    }
+
+    #[test]
+    fn shared_socket_errors_must_be_about_the_destination() {
+        // Our echo request back from the error queue, with a router's net unreachable about it
+        let request = echo(ECHO_REQUEST_V4, 4);
+        let router = [198, 51, 100, 1];
+        let error = Some((libc::SO_EE_ORIGIN_ICMP, UNREACHABLE_V4, 0, 0));
+
+        let about_us = received(&request, router, Some(DESTINATION), error);
+        let about_them = received(&request, router, Some([192, 0, 2, 8]), error);
+        assert!(receiver(true, true, 4).process_error(&request, &about_us, Instant::now())
+            .is_some_and(|pong| pong.mtype == ReplyType::DestinationUnreachable(0)));
+        assert!(receiver(true, true, 4).process_error(&request, &about_them, Instant::now()).is_none());
+        assert!(receiver(true, false, 4).process_error(&request, &about_them, Instant::now()).is_some());
+    }
+
+    #[test]
+    fn corrupted_payloads_are_reported() {
+        let shared = receiver(false, false, 6);
+        *shared.payload.lock().unwrap() = (24, false);
+        let sent_at = shared.in_flight.lock().unwrap()[&6].sent_at;
+
+        // What Pinger::ping sends: no id this time, then the send time, then the pattern
+        let mut payload: Vec<u8> = (0..8).collect();
+        payload.extend_from_slice(&(sent_at.duration_since(shared.epoch).as_nanos() as u64).to_be_bytes());
+        payload.extend(16..24);
+        let [ih, il] = SESSION.to_be_bytes();
+        let reply = |payload: &[u8]| ipv4(DESTINATION, [192, 0, 2, 1], &icmp(ECHO_REPLY_V4, 0, [ih, il, 0, 6], payload));
+
+        let buf = reply(&payload);
+        let pong = shared.process_packet(&buf, &received(&buf, DESTINATION, None, None), Instant::now()).expect("reply should match");
+        assert!(pong.corruption.is_none());
+
+        payload[20] ^= 0x80;
+        payload[22] ^= 0x01;
+        shared.in_flight.lock().unwrap().insert(6, Outstanding { sent_at, id: None, seed: None, hardware_sent: None });
+        let buf = reply(&payload);
+        let pong = shared.process_packet(&buf, &received(&buf, DESTINATION, None, None), Instant::now()).expect("reply should match");
+        let corruption = pong.corruption.expect("corruption should be found");
+        assert_eq!((corruption.offset, corruption.expected, corruption.got, corruption.bytes), (20, 0x14, 0x94, 2));
+    }
+
+    #[test]
+    fn random_payloads_are_checked_against_their_seed() {
+        let shared = receiver(false, false, 6);
+        *shared.payload.lock().unwrap() = (12, false);
+        let sent_at = shared.in_flight.lock().unwrap()[&6].sent_at;
+        let probe = Outstanding { sent_at, id: None, seed: Some(0x1234_5678), hardware_sent: None };
+
+        let mut payload: Vec<u8> = (0..12).map(|at| payload_byte(probe.seed, at)).collect();
+        assert_ne!(payload, (0..12).collect::<Vec<u8>>());
+        assert!(shared.corruption(&payload, &probe).is_none());
+
+        // The pattern isn't what was sent this time
+        assert!(shared.corruption(&(0..12).collect::<Vec<u8>>(), &probe).is_some());
+        payload[9] ^= 0x10;
+        assert_eq!(shared.corruption(&payload, &probe).map(|corruption| (corruption.offset, corruption.bytes)), Some((9, 1)));
+    }
+
+    #[test]
+    fn hardware_stamps_time_the_reply_when_both_ends_have_one() {
+        let shared = receiver(false, false, 6);
+        shared.in_flight.lock().unwrap().get_mut(&6).unwrap().hardware_sent = Some(Duration::new(1000, 250_000));
+        let [ih, il] = SESSION.to_be_bytes();
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &icmp(ECHO_REPLY_V4, 0, [ih, il, 0, 6], &[]));
+
+        let mut stamped = received(&buf, DESTINATION, None, None);
+        stamped.hardware = Some(Duration::new(1000, 262_345));
+        let pong = shared.process_packet(&buf, &stamped, Instant::now()).expect("reply should match");
+        assert_eq!(pong.rtt, Duration::from_nanos(12_345));
+        assert!(pong.hardware.is_some());
+
+        // The card didn't stamp this one coming in, it's timed by us
+        shared.in_flight.lock().unwrap().insert(7, Outstanding { sent_at: Instant::now(), id: None, seed: None, hardware_sent: Some(Duration::new(1000, 0)) });
+        let buf = ipv4(DESTINATION, [192, 0, 2, 1], &icmp(ECHO_REPLY_V4, 0, [ih, il, 0, 7], &[]));
+        let pong = shared.process_packet(&buf, &received(&buf, DESTINATION, None, None), Instant::now()).expect("reply should match");
+        assert!(pong.hardware.is_none());
+    }
+
+    #[test]
+    fn pinger_gets_its_answer_through_the_transport() {
+        let (mut pinger, transport) = mock_pinger();
+        let sequence = pinger.ping().unwrap();
+        transport.answer(0);
+
+        let pong = pinger.receive_pong(sequence, Duration::from_secs(5)).expect("the reply should come through");
+        assert_eq!(pong.sequence, sequence);
+        assert!(pong.mtype == ReplyType::Reply);
+        assert_eq!(pong.address, IpAddr::from(DESTINATION));
+    }
+
+    #[test]
+    fn pipelined_replies_go_to_their_own_probes() {
+        let (mut pinger, transport) = mock_pinger();
+        let first = pinger.ping().unwrap();
+        let second = pinger.ping().unwrap();
+        transport.answer(1);
+        transport.answer(0);
+
+        assert_eq!(pinger.receive_any(Duration::from_secs(5)).unwrap().sequence, second);
+        assert_eq!(pinger.receive_any(Duration::from_secs(5)).unwrap().sequence, first);
+    }
+
+    #[test]
+    fn second_answers_are_duplicates() {
+        let (mut pinger, transport) = mock_pinger();
+        let sequence = pinger.ping().unwrap();
+        transport.answer(0);
+        transport.answer(0);
+
+        let first = pinger.receive_any(Duration::from_secs(5)).unwrap();
+        let second = pinger.receive_any(Duration::from_secs(5)).unwrap();
+        assert_eq!((first.sequence, first.duplicate), (sequence, false));
+        assert_eq!((second.sequence, second.duplicate), (sequence, true));
+
+        // The sequence number coming round again is a new probe, not a duplicate
+        pinger.sequence = sequence.wrapping_sub(1);
+        pinger.ping().unwrap();
+        transport.answer(1);
+        assert!(!pinger.receive_any(Duration::from_secs(5)).unwrap().duplicate);
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let (mut pinger, transport) = mock_pinger();
+        pinger.sequence = u16::MAX - 1;
+        let last = pinger.ping().unwrap();
+        let wrapped = pinger.ping().unwrap();
+        assert_eq!((last, wrapped), (u16::MAX, 0));
+
+        transport.answer(1);
+        transport.answer(0);
+        assert_eq!(pinger.receive_any(Duration::from_secs(5)).unwrap().sequence, wrapped);
+        assert_eq!(pinger.receive_any(Duration::from_secs(5)).unwrap().sequence, last);
+    }
+
+    #[test]
+    fn replies_after_the_timeout_come_back_late() {
+        let (mut pinger, transport) = mock_pinger();
+        let sequence = pinger.ping().unwrap();
+
+        let timed_out = pinger.receive_pong(sequence, Duration::from_millis(20)).err().map(|e| e.kind());
+        assert_eq!(timed_out, Some(ErrorKind::WouldBlock));
+
+        // Given up on, so the answer turning up now is late, and waiting on the next probe skips it
+        transport.answer(0);
+        transport.answer(0);
+        let late = pinger.receive_any(Duration::from_secs(5)).unwrap();
+        assert_eq!((late.sequence, late.late, late.duplicate), (sequence, true, false));
+        assert!(pinger.receive_any(Duration::from_secs(5)).unwrap().duplicate);
+
+        let next = pinger.ping().unwrap();
+        transport.answer(0);
+        assert!(pinger.receive_pong(next, Duration::from_millis(50)).is_err());
     }
 }
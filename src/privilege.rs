@@ -0,0 +1,176 @@
+//! Explaining what to do when we're not allowed to open the sockets we need, rather
+//! than leaving people with a panic and an EPERM. And when we are privileged, letting
+//! go of it as soon as the sockets are open.
+
+use socket2::{Socket, Domain, Protocol};
+
+use std::env;
+use std::fs;
+use std::io::{Result, Error, ErrorKind};
+use std::net::IpAddr;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::color::*;
+
+/// Unwraps the result of setting up a probe. Failing for lack of privileges exits with
+/// an explanation of the ways around it, anything else panics with `what` like before.
+pub fn unwrap_socket<T>(result: Result<T>, address: IpAddr, what: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(ref e) if e.kind() == ErrorKind::PermissionDenied => {
+            eprintln!("{} {}: {}", "Error:".red().bold(), what, e);
+            eprintln!("{}", explain(address));
+            process::exit(1);
+        }
+        Err(e) => panic!("{}: {:?}", what, e),
+    }
+}
+
+/// Which sockets this user can actually open, and how to get the missing ones
+pub fn explain(address: IpAddr) -> String {
+    let ipv6 = address.is_ipv6();
+    let (domain, protocol) = if ipv6 { (Domain::ipv6(), Protocol::icmpv6()) } else { (Domain::ipv4(), Protocol::icmpv4()) };
+
+    let raw = can_open(domain, socket2::Type::raw(), protocol);
+    let datagram = can_open(domain, socket2::Type::dgram(), protocol);
+    let packet = can_open(Domain::from(libc::AF_PACKET), socket2::Type::raw(), Protocol::from((libc::ETH_P_ARP as u16).to_be() as i32));
+
+    let range = fs::read_to_string("/proc/sys/net/ipv4/ping_group_range").map(|range| range.split_whitespace().collect::<Vec<_>>().join(" "));
+    let (gid, groups) = groups();
+
+    let mut lines = vec![
+        "Raw sockets need root or CAP_NET_RAW. Unprivileged ICMP datagram sockets work for anyone in a group".to_string(),
+        "within the net.ipv4.ping_group_range sysctl (it covers IPv6 too).".to_string(),
+        String::new(),
+        format!("  {:<26}{}", format!("raw ICMP{} sockets:", if ipv6 { "v6" } else { "" }), yes_no(&raw)),
+        format!("  {:<26}{}", format!("ICMP{} datagram sockets:", if ipv6 { "v6" } else { "" }), yes_no(&datagram)),
+        format!("  {:<26}{}", "packet sockets (--arp):", yes_no(&packet)),
+    ];
+
+    match &range {
+        Ok(range) => lines.push(format!("  ping_group_range is \"{}\", your groups are {}", range,
+            groups.iter().map(|group| group.to_string()).collect::<Vec<_>>().join(", "))),
+        Err(e) => lines.push(format!("  couldn't read ping_group_range: {}", e)),
+    }
+
+    lines.push(String::new());
+    lines.push("To fix it, one of:".to_string());
+    let exe = std::env::current_exe().map(|exe| exe.display().to_string()).unwrap_or_else(|_| "ring".to_string());
+    lines.push(format!("  sudo setcap cap_net_raw+ep {}", exe));
+    if datagram.is_err() {
+        lines.push(format!("  sudo sysctl -w net.ipv4.ping_group_range=\"{} {}\"   (to persist it, add it to /etc/sysctl.d/)", gid, gid));
+    }
+    lines.push("  or use a backend that needs nothing special: --tcp PORT, or --system-ping".to_string());
+
+    lines.join("\n")
+}
+
+fn can_open(domain: Domain, stype: socket2::Type, protocol: Protocol) -> Result<()> {
+    Socket::new(domain, stype.cloexec(), Some(protocol)).map(drop)
+}
+
+fn yes_no(result: &Result<()>) -> ColoredString {
+    match result {
+        Ok(()) => "yes".green(),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => "no".red(),
+        Err(e) => format!("no ({})", e).red(),
+    }
+}
+
+// Our primary group, and every group we're in
+fn groups() -> (libc::gid_t, Vec<libc::gid_t>) {
+    let gid = unsafe { libc::getgid() };
+    let mut groups = vec![gid];
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count > 0 {
+        let mut supplementary = vec![0; count as usize];
+        let count = unsafe { libc::getgroups(count, supplementary.as_mut_ptr()) };
+        supplementary.truncate(std::cmp::max(count, 0) as usize);
+        groups.extend(supplementary.into_iter().filter(|&group| group != gid));
+    }
+
+    (gid, groups)
+}
+
+// Anything shorter floods the destination, only root gets to go that low (same as ping)
+const MIN_INTERVAL: Duration = Duration::from_millis(2);
+
+// Whether whoever ran us was root, remembered for after dropping to them
+static RUN_BY_ROOT: AtomicBool = AtomicBool::new(false);
+
+/// Exits if `interval` is short enough to flood someone, unless root (logged in as it, or
+/// through sudo, not just a setuid binary) asked for it or `force` says it's on purpose
+pub fn check_interval(interval: Duration, force: bool) {
+    let root = RUN_BY_ROOT.load(Ordering::SeqCst) || unsafe { libc::getuid() } == 0;
+    if interval < MIN_INTERVAL && !force && !root {
+        eprintln!("{} intervals under {} can flood the destination, only root can go that low (or use --force if it's yours)",
+            "Error:".red().bold(), humantime::format_duration(MIN_INTERVAL));
+        process::exit(1);
+    }
+}
+
+/// Once the sockets are open root has done its job, so become whoever actually ran us:
+/// the real user of a setuid binary, or the one behind sudo. Everything after this
+/// (DNS, files, sinks) runs as them. Does nothing if there's nobody to become.
+/// `memory_locked` is for --realtime having locked everything, now and in the future
+pub fn drop_to_invoker(memory_locked: bool) -> Result<()> {
+    RUN_BY_ROOT.store(unsafe { libc::getuid() } == 0, Ordering::SeqCst);
+    let (uid, gid) = match invoker() {
+        Some(invoker) => invoker,
+        None => return Ok(()),
+    };
+
+    unsafe {
+        // Groups first, they can't be changed anymore once we aren't root
+        if libc::geteuid() == 0 {
+            let user = libc::getpwuid(uid);
+            let failed = if user.is_null() {
+                libc::setgroups(1, &gid) != 0
+            } else {
+                libc::initgroups((*user).pw_name, gid) != 0
+            };
+            if failed {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        // Memory locked from now on counts against their limit, which not even new thread
+        // stacks fit in by default. Lift it while we can, or settle for what's locked so far
+        if memory_locked {
+            let unlimited = libc::rlimit { rlim_cur: libc::RLIM_INFINITY, rlim_max: libc::RLIM_INFINITY };
+            if libc::setrlimit(libc::RLIMIT_MEMLOCK, &unlimited) != 0 {
+                libc::mlockall(libc::MCL_CURRENT);
+            }
+        }
+
+        if libc::setresgid(gid, gid, gid) != 0 || libc::setresuid(uid, uid, uid) != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // Make sure there's no way back
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(Error::other("could still get root back after dropping it"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Who to drop to, if we're privileged and know who's really behind it
+fn invoker() -> Option<(libc::uid_t, libc::gid_t)> {
+    let (uid, euid) = unsafe { (libc::getuid(), libc::geteuid()) };
+    if uid != euid {
+        return Some((uid, unsafe { libc::getgid() })); // Setuid
+    }
+    if euid != 0 {
+        return None;
+    }
+
+    // Logged in as root is just root, there's nobody else to be
+    let uid: libc::uid_t = env::var("SUDO_UID").ok()?.parse().ok()?;
+    let gid: libc::gid_t = env::var("SUDO_GID").ok()?.parse().ok()?;
+    if uid == 0 { None } else { Some((uid, gid)) }
+}
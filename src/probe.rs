@@ -0,0 +1,74 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::ping::{Pinger, PongResult, ProcessingStats};
+use crate::trigger::Waker;
+
+/// A way of measuring round trips to a destination. ICMP echo is the usual one, the
+/// others exist for networks that filter it. Sessions only talk to this, so every
+/// backend gets the same statistics and output.
+pub trait Probe: Send {
+    /// Send a probe, returns the sequence number used
+    fn ping(&mut self) -> Result<u16>;
+
+    /// Wait for the answer to probe `sequence_num`. Timing out is `ErrorKind::WouldBlock`.
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult>;
+
+    /// Tag the next probe with its id, for backends that have somewhere to put it
+    fn set_probe_id(&mut self, _id: u64) {}
+
+    /// Send the next probe from this local address, for backends that can pick one
+    fn set_source(&mut self, _source: IpAddr) {}
+
+    /// Put this many data bytes in the next probe, for backends that have data to size
+    fn set_size(&mut self, _size: usize) {}
+
+    /// Whether more probes can go out before the earlier ones are answered. Backends
+    /// that do all their work while waiting (a whole TCP connect, ...) can't.
+    fn pipelined(&self) -> bool { false }
+
+    /// Wait for the answer to whichever outstanding probe comes back first, only for
+    /// pipelined backends. Timing out is `ErrorKind::WouldBlock`.
+    fn receive_any(&self, _timeout: Duration) -> Result<PongResult> {
+        Err(Error::new(ErrorKind::Unsupported, "probes can only be waited on one at a time"))
+    }
+
+    /// Give up on a probe, an answer arriving after this is ignored
+    fn forget(&self, _sequence_num: u16) {}
+
+    /// For --trigger, a way to cut `receive_any` short when it's time to send
+    fn waker(&mut self) -> Option<Waker> { None }
+
+    /// Short name of the backend, goes in the summary so results from different ones can be told apart
+    fn backend(&self) -> &'static str;
+
+    /// Extra detail for the banner, like the port being probed
+    fn describe(&self) -> Option<String> { None }
+
+    /// Size the replies should be, if the backend can tell
+    fn request_size(&self) -> Option<usize> { None }
+
+    fn checksum_failures(&self) -> u32 { 0 }
+    fn kernel_drops(&self) -> Result<u32> { Ok(0) }
+    fn processing_stats(&self) -> ProcessingStats { ProcessingStats::default() }
+    fn syscalls(&self) -> u64 { 0 }
+}
+
+impl Probe for Pinger {
+    fn ping(&mut self) -> Result<u16> { Pinger::ping(self) }
+    fn set_probe_id(&mut self, id: u64) { Pinger::set_probe_id(self, id) }
+    fn set_source(&mut self, source: IpAddr) { Pinger::set_source(self, source) }
+    fn set_size(&mut self, size: usize) { Pinger::set_payload_size(self, size) }
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> { Pinger::receive_pong(self, sequence_num, timeout) }
+    fn pipelined(&self) -> bool { true }
+    fn receive_any(&self, timeout: Duration) -> Result<PongResult> { Pinger::receive_any(self, timeout) }
+    fn forget(&self, sequence_num: u16) { Pinger::forget(self, sequence_num) }
+    fn waker(&mut self) -> Option<Waker> { Pinger::waker(self).ok() }
+    fn backend(&self) -> &'static str { "icmp" }
+    fn request_size(&self) -> Option<usize> { Some(Pinger::request_size(self)) }
+    fn checksum_failures(&self) -> u32 { Pinger::checksum_failures(self) }
+    fn kernel_drops(&self) -> Result<u32> { Pinger::kernel_drops(self) }
+    fn processing_stats(&self) -> ProcessingStats { Pinger::processing_stats(self) }
+    fn syscalls(&self) -> u64 { Pinger::syscalls(self) }
+}
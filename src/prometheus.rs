@@ -0,0 +1,147 @@
+//! An endpoint for Prometheus to scrape (--prometheus), so a long running ring can be a
+//! blackbox probe. It's a sink like any other, it just has to keep answering scrapes
+//! for as long as we run, which is a lot to ask of a --sink-exec program.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Result, Read, Write, BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::event::{Event, Status};
+use crate::sink::Sink;
+
+// Upper bounds of the rtt histogram buckets, in seconds. From a LAN to a bad satellite link
+const BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Default)]
+struct Target {
+    sent: u64,
+    lost: u64,
+    buckets: [u64; BUCKETS.len()], // Not cumulative, that's worked out when scraped
+    rtt_sum: f64,
+    rtt_count: u64,
+}
+
+type Targets = Arc<Mutex<BTreeMap<String, Target>>>;
+
+pub struct PrometheusSink {
+    targets: Targets,
+}
+
+impl PrometheusSink {
+    /// Starts serving on `address`, ex: "0.0.0.0:9101", or just ":9101" for every interface
+    pub fn new(address: &str) -> Result<Self> {
+        let address = if address.starts_with(':') { format!("0.0.0.0{}", address) } else { address.to_string() };
+        let listener = TcpListener::bind(&address)?;
+        let targets = Targets::default();
+
+        let shared = targets.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let served = stream.and_then(|stream| serve(stream, &shared));
+                if let Err(e) = served {
+                    log::warn!("prometheus scrape failed: {}", e);
+                }
+            }
+        });
+
+        Ok(PrometheusSink { targets })
+    }
+}
+
+impl Sink for PrometheusSink {
+    fn send(&self, event: Event, _line: &str) {
+        let probe = match event {
+            Event::Probe(probe) => probe,
+            _ => return,
+        };
+
+        if !probe.counts_towards_totals() {
+            return;
+        }
+
+        let mut targets = self.targets.lock().unwrap();
+        let target = targets.entry(probe.host.clone()).or_default();
+        target.sent += 1;
+
+        match (probe.status, probe.rtt) {
+            (Status::Reply, Some(rtt)) | (Status::Reset, Some(rtt)) => {
+                let seconds = rtt.as_secs_f64();
+                if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+                    target.buckets[bucket] += 1;
+                }
+                target.rtt_sum += seconds;
+                target.rtt_count += 1;
+            }
+            _ => target.lost += 1,
+        }
+    }
+}
+
+// Answers one scrape, anything but the metrics is a 404
+fn serve(mut stream: TcpStream, targets: &Targets) -> Result<()> {
+    // Don't let a client that never finishes its request hold up everyone else's scrapes
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new((&stream).take(8192));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Nothing in the headers matters, but they should be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let response = if path == "/metrics" || path.starts_with("/metrics?") {
+        let body = render(&targets.lock().unwrap());
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    } else {
+        let body = "Try /metrics\n";
+        format!("HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    };
+
+    stream.write_all(response.as_bytes())
+}
+
+// The text exposition format
+fn render(targets: &BTreeMap<String, Target>) -> String {
+    let mut body = String::new();
+
+    writeln!(body, "# HELP ring_packets_sent_total Probes sent to the target, counted once answered or given up on.").unwrap();
+    writeln!(body, "# TYPE ring_packets_sent_total counter").unwrap();
+    for (host, target) in targets {
+        writeln!(body, "ring_packets_sent_total{{target=\"{}\"}} {}", escape(host), target.sent).unwrap();
+    }
+
+    writeln!(body, "# HELP ring_packets_lost_total Probes that never got an answer.").unwrap();
+    writeln!(body, "# TYPE ring_packets_lost_total counter").unwrap();
+    for (host, target) in targets {
+        writeln!(body, "ring_packets_lost_total{{target=\"{}\"}} {}", escape(host), target.lost).unwrap();
+    }
+
+    writeln!(body, "# HELP ring_rtt_seconds Round trip times of answered probes.").unwrap();
+    writeln!(body, "# TYPE ring_rtt_seconds histogram").unwrap();
+    for (host, target) in targets {
+        let host = escape(host);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&target.buckets) {
+            cumulative += count;
+            writeln!(body, "ring_rtt_seconds_bucket{{target=\"{}\",le=\"{}\"}} {}", host, bound, cumulative).unwrap();
+        }
+        writeln!(body, "ring_rtt_seconds_bucket{{target=\"{}\",le=\"+Inf\"}} {}", host, target.rtt_count).unwrap();
+        writeln!(body, "ring_rtt_seconds_sum{{target=\"{}\"}} {}", host, target.rtt_sum).unwrap();
+        writeln!(body, "ring_rtt_seconds_count{{target=\"{}\"}} {}", host, target.rtt_count).unwrap();
+    }
+
+    body
+}
+
+// Label values are quoted, so backslashes, quotes and newlines need escaping
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
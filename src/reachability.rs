@@ -0,0 +1,132 @@
+//! Whether a destination's up or down, going by how many probes in a row were (or
+//! weren't) answered. Going down starts an outage and coming back up ends it, the
+//! outages are what --outages reports and what --notify pops up about.
+
+use std::time::SystemTime;
+
+pub struct Thresholds {
+    pub down_after: u32, // Probes lost in a row before it's down
+    pub up_after: u32,   // Probes answered in a row before it's back up
+}
+
+/// From the first probe that went unanswered to the first one of those that brought it back
+pub struct Outage {
+    pub start: SystemTime,
+    pub end: Option<SystemTime>, // None while it's still going
+    pub lost: u32,
+}
+
+/// It went down or came back up
+pub enum Change {
+    Down { since: SystemTime, lost: u32 },
+    Up(usize), // Which of the outages just ended
+}
+
+/// One destination's state, and every outage it's had
+#[derive(Default)]
+pub struct State {
+    pub outages: Vec<Outage>,
+    down: bool,
+    streak: u32, // Probes in a row that are going the other way
+    streak_start: Option<SystemTime>,
+}
+
+impl State {
+    /// Count a probe sent at `sent`, answered or not
+    pub fn record(&mut self, thresholds: &Thresholds, sent: SystemTime, answered: bool) -> Option<Change> {
+        if answered != self.down {
+            // Carrying on as it was, whatever was building up for a change didn't last
+            self.streak = 0;
+            if self.down {
+                self.outages.last_mut().unwrap().lost += 1;
+            }
+            return None;
+        }
+
+        if self.streak == 0 {
+            self.streak_start = Some(sent);
+        }
+        self.streak += 1;
+        let start = self.streak_start.unwrap();
+
+        if !self.down && self.streak >= thresholds.down_after {
+            self.down = true;
+            self.outages.push(Outage { start, end: None, lost: self.streak });
+            self.streak = 0;
+            Some(Change::Down { since: start, lost: thresholds.down_after })
+        } else if self.down && self.streak >= thresholds.up_after {
+            self.down = false;
+            self.outages.last_mut().unwrap().end = Some(start);
+            self.streak = 0;
+            Some(Change::Up(self.outages.len() - 1))
+        } else {
+            None
+        }
+    }
+}
+
+impl Outage {
+    /// How long it lasted, or has so far
+    pub fn seconds(&self) -> f64 {
+        self.end.unwrap_or_else(SystemTime::now).duration_since(self.start).unwrap_or_default().as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const THRESHOLDS: Thresholds = Thresholds { down_after: 3, up_after: 2 };
+
+    fn at(second: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(second)
+    }
+
+    // Probes a second apart from `first`, true for answered ones. What changed at each
+    fn run(state: &mut State, first: u64, probes: &[bool]) -> Vec<Option<Change>> {
+        probes.iter().enumerate().map(|(i, &answered)| state.record(&THRESHOLDS, at(first + i as u64), answered)).collect()
+    }
+
+    #[test]
+    fn down_exactly_at_down_after() {
+        let mut state = State::default();
+        let changes = run(&mut state, 0, &[true, false, false, false]);
+        assert!(changes[..3].iter().all(Option::is_none));
+        match &changes[3] {
+            Some(Change::Down { since, lost }) => assert_eq!((*since, *lost), (at(1), 3)),
+            _ => panic!("not down after 3 lost"),
+        }
+        assert_eq!(state.outages.len(), 1);
+        assert_eq!((state.outages[0].start, state.outages[0].end), (at(1), None));
+    }
+
+    #[test]
+    fn a_broken_streak_starts_over() {
+        let mut state = State::default();
+        assert!(run(&mut state, 0, &[false, false, true, false, false]).iter().all(Option::is_none));
+        assert!(state.outages.is_empty());
+        // The third in a row from the new streak's start
+        assert!(matches!(state.record(&THRESHOLDS, at(5), false), Some(Change::Down { since, .. }) if since == at(3)));
+    }
+
+    #[test]
+    fn every_probe_lost_while_down_counts_towards_the_outage() {
+        let mut state = State::default();
+        // Down, one answer that doesn't bring it back, then more lost
+        run(&mut state, 0, &[false, false, false, false, true, false, false]);
+        assert_eq!(state.outages[0].lost, 6);
+        assert_eq!(state.outages[0].end, None);
+    }
+
+    #[test]
+    fn outages_end_where_the_recovering_streak_started() {
+        let mut state = State::default();
+        let changes = run(&mut state, 0, &[false, false, false, false, true, true, true]);
+        assert!(matches!(changes[5], Some(Change::Up(0))));
+        assert!(changes[6].is_none());
+        assert_eq!(state.outages[0].end, Some(at(4)));
+        assert_eq!(state.outages[0].seconds(), 4.0);
+        assert_eq!(state.outages[0].lost, 4);
+    }
+}
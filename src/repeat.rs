@@ -0,0 +1,50 @@
+//! Collapsing runs of the same message, like syslogd's "last message repeated N times".
+//! A network that's down says the same thing every second for as long as it's down, and
+//! an hour of that shouldn't bury everything else in the output. The count comes out
+//! every so often while it's still going, and when something else finally happens.
+
+use std::time::{Duration, Instant};
+
+// How often a run that's still going gets its count reported
+const REPORT_EVERY: Duration = Duration::from_secs(60);
+
+/// Tracks the latest message, `T` is whatever identifies it (and is needed to report it)
+pub struct Repeats<T> {
+    last: Option<T>,
+    count: u32, // Repeats of `last` not reported yet
+    since: Instant,
+}
+
+impl<T: PartialEq + Clone> Repeats<T> {
+    pub fn new() -> Self {
+        Repeats { last: None, count: 0, since: Instant::now() }
+    }
+
+    /// Whether `message` should be shown, along with any repeats due to be reported first.
+    /// None is for lines that never collapse, they still end a run
+    pub fn check(&mut self, message: Option<T>) -> (Option<(T, u32)>, bool) {
+        if message.is_some() && message == self.last {
+            self.count += 1;
+            if self.since.elapsed() < REPORT_EVERY {
+                return (None, false);
+            }
+            self.since = Instant::now();
+            let count = std::mem::replace(&mut self.count, 0);
+            return (self.last.clone().map(|last| (last, count)), false);
+        }
+
+        let pending = self.flush();
+        self.last = message;
+        self.since = Instant::now();
+        (pending, true)
+    }
+
+    /// Repeats that haven't been reported yet, for when there's nothing more coming
+    pub fn flush(&mut self) -> Option<(T, u32)> {
+        let count = std::mem::replace(&mut self.count, 0);
+        match &self.last {
+            Some(last) if count > 0 => Some((last.clone(), count)),
+            _ => None,
+        }
+    }
+}
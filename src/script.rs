@@ -0,0 +1,113 @@
+//! User supplied expressions over events (--filter and --derive), so there's no
+//! need for a new flag every time someone wants to see a slightly different subset.
+//! Expressions see every field of the event as a variable, fields that aren't set on
+//! an event are `()`, and comparing those to anything is just false. Builds without
+//! the `scripting` feature leave the engine out, and refuse the flags.
+
+#[cfg(feature = "scripting")]
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde_json::Value;
+
+#[cfg(feature = "scripting")]
+pub struct Script {
+    engine: Engine,
+    filter: Option<AST>,
+    derive: Vec<(String, AST)>,
+}
+
+// Optional event fields, so a filter for one kind of event doesn't error on another
+#[cfg(feature = "scripting")]
+const FIELDS: &[&str] = &[
+    "kind", "host", "destination", "seq", "id", "status", "from", "hostname", "rtt_ms", "ttl", "size", "size_mismatch", "corruption",
+    "route", "gateway", "phases", "detail", "sent", "lost", "round", "received", "loss", "rtt_min_ms", "rtt_avg_ms",
+    "rtt_max_ms", "backend", "size_mismatches", "corrupted_payloads", "checksum_failures", "kernel_drops", "window", "compliant", "breaches", "origin",
+    "anomaly", "clock_jump", "jump", "seconds", "excluded", "rtt_median_ms", "baseline_rtt_ms", "baseline_loss", "anomalies",
+    "segment", "segments", "after", "mac", "reason", "lost_reasons", "state", "gateway_lossy", "transport", "interim",
+    "burst", "lost_at", "rtt_spread_ms", "ecn", "rollup", "start", "end", "outages", "duplicates", "sizes", "timestamps",
+];
+
+#[cfg(feature = "scripting")]
+impl Script {
+    /// `derive` takes `name=expression` pairs, each one can use the ones before it
+    pub fn new(filter: Option<&str>, derive: &[&str]) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        // Only expressions, nothing that can loop forever inside the receive loop
+        engine.set_max_operations(10_000);
+
+        let filter = filter.map(|filter| engine.compile_expression(filter)
+            .map_err(|e| format!("invalid filter: {}", e))).transpose()?;
+
+        let derive = derive.iter().map(|derive| {
+            let (name, expression) = derive.split_once('=').ok_or_else(|| format!("invalid derive {:?}, expected name=expression", derive))?;
+            let ast = engine.compile_expression(expression).map_err(|e| format!("invalid derive for {}: {}", name, e))?;
+            Ok((name.trim().to_string(), ast))
+        }).collect::<Result<_, String>>()?;
+
+        Ok(Script { engine, filter, derive })
+    }
+
+    /// Whether the event passes the filter. An expression that fails counts as false,
+    /// a typo shouldn't take down a long running session
+    pub fn keep(&self, event: &Value) -> bool {
+        match &self.filter {
+            Some(filter) => self.engine.eval_ast_with_scope::<bool>(&mut scope(event), filter).unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Runs the filter, then adds the derived fields to the event. False if it was filtered out
+    pub fn apply(&self, event: &mut Value) -> bool {
+        if !self.keep(event) {
+            return false;
+        }
+
+        let mut scope = scope(event);
+        for (name, ast) in &self.derive {
+            let value = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast).unwrap_or(Dynamic::UNIT);
+            let json = rhai::serde::from_dynamic::<Value>(&value).unwrap_or(Value::Null);
+
+            scope.set_or_push(name.as_str(), value);
+            if let Value::Object(fields) = event {
+                fields.insert(name.clone(), json);
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn scope(event: &Value) -> Scope<'static> {
+    let mut scope = Scope::new();
+    for field in FIELDS {
+        scope.push_dynamic(*field, Dynamic::UNIT);
+    }
+
+    if let Value::Object(fields) = event {
+        for (name, value) in fields {
+            // `type` is a keyword-ish name in rhai, kind is close enough
+            let name = if name == "type" { "kind" } else { name.as_str() };
+            scope.set_or_push(name, rhai::serde::to_dynamic(value).unwrap_or(Dynamic::UNIT));
+        }
+    }
+
+    scope
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct Script;
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn new(_filter: Option<&str>, _derive: &[&str]) -> Result<Self, String> {
+        Err("this ring was built without the `scripting` feature".to_string())
+    }
+
+    pub fn keep(&self, _event: &Value) -> bool {
+        true
+    }
+
+    pub fn apply(&self, _event: &mut Value) -> bool {
+        true
+    }
+}
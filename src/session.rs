@@ -0,0 +1,1503 @@
+use std::thread;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::ping::{self, PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::output::{self, Locked, Output, Format};
+use crate::event::{self, AlertEvent, AnomalyEvent, EcnSummary, Reason, ClockEvent, Event, GatewayLosses, ProbeEvent, BurstEvent, OutageSummary, ReachabilityEvent, RollupEvent, RoundEvent, SegmentSummary, SizeSummary, SlaEvent, SourceSummary, SummaryEvent, Status};
+use crate::origin::Origin;
+use crate::script::Script;
+use crate::sink::Sink;
+use crate::util;
+use crate::locale;
+use crate::packet;
+use crate::stats::{self, Breach, ProbeGroup};
+use crate::sla::{self, Sla};
+use crate::baseline::{Baseline, PeriodAnomaly};
+use crate::alert::{self, Alert};
+use crate::clock::{self, Jump};
+use crate::gateway;
+use crate::repeat::Repeats;
+use crate::http;
+use crate::trigger;
+use crate::notify;
+use crate::reachability::{self, Thresholds};
+use crate::color::*;
+
+// With --backoff, how many probes in a row go unanswered before the interval starts stretching
+const BACKOFF_AFTER: u32 = 5;
+
+// With --trigger, as good as forever: how long to wait when there's nothing to send
+const UNSCHEDULED: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+// Sequence numbers are 16 bits and wrap around, then they're only told apart by what's still out
+const SEQUENCE_NUMBERS: usize = 1 << 16;
+
+/// Settings shared by every destination being pinged
+pub struct Config {
+    pub timeout: Duration,
+    pub grace: Duration, // How long after the timeout a reply still counts, leniently
+    pub interval: Duration,
+    pub cpu: Option<usize>,
+    pub round: Option<u32>, // Print one aggregated line per this many probes
+    pub format: Format,
+    pub origin: Option<Origin>,
+    pub script: Option<Script>, // --filter and --derive
+    pub sinks: Vec<Box<dyn Sink>>,
+    pub sla: Option<Sla>,
+    pub alert: Option<Alert>,
+    pub reset_on_resume: bool, // Start windowed stats over after a suspend or stall
+    pub collapse_repeats: bool, // Print runs of the same error once, with a count
+    pub gateways: Vec<gateway::Watch>, // With --with-gateway, one for each family
+    pub trigger: bool, // Probes go out on --trigger instead of every interval
+    pub backoff: Option<Duration>, // With --backoff, how far the interval can stretch while nothing answers
+    pub burst: Option<u32>, // Probes sent back to back every interval
+    pub ecn: Option<u8>, // The ECN codepoint going out, with --ecn
+    pub notify: bool, // Desktop notifications when a destination goes down, or comes back
+    pub outages: bool, // Report each time a destination goes down or comes back, and the outages in the summary
+    pub reachability: Option<Thresholds>, // When it counts as down or up, for --outages and --notify
+    pub summary_interval: Option<Duration>, // A one line rollup this often, of the probes since the last
+    pub rtt_colors: Option<RttColors>,
+}
+
+/// --rtt-colors: time= is green under `slow`, yellow from there and red from `very_slow` up
+#[derive(Clone, Copy)]
+pub struct RttColors {
+    pub slow: Duration,
+    pub very_slow: Duration,
+}
+
+impl RttColors {
+    /// `slow:very_slow`, ex: 50ms:200ms
+    pub fn parse(thresholds: &str) -> Option<RttColors> {
+        let (slow, very_slow) = thresholds.split_once(':')?;
+        let (slow, very_slow) = (humantime::parse_duration(slow).ok()?, humantime::parse_duration(very_slow).ok()?);
+        if slow <= very_slow { Some(RttColors { slow, very_slow }) } else { None }
+    }
+
+    fn paint(&self, rtt: Duration, text: &str) -> ColoredString {
+        if rtt >= self.very_slow {
+            text.red().bold()
+        } else if rtt >= self.slow {
+            text.yellow().bold()
+        } else {
+            text.green().bold()
+        }
+    }
+}
+
+impl Config {
+    /// The JSON line for an event, or None if the filter drops it
+    pub fn json(&self, event: Event) -> Option<String> {
+        match &self.script {
+            Some(script) => {
+                let mut value = event.to_value(self.origin.as_ref());
+                if script.apply(&mut value) { Some(value.to_string()) } else { None }
+            }
+            None => Some(event.to_json(self.origin.as_ref())),
+        }
+    }
+
+    /// Whether the filter lets an event through to the human output
+    pub fn wanted(&self, event: Event) -> bool {
+        self.script.as_ref().is_none_or(|script| script.keep(&event.to_value(self.origin.as_ref())))
+    }
+
+    /// Hands an event to the JSON output and the sinks. True if it should be printed for
+    /// the human output too, which the caller has to do itself
+    pub fn publish(&self, out: &mut Output, event: Event) -> bool {
+        if self.format == Format::Human && self.sinks.is_empty() {
+            return self.wanted(event); // Skip making JSON nobody wants
+        }
+
+        let line = match self.json(event) {
+            Some(line) => line,
+            None => return false,
+        };
+
+        for sink in &self.sinks {
+            sink.send(event, &line);
+        }
+
+        match self.format {
+            Format::Json => { writeln!(out, "{}", line); false }
+            Format::Human => true,
+        }
+    }
+}
+
+// With --burst, one interval's worth of probes
+#[derive(Default)]
+struct Burst {
+    number: u32,
+    first: u16, // Sequence number, the rest follow on from it
+    size: u32,
+    done: u32, // Answered or given up on
+    probes: ProbeGroup,
+    lost_at: Vec<u32>,
+}
+
+/// The part of a run between two suspends (or stalls)
+pub struct Segment {
+    pub after: Option<Jump>, // None for the first one
+    pub probes: ProbeGroup,
+}
+
+/// Pinging a single destination, and the statistics gathered doing it
+pub struct Session {
+    pub host: String,
+    pub destination: IpAddr,
+    pub pinger: Box<dyn Probe>,
+    tag: Option<String>, // Put in front of every line when several destinations share the output
+    pub mac: Option<[u8; 6]>, // For destinations on our own subnet
+    pub index: usize, // Where it is among the destinations, for --group-by round
+
+    last_route: Option<Vec<Ipv4Addr>>,
+    repeats: Repeats<(bool, String)>, // The latest error line, and whether it went to stderr
+    round: ProbeGroup,
+    rounds: u32,
+    rollup: ProbeGroup, // With --summary-interval, since the last rollup
+    rollups: u32,
+    rollup_start: Option<Instant>,
+
+    sla: Vec<ProbeGroup>, // One for each SLA window, since the last report
+    next_sla_report: Option<Instant>,
+    webhooks: Vec<thread::JoinHandle<()>>,
+
+    pub baseline: Option<Baseline>, // With --baseline, learning as it goes
+    alert: alert::State,
+
+    clock: clock::Watch,
+    caught: HashMap<u16, Jump>, // Probes that were out when the clock jumped
+    given_up: HashMap<u16, Reason>, // What each probe counted lost was lost to, in case it's answered late
+    pub excluded: u32,
+    pub segments: Vec<Segment>, // Split at each suspend or stall, there's always at least one
+    pub sources: Vec<(IpAddr, ProbeGroup)>, // With --source, taking turns a probe each
+    pub sizes: Vec<(usize, ProbeGroup)>,    // With --size-sweep, the same way
+    pub is_gateway: bool, // Probing the first hop for --with-gateway, for the others to compare with
+
+    last_sequence: u16, // Of the latest probe, sent or not
+    run_id: u32,        // The first half of every probe id, random for each run
+    probes: u32,        // Probes that got a sequence number, the second half of the latest id
+    drops_blamed: u32,  // Kernel drops already given as the reason for a timeout
+    stats_requests: u32, // Times the stats so far were asked for, that we've shown them
+    triggers: u64, // With --trigger, how many we've sent a probe for
+    unanswered: u32, // Probes lost in a row
+    backed_off: Option<Duration>, // With --backoff, the stretched interval while nothing answers
+    reachability: reachability::State, // With --outages or --notify
+    bursts: VecDeque<Burst>, // With --burst, the ones with probes still out
+    burst_count: u32,
+
+    pub sent: u32,
+    pub lost: u32,
+    pub lost_reasons: BTreeMap<Reason, u32>,
+    pub size_mismatches: u32,
+    pub corrupted_payloads: u32,
+    pub late: u32, // Lost, but answered after all (within the --grace, or once given up on)
+    pub duplicates: u32, // Extra answers to probes that already had one
+    ecn: Option<EcnSummary>, // With --ecn, once a reply's said what it came back with
+    lost_with_gateway: u32,   // Lost while the gateway was losing probes too
+    lost_beyond_gateway: u32, // Lost while the gateway was fine
+}
+
+impl Session {
+    pub fn new(host: &str, destination: IpAddr, pinger: Box<dyn Probe>, tagged: bool) -> Self {
+        Session {
+            host: host.to_string(),
+            destination, pinger,
+            tag: if tagged { Some(format!("[{}] ", host)) } else { None },
+            mac: None, index: 0,
+            last_route: None, repeats: Repeats::new(),
+            round: ProbeGroup::default(), rounds: 0,
+            rollup: ProbeGroup::default(), rollups: 0, rollup_start: None,
+            sla: Vec::new(), next_sla_report: None, webhooks: Vec::new(),
+            baseline: None, alert: alert::State::default(),
+            clock: clock::Watch::start(), caught: HashMap::new(), given_up: HashMap::new(), excluded: 0,
+            segments: vec![Segment { after: None, probes: ProbeGroup::default() }],
+            sources: Vec::new(), sizes: Vec::new(), is_gateway: false,
+            last_sequence: 0, run_id: rand::random(), probes: 0, drops_blamed: 0, stats_requests: 0, triggers: 0, unanswered: 0, backed_off: None, reachability: reachability::State::default(),
+            bursts: VecDeque::new(), burst_count: 0,
+            sent: 0, lost: 0, lost_reasons: BTreeMap::new(), size_mismatches: 0, corrupted_payloads: 0, late: 0, duplicates: 0,
+            ecn: None, lost_with_gateway: 0, lost_beyond_gateway: 0,
+        }
+    }
+
+    pub fn loss(&self) -> f32 {
+        100f32 * (self.lost as f32) / (self.sent as f32)
+    }
+
+    fn tag(&self) -> ColoredString {
+        self.tag.as_deref().unwrap_or("").magenta()
+    }
+
+    /// Keep pinging until `running` is cleared. The output is only locked while
+    /// writing, and each event's lines are written under a single lock so
+    /// concurrent sessions never interleave mid-line. The sinks get events under
+    /// the same lock, so they see them in the order they're printed.
+    pub fn run(&mut self, config: &Config, out: &Mutex<Output>, running: &AtomicBool) {
+        if let Some(cpu) = config.cpu {
+            // It's been checked it's there, main exits otherwise
+            if let Err(e) = util::pin_to_cpu(cpu) {
+                log::warn!("couldn't pin to cpu {}: {}", cpu, e);
+            }
+        }
+
+        self.clock = clock::Watch::start(); // Preflight took however long it took
+        self.rollup_start = Some(Instant::now());
+        if config.trigger {
+            if let Some(waker) = self.pinger.waker() {
+                trigger::register(waker);
+            }
+        }
+        if self.pinger.pipelined() {
+            self.run_pipelined(config, out, running);
+        } else {
+            self.run_lockstep(config, out, running);
+        }
+
+        if let Some(repeated) = self.repeats.flush() {
+            self.print_repeats(&mut self.lock(out), repeated);
+        }
+
+        // Don't lose a partly filled round
+        if self.round.sent > 0 {
+            self.print_round(config, &mut self.lock(out));
+        }
+
+        if let Some(sla) = &config.sla {
+            self.report_sla(config, sla, &mut self.lock(out));
+        }
+
+        // Give any alerts still going out a chance to arrive
+        for webhook in self.webhooks.drain(..) {
+            let _ = webhook.join();
+        }
+        self.lock(out).stopped();
+    }
+
+    // The output, to write this destination's lines
+    fn lock<'a>(&self, out: &'a Mutex<Output>) -> Locked<'a> {
+        output::lock_for(out, self.index)
+    }
+
+    // Probes go out on schedule no matter what's still outstanding, and answers are
+    // reported as they arrive, whichever probe they're for
+    fn run_pipelined(&mut self, config: &Config, out: &Mutex<Output>, running: &AtomicBool) {
+        let mut outstanding: VecDeque<(u16, Instant)> = VecDeque::new(); // With deadlines, in the order sent
+        let mut next_send = Instant::now();
+        let mut last_sent = next_send;
+
+        while running.load(Ordering::SeqCst) {
+            self.check_stats_request(config, out);
+            let now = Instant::now();
+            if config.trigger {
+                next_send = self.next_triggered(now);
+            }
+            if now >= next_send {
+                let size = config.burst.unwrap_or(1);
+                if config.burst.is_some() {
+                    self.burst_count += 1;
+                    self.bursts.push_back(Burst { number: self.burst_count, first: self.last_sequence.wrapping_add(1), size, ..Burst::default() });
+                }
+                for _ in 0..size {
+                    // With every sequence number out at once (tiny intervals, long timeouts), the
+                    // next probe would be taken for the oldest one. Give up on that one to make room
+                    if outstanding.len() >= SEQUENCE_NUMBERS {
+                        let (sequence_num, _) = outstanding.pop_front().unwrap();
+                        self.pinger.forget(sequence_num);
+                        self.probe_failed(config, out, sequence_num, Error::new(ErrorKind::WouldBlock, "timed out"));
+                    }
+                    match self.ping() {
+                        Ok(sequence_num) => {
+                            self.sent += 1;
+                            self.last_sequence = sequence_num;
+                            outstanding.push_back((sequence_num, now + config.timeout + config.grace));
+                        }
+                        Err(e) => self.send_failed(config, out, e),
+                    }
+                }
+                last_sent = now;
+
+                if config.trigger {
+                    self.triggers += 1;
+                    next_send = self.next_triggered(now);
+                } else {
+                    // After a stall carry on from now, catching up would be a burst of probes
+                    next_send = std::cmp::max(next_send + self.interval(config), now);
+                }
+            }
+
+            // Every probe has the same timeout, so the oldest ones always expire first
+            while let Some(&(sequence_num, deadline)) = outstanding.front() {
+                if deadline > now { break }
+                outstanding.pop_front();
+                self.pinger.forget(sequence_num);
+                self.probe_failed(config, out, sequence_num, Error::new(ErrorKind::WouldBlock, "timed out"));
+            }
+            if config.trigger && outstanding.is_empty() && trigger::over(self.triggers) {
+                break;
+            }
+
+            let wake = outstanding.front().map_or(next_send, |&(_, deadline)| std::cmp::min(deadline, next_send));
+            let pong = self.pinger.receive_any(wake.saturating_duration_since(Instant::now()));
+            self.check_clock(config, out, outstanding.iter().map(|&(sequence, _)| sequence));
+
+            match pong {
+                Ok(pong) => {
+                    let sequence_num = pong.sequence;
+                    if self.probe_answered(config, out, pong) {
+                        outstanding.retain(|&(sequence, _)| sequence != sequence_num);
+                        // Answered again after backing off, the next probe doesn't wait out the long interval
+                        if !config.trigger {
+                            next_send = std::cmp::min(next_send, last_sent + self.interval(config));
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {} // Time to send, or something expired
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => break, // Ctrl+C most likely
+                Err(e) => {
+                    eprintln!("{}Error receiving pong: {}", self.tag(), e);
+                    util::sleep(wake.saturating_duration_since(Instant::now()));
+                }
+            }
+        }
+
+        // Quitting, whatever's still out never got its chance
+        for (sequence_num, _) in outstanding {
+            self.pinger.forget(sequence_num);
+            self.probe_failed(config, out, sequence_num, Error::new(ErrorKind::Interrupted, "interrupted"));
+        }
+    }
+
+    // How long until the next probe, longer while backing off
+    fn interval(&self, config: &Config) -> Duration {
+        self.backed_off.unwrap_or(config.interval)
+    }
+
+    // With --backoff, stretch the interval once the destination's been quiet a while, and
+    // snap back as soon as it answers
+    fn back_off(&mut self, config: &Config, out: &Mutex<Output>, cap: Duration) {
+        let stretched = if self.unanswered == 0 {
+            None
+        } else {
+            if self.unanswered < BACKOFF_AFTER {
+                return;
+            }
+            Some(std::cmp::min(self.interval(config) * 2, std::cmp::max(cap, config.interval)))
+        };
+        if stretched == self.backed_off {
+            return;
+        }
+
+        if config.format == Format::Human {
+            let mut out = self.lock(out);
+            match stretched {
+                Some(interval) if self.backed_off.is_none() => writeln!(out, "{}{} no answer to {} probes in a row, probing every {} (up to {})",
+                    self.tag(), "BACKING OFF:".yellow().bold(), self.unanswered, humantime::format_duration(interval), humantime::format_duration(cap)),
+                Some(_) => {} // Just stretching further, once is enough to say
+                None => writeln!(out, "{}{} back to probing every {}", self.tag(), "ANSWERING:".green().bold(), humantime::format_duration(config.interval)),
+            }
+        }
+        self.backed_off = stretched;
+    }
+
+    // It went down or came back up: a line with --outages, a popup with --notify
+    fn report_reachability(&self, config: &Config, out: &Mutex<Output>, change: reachability::Change) {
+        let event = match change {
+            reachability::Change::Down { since, lost } => ReachabilityEvent {
+                host: self.host.clone(), destination: self.destination, state: "down",
+                start: timestamp(since), end: None, seconds: None, lost,
+            },
+            reachability::Change::Up(index) => {
+                let outage = &self.reachability.outages[index];
+                ReachabilityEvent {
+                    host: self.host.clone(), destination: self.destination, state: "up",
+                    start: timestamp(outage.start), end: outage.end.map(timestamp), seconds: Some(outage.seconds()), lost: outage.lost,
+                }
+            }
+        };
+        let down_for = event.seconds.map(|seconds| humantime::format_duration(Duration::from_secs(seconds as u64)));
+
+        if config.outages {
+            let mut out = self.lock(out);
+            if config.publish(&mut out, Event::Reachability(&event)) {
+                match &down_for {
+                    None => writeln!(out, "{}{} since {}, no answer to {} probes in a row", self.tag(), "DOWN:".red().bold(), event.start, event.lost),
+                    Some(down_for) => writeln!(out, "{}{} at {}, after {} down ({} probes lost)", self.tag(), "UP:".green().bold(),
+                        event.end.as_deref().unwrap_or_default(), down_for.to_string().bold(), event.lost),
+                }
+            }
+        }
+
+        if config.notify {
+            match down_for {
+                None => notify::send(format!("{} is unreachable", self.host),
+                    format!("No answer from {} to {} probes in a row", self.destination, event.lost), true),
+                Some(down_for) => notify::send(format!("{} is reachable again", self.host),
+                    format!("{} is answering, after {} down", self.destination, down_for), false),
+            }
+        }
+    }
+
+    // With --trigger, right away if there's one we haven't sent for yet. Otherwise it's
+    // up to the waker to cut the wait short
+    fn next_triggered(&self, now: Instant) -> Instant {
+        if self.triggers < trigger::fired() { now } else { now + UNSCHEDULED }
+    }
+
+    // One probe at a time, for backends that can't tell answers to different probes apart
+    fn run_lockstep(&mut self, config: &Config, out: &Mutex<Output>, running: &AtomicBool) {
+        let mut due = Instant::now(); // When the last probe was meant to go out
+        while running.load(Ordering::SeqCst) {
+            self.check_stats_request(config, out);
+            if config.trigger {
+                // Can't be woken while waiting on an answer, a trigger then waits its turn
+                if !trigger::wait(self.triggers) {
+                    break;
+                }
+                self.triggers += 1;
+            }
+
+            let sequence_num = match self.ping() {
+                Ok(n) => n,
+                Err(e) => {
+                    self.send_failed(config, out, e);
+                    if !config.trigger {
+                        util::sleep_until_next(&mut due, self.interval(config));
+                    }
+                    continue;
+                }
+            };
+
+            self.sent += 1;
+            self.last_sequence = sequence_num;
+
+            let wait_until = Instant::now() + config.timeout + config.grace;
+            loop {
+                let pong = self.pinger.receive_pong(sequence_num, wait_until.saturating_duration_since(Instant::now()));
+                self.check_clock(config, out, std::iter::once(sequence_num));
+
+                match pong {
+                    Ok(pong) => if self.probe_answered(config, out, pong) { break },
+                    Err(e) => { self.probe_failed(config, out, sequence_num, e); break }
+                }
+            }
+
+            // Counted from when this probe was due, not from its answer. Wakes up early for
+            // Ctrl+C, so quitting is never stuck behind a long interval
+            if !config.trigger {
+                util::sleep_until_next(&mut due, self.interval(config));
+            }
+        }
+    }
+
+    // Ctrl+\ (or Enter) asks for the stats so far, without stopping like Ctrl+C
+    fn check_stats_request(&mut self, config: &Config, out: &Mutex<Output>) {
+        let requests = util::stats_requests();
+        if requests == self.stats_requests {
+            return;
+        }
+        self.stats_requests = requests;
+
+        let done = self.done();
+        if done.sent == 0 {
+            return; // Nothing to say yet
+        }
+
+        let mut summary = self.summary();
+        summary.interim = true;
+        summary.sent = done.sent;
+        summary.received = done.sent - done.lost;
+        summary.loss = done.loss();
+
+        let mut out = self.lock(out);
+        if config.publish(&mut out, Event::Summary(&summary)) {
+            let still_out = self.sent.saturating_sub(done.sent);
+            writeln!(out, "{}{}/{} received, {}% packet loss, rtt min/avg/max={}{}", self.tag(), summary.received.to_string().bold(),
+                summary.sent.to_string().bold(), locale::decimal(summary.loss, 2).bold(), done.rtt.format(),
+                if still_out > 0 { format!(", {} still out", still_out) } else { String::new() });
+        }
+    }
+
+    /// All the probes that are done, across segments. The ones still out haven't been lost yet
+    pub fn done(&self) -> ProbeGroup {
+        let mut done = ProbeGroup::default();
+        for segment in &self.segments {
+            done.add(&segment.probes);
+        }
+        done
+    }
+
+    /// Send the probes from these local addresses, taking turns, and keep stats for each
+    pub fn set_sources(&mut self, sources: Vec<IpAddr>) {
+        self.sources = sources.into_iter().map(|source| (source, ProbeGroup::default())).collect();
+    }
+
+    /// Go through these payload sizes, a probe each in turn, and keep stats for each
+    pub fn set_sizes(&mut self, sizes: Vec<usize>) {
+        self.sizes = sizes.into_iter().map(|size| (size, ProbeGroup::default())).collect();
+    }
+
+    // Sends the next probe, with its id, from its source and at its size
+    fn ping(&mut self) -> std::io::Result<u16> {
+        self.probes += 1;
+        self.pinger.set_probe_id(((self.run_id as u64) << 32) | self.probes as u64);
+        if let Some(source) = self.source(self.probes) {
+            self.pinger.set_source(source);
+        }
+        if let Some(size) = self.size(self.probes) {
+            self.pinger.set_size(size);
+        }
+        let sequence_num = self.pinger.ping()?;
+        self.given_up.remove(&sequence_num);
+        Ok(sequence_num)
+    }
+
+    // Which probe was the latest sent with `sequence_num`, they're counted rather than a
+    // sequence number that wraps. There's never more than 65536 out, see run_pipelined
+    fn probe_number(&self, sequence_num: u16) -> u32 {
+        self.probes.wrapping_sub(self.last_sequence.wrapping_sub(sequence_num) as u32)
+    }
+
+    fn probe_id(&self, sequence_num: u16) -> String {
+        format!("{:08x}{:08x}", self.run_id, self.probe_number(sequence_num))
+    }
+
+    // The source address of a probe, by its number
+    fn source(&self, probe: u32) -> Option<IpAddr> {
+        if self.sources.is_empty() {
+            return None;
+        }
+        Some(self.sources[probe.wrapping_sub(1) as usize % self.sources.len()].0)
+    }
+
+    // The payload size of a probe, by its number
+    fn size(&self, probe: u32) -> Option<usize> {
+        if self.sizes.is_empty() {
+            return None;
+        }
+        Some(self.sizes[probe.wrapping_sub(1) as usize % self.sizes.len()].0)
+    }
+
+    // How big probe `sequence_num` went out, header and all. The pinger only knows the latest's
+    fn request_size(&self, sequence_num: u16) -> Option<usize> {
+        let latest = self.pinger.request_size()?;
+        Some(self.size(self.probe_number(sequence_num)).map_or(latest, |size| packet::ICMP_ECHO_HEADER_LEN + size))
+    }
+
+    // Report an answer to a probe. False for redirects, which are only advice from a
+    // router: the packet was still forwarded, so keep waiting for the real answer
+    fn probe_answered(&mut self, config: &Config, out: &Mutex<Output>, pong: PongResult) -> bool {
+        if let ReplyType::Redirect(code, gateway) = pong.mtype {
+            let mut event = self.event(pong.sequence, Status::Redirect);
+            event.from = Some(pong.address);
+            event.gateway = Some(gateway);
+            event.detail = Some(ping::redirect_reason(self.destination.is_ipv6(), code).to_string());
+            self.report(config, out, &event);
+            return false;
+        }
+
+        if pong.duplicate || pong.late {
+            let status = if pong.duplicate {
+                self.duplicates += 1;
+                Status::Duplicate
+            } else {
+                // Lost as far as the totals go, if it's still counted that way (not taken back
+                // out for a clock jump). The lenient count (and the reasons) can take it as answered
+                if let Some(reason) = self.given_up.remove(&pong.sequence) {
+                    self.late += 1;
+                    if let Some(count) = self.lost_reasons.get_mut(&reason) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.lost_reasons.remove(&reason);
+                        }
+                    }
+                    *self.lost_reasons.entry(Reason::Late).or_insert(0) += 1;
+                }
+                Status::LateReply
+            };
+            let mut event = self.event(pong.sequence, status);
+            event.from = Some(pong.address);
+            event.hostname = pong.hostname;
+            event.ttl = pong.ttl;
+            event.size = self.pinger.request_size().and(Some(pong.size));
+            event.set_rtt(pong.rtt);
+            self.report(config, out, &event);
+            return false;
+        }
+
+        let caught = self.caught.remove(&pong.sequence);
+        let mut event = self.pong_event(config, pong);
+        if let Some(jump) = caught {
+            self.exclude(&mut event, jump);
+            self.report(config, out, &event);
+            self.burst_probe_done(config, out, event.seq, None);
+            return true;
+        }
+
+        self.compare_gateway(config, &mut event);
+
+        self.report(config, out, &event);
+        self.finish_probe(config, out, &event);
+        true
+    }
+
+    // Report a probe that never got an answer
+    fn probe_failed(&mut self, config: &Config, out: &Mutex<Output>, sequence_num: u16, e: Error) {
+        self.lost += 1;
+
+        let mut event = match e.kind() {
+            ErrorKind::WouldBlock => self.event(sequence_num, Status::Timeout),
+            ErrorKind::Interrupted => self.event(sequence_num, Status::Interrupted), // Ctrl+C most likely
+            _ => self.event(sequence_num, Status::Error),
+        };
+        event.reason = Some(self.failure_reason(&e));
+        event.detail = Some(format!("{:?}", e));
+
+        if let Some(jump) = self.caught.remove(&sequence_num) {
+            self.exclude(&mut event, jump);
+            self.report(config, out, &event);
+            self.burst_probe_done(config, out, sequence_num, None);
+            return;
+        }
+
+        self.compare_gateway(config, &mut event);
+        self.report(config, out, &event);
+        self.finish_probe(config, out, &event);
+    }
+
+    // With --with-gateway, whether the gateway was losing probes too when this one was lost.
+    // Only for losses out on the network, not ones that never left or ended up in our kernel
+    fn compare_gateway(&mut self, config: &Config, event: &mut ProbeEvent) {
+        if self.is_gateway || !matches!(event.reason, Some(Reason::NoReply) | Some(Reason::Unreachable)
+            | Some(Reason::TimeExceeded) | Some(Reason::ParameterProblem) | Some(Reason::Error)) {
+            return;
+        }
+        let watch = match config.gateways.iter().find(|watch| watch.address.is_ipv6() == self.destination.is_ipv6()) {
+            Some(watch) => watch,
+            None => return,
+        };
+
+        let lossy = watch.lossy_around(sent_at(config, event), config.interval, event.status == Status::Timeout);
+        if lossy { self.lost_with_gateway += 1 } else { self.lost_beyond_gateway += 1 }
+        event.gateway_lossy = Some(lossy);
+    }
+
+    // Best guess at why a probe got no answer
+    fn failure_reason(&mut self, e: &Error) -> Reason {
+        match e.kind() {
+            ErrorKind::WouldBlock => {
+                // Every drop since the last look can explain one timeout
+                match self.pinger.kernel_drops() {
+                    Ok(drops) if drops > self.drops_blamed => { self.drops_blamed += 1; Reason::KernelDrop }
+                    _ => Reason::NoReply,
+                }
+            }
+            ErrorKind::Interrupted => Reason::Interrupted,
+            // Backends that get ICMP errors as socket errors, like a TCP connect
+            _ if matches!(e.raw_os_error(), Some(libc::EHOSTUNREACH) | Some(libc::ENETUNREACH)) => Reason::Unreachable,
+            _ => Reason::Error,
+        }
+    }
+
+    // A probe that couldn't even be sent still counts, a network that's down is the
+    // biggest outage there is. It used up a sequence number all the same
+    fn send_failed(&mut self, config: &Config, out: &Mutex<Output>, e: Error) {
+        self.sent += 1;
+        self.lost += 1;
+        self.last_sequence = self.last_sequence.wrapping_add(1);
+
+        let mut event = self.event(self.last_sequence, Status::Error);
+        event.reason = Some(Reason::SendFailed);
+        event.detail = Some(e.to_string());
+        self.report(config, out, &event);
+        self.finish_probe(config, out, &event);
+    }
+
+    // Looks for a clock jump since the last check, the probes still `outstanding` were caught in it
+    fn check_clock(&mut self, config: &Config, out: &Mutex<Output>, outstanding: impl Iterator<Item = u16>) {
+        // Pipelined, it's never longer than an interval between checks. One at a time it can
+        // be a whole timeout waiting on an answer, then the interval until the next probe.
+        // Triggered, there's no telling how long until the next one, only a suspend shows
+        let expected = if config.trigger {
+            UNSCHEDULED
+        } else if self.pinger.pipelined() {
+            self.interval(config)
+        } else {
+            config.timeout + self.interval(config)
+        };
+        let jump = match self.clock.check(expected) {
+            Some(jump) => jump,
+            None => return,
+        };
+
+        for sequence_num in outstanding {
+            self.caught.insert(sequence_num, jump);
+        }
+
+        if jump.is_gap() {
+            self.segments.push(Segment { after: Some(jump), probes: ProbeGroup::default() });
+        }
+
+        let event = ClockEvent {
+            host: self.host.clone(), jump: jump.kind(), seconds: jump.seconds(), detail: jump.describe(),
+            segment: if jump.is_gap() { Some(self.segments.len() as u32) } else { None },
+        };
+        let mut out = self.lock(out);
+        if config.publish(&mut out, Event::Clock(&event)) {
+            writeln!(out, "{}{} {}, probes out meanwhile aren't counted", self.tag(), "Clock:".yellow().bold(), event.detail);
+            if let Some(segment) = event.segment {
+                writeln!(out, "{}{}", self.tag(), format!("segment {} after {}", segment, jump.cause()).bold());
+            }
+        }
+
+        if jump.is_gap() && config.reset_on_resume {
+            self.reset_windows(config, &mut out);
+        }
+    }
+
+    // Whatever was going on before now is another network, as far as we know (the laptop
+    // went to sleep at home and woke up at work), so don't mix it into what comes after
+    fn reset_windows(&mut self, config: &Config, out: &mut Output) {
+        if self.round.sent > 0 {
+            self.print_round(config, out);
+        }
+
+        if let Some(sla) = &config.sla {
+            self.report_sla(config, sla, out);
+            self.next_sla_report = None;
+        }
+
+        if let Some(baseline) = &mut self.baseline {
+            baseline.restart_period();
+        }
+    }
+
+    // Takes a probe caught in a clock jump back out of the totals, its time means nothing
+    fn exclude(&mut self, event: &mut ProbeEvent, jump: Jump) {
+        if event.status != Status::Reply && event.status != Status::Reset {
+            self.lost -= 1;
+        }
+        self.sent -= 1;
+        self.excluded += 1;
+
+        event.sent = self.sent;
+        event.lost = self.lost;
+        event.clock_jump = Some(jump.describe());
+    }
+
+    /// Send a quick burst of probes before settling into the normal cadence. Fails if
+    /// the first stage of the path is clearly dead (no route, local address resolution
+    /// failing), so there's no need to wait through several full timeouts to find out.
+    /// Silence alone is only a warning: plenty of hosts just don't answer pings.
+    pub fn preflight(&mut self) -> Result<(), String> {
+        const PROBES: u32 = 3;
+        const TIMEOUT: Duration = Duration::from_millis(300);
+
+        let mut local_failures = Vec::new();
+        for _ in 0..PROBES {
+            let sequence_num = match self.pinger.ping() {
+                Ok(n) => n,
+                Err(e) => {
+                    // Nowhere to send it at all, that's a local problem
+                    local_failures.push(format!("sending failed: {}", e));
+                    continue;
+                }
+            };
+
+            if let Ok(pong) = self.pinger.receive_pong(sequence_num, TIMEOUT) {
+                match pong.mtype {
+                    ReplyType::DestinationUnreachable(code) => local_failures.push(format!("{} (from {})",
+                        ping::unreachable_reason(self.destination.is_ipv6(), code), pong.address)),
+                    _ => return Ok(()), // Something out there is answering, the path is at least partly alive
+                }
+            }
+        }
+
+        if local_failures.len() as u32 == PROBES {
+            local_failures.dedup(); // The same error three times over says nothing new
+            return Err(local_failures.join(", "));
+        }
+
+        log::warn!("{}no reply to {} pre-flight probes, continuing anyway", self.tag(), PROBES);
+        Ok(())
+    }
+
+    /// A blank event for this session, with the running totals filled in
+    fn event(&self, sequence_num: u16, status: Status) -> ProbeEvent {
+        let mut event = ProbeEvent::new(&self.host, self.destination, sequence_num, status);
+        event.id = Some(self.probe_id(sequence_num));
+        event.source = self.source(self.probe_number(sequence_num));
+        event.sent = self.sent;
+        event.lost = self.lost;
+        event
+    }
+
+    /// Count a pong, and describe it as an event
+    fn pong_event(&mut self, config: &Config, pong: PongResult) -> ProbeEvent {
+        let ipv6 = self.destination.is_ipv6();
+
+        let (status, detail) = match pong.mtype {
+            // Only waited for with --grace
+            ReplyType::Reply | ReplyType::Reset if pong.rtt > config.timeout => (Status::Late, None),
+            ReplyType::Reply => (Status::Reply, None),
+            ReplyType::Reset => (Status::Reset, Some("Port closed (RST)".to_string())),
+            ReplyType::TimeLimitExceeded => (Status::TimeExceeded, Some("Time to live exceeded".to_string())),
+            ReplyType::DestinationUnreachable(code) => (Status::Unreachable, Some(ping::unreachable_reason(ipv6, code).to_string())),
+            ReplyType::ParameterProblem(code, pointer) =>
+                (Status::ParameterProblem, Some(format!("{}: pointer = {}", ping::parameter_problem_reason(ipv6, code), pointer))),
+            ReplyType::Redirect(_, _) => unreachable!(), // Handled while receiving
+        };
+
+        // Any of the errors count as a lost packet (TTL timeouts, can't reach it, or it was discarded).
+        // A RST is still an answer from the host though.
+        if status != Status::Reply && status != Status::Reset {
+            self.lost += 1;
+        }
+
+        let mut event = self.event(pong.sequence, status);
+        event.reason = match status {
+            Status::TimeExceeded => Some(Reason::TimeExceeded),
+            Status::Unreachable => Some(Reason::Unreachable),
+            Status::ParameterProblem => Some(Reason::ParameterProblem),
+            Status::Late => Some(Reason::Late),
+            _ => None,
+        };
+        event.from = Some(pong.address);
+        event.hostname = pong.hostname;
+        event.ttl = pong.ttl;
+        event.detail = detail;
+        event.transport = pong.transport;
+
+        if status == Status::Reset {
+            event.set_rtt(pong.rtt);
+        } else if status == Status::Reply || status == Status::Late {
+            event.set_rtt(pong.rtt);
+            if let Some(baseline) = &self.baseline {
+                event.anomaly = baseline.probe_anomaly(pong.rtt);
+            }
+            event.phases = pong.phases;
+            event.detail = pong.detail;
+            event.size = self.pinger.request_size().and(Some(pong.size));
+            event.route = pong.route;
+
+            // Middleboxes trimming or padding our data show up as a different sized reply
+            if let Some(request_size) = self.request_size(pong.sequence) {
+                let mismatch = pong.size as i64 - request_size as i64;
+                if mismatch != 0 {
+                    self.size_mismatches += 1;
+                    event.size_mismatch = Some(mismatch);
+                }
+            }
+
+            // Bytes flipped on the way, that the checksum didn't catch (or was fixed up for)
+            if pong.corruption.is_some() {
+                self.corrupted_payloads += 1;
+                event.corruption = pong.corruption;
+            }
+
+            if let (Some(sent), Some(tos)) = (config.ecn, pong.tos) {
+                self.record_ecn(sent, tos);
+                event.ecn = Some(packet::ecn_name(tos));
+            }
+            if pong.hardware.is_some() {
+                event.timestamps = Some("hardware");
+            }
+        }
+
+        event
+    }
+
+    // Whether the codepoint we sent made it there and back
+    fn record_ecn(&mut self, sent: u8, tos: u8) {
+        let ecn = self.ecn.get_or_insert(EcnSummary { sent: packet::ecn_name(sent), kept: 0, cleared: 0, marked: 0, changed: 0 });
+        match tos & packet::ECN_MASK {
+            got if got == sent => ecn.kept += 1,
+            packet::ECN_NOT_ECT => ecn.cleared += 1,
+            packet::ECN_CE => ecn.marked += 1,
+            _ => ecn.changed += 1,
+        }
+    }
+
+    fn report(&mut self, config: &Config, out: &Mutex<Output>, event: &ProbeEvent) {
+        // Rounds and bursts replace the per-probe lines
+        if (config.round.is_some() || config.burst.is_some()) && event.status.is_outcome() {
+            return;
+        }
+
+        let mut out = self.lock(out);
+        if config.publish(&mut out, Event::Probe(event)) {
+            if config.collapse_repeats {
+                let (repeated, show) = self.repeats.check(repeat_key(event));
+                if let Some(repeated) = repeated {
+                    self.print_repeats(&mut out, repeated);
+                }
+                if !show {
+                    return;
+                }
+            }
+            self.print_event(&mut out, event, config.rtt_colors);
+        }
+    }
+
+    fn print_repeats(&self, out: &mut Output, ((stderr, _), count): ((bool, String), u32)) {
+        let line = format!("{}(last message repeated {} time{})", self.tag(), count, if count == 1 { "" } else { "s" });
+        if stderr {
+            eprintln!("{}", line);
+        } else {
+            writeln!(out, "{}", line);
+        }
+    }
+
+    fn print_event(&mut self, out: &mut Output, event: &ProbeEvent, colors: Option<RttColors>) {
+        let tag = self.tag();
+        let address = event.from.unwrap_or(event.destination);
+        let name = event.hostname.clone().unwrap_or_else(|| address.to_string());
+        // Just the address when it has no name (or -n), like ping
+        let numeric = match event.hostname {
+            Some(_) => format!(" ({})", address),
+            None => String::new(),
+        };
+
+        match event.status {
+            Status::Reply | Status::Reset | Status::Late | Status::Duplicate | Status::LateReply => {
+                match event.size {
+                    Some(size) => write!(out, "{}{} bytes from {}{}: ", tag, size, name.yellow(), numeric),
+                    None => write!(out, "{}Reply from {}{}: ", tag, name.yellow(), numeric),
+                }
+                
+                write!(out, "{}={} ", if event.size.is_some() { "icmp_seq" } else { "seq" }, event.seq.to_string().bold());
+        
+                // Turns out it's really difficult to get the hop_limit from ipv6 packets because
+                // the raw socket for ipv6 connections doesn't include the ipv6 header when it puts
+                // the message into the buffer. (But it does put the ipv4 header in when the connection is ipv4)
+                // Making this work would involve adding features to the socket2 crate to be able to use `recvmsg`
+                if let Some(ttl) = event.ttl {
+                    write!(out, "ttl={} ", ttl.to_string().bold());
+                }
+
+                let rtt = event.rtt.unwrap_or_default();
+                let time = stats::format_rtt(rtt);
+                let time = colors.map_or_else(|| time.bold(), |colors| colors.paint(rtt, &time));
+                write!(out, "time={}{} ", time, if event.timestamps.is_some() { " (hw)" } else { "" });
+
+                write!(out, "loss={}%", locale::decimal(event.loss(), 2).bold());
+
+                if let Some(source) = event.source {
+                    write!(out, " src={}", source);
+                }
+
+                for (name, time) in &event.phases {
+                    write!(out, " {}={}", name, stats::format_rtt(*time).bold());
+                }
+
+                match (&event.detail, &event.transport) {
+                    (_, Some(transport)) if event.status == Status::Reset => write!(out, " {}", format!("Port closed, {}", transport.describe()).yellow()),
+                    (detail, transport) => {
+                        if let Some(detail) = detail {
+                            write!(out, " {}", detail.yellow());
+                        }
+                        if let Some(transport) = transport {
+                            write!(out, " {}", transport.describe());
+                        }
+                    }
+                }
+
+                match event.size_mismatch {
+                    Some(mismatch) if mismatch < 0 => write!(out, " {}", format!("(truncated by {} bytes)", -mismatch).red()),
+                    Some(mismatch) => write!(out, " {}", format!("(padded by {} bytes)", mismatch).red()),
+                    None => {}
+                }
+                if let Some(corruption) = &event.corruption {
+                    write!(out, " {}", format!("({})", corruption.describe()).red());
+                }
+                if let Some(ecn) = event.ecn.filter(|&ecn| self.ecn.as_ref().is_some_and(|summary| summary.sent != ecn)) {
+                    write!(out, " {}", format!("(ecn came back {})", ecn).red());
+                }
+
+                if let Some(anomaly) = &event.anomaly {
+                    write!(out, " {} {}", "ANOMALY:".red().bold(), anomaly);
+                }
+
+                if event.status == Status::Late {
+                    write!(out, " {}", "(late, lost by the timeout)".red());
+                }
+                if event.status == Status::Duplicate {
+                    write!(out, " {}", "(DUP!)".red().bold());
+                }
+                if event.status == Status::LateReply {
+                    write!(out, " {}", "(late reply, already counted lost)".red());
+                }
+
+                writeln!(out); // Finish the line
+
+                if let Some(route) = &event.route {
+                    if self.last_route.as_ref() == Some(route) {
+                        writeln!(out, "{}(same route)", tag);
+                    } else {
+                        for (i, hop) in route.iter().enumerate() {
+                            writeln!(out, "{}{}\t{}", tag, if i == 0 { "RR:" } else { "" }, hop);
+                        }
+                        self.last_route = Some(route.clone());
+                    }
+                }
+            }
+
+            Status::TimeExceeded => {
+                write!(out, "{}From {}{}: ", tag, name, numeric);
+                write!(out, "icmp_seq={} ", event.seq);
+                writeln!(out, "{}{}{}", event.detail.as_deref().unwrap_or(""), quoting(event), gateway_note(event));
+            }
+
+            Status::Unreachable | Status::ParameterProblem => {
+                write!(out, "{}From {}{}: ", tag, name, numeric);
+                write!(out, "icmp_seq={} ", event.seq);
+                writeln!(out, "{}{}{}", event.detail.as_deref().unwrap_or("").red(), quoting(event), gateway_note(event));
+            }
+
+            Status::Redirect => {
+                writeln!(out, "{}From {}: icmp_seq={} {} (New nexthop: {})", tag, address, event.seq,
+                    event.detail.as_deref().unwrap_or("").yellow(), event.gateway.map(|g| g.to_string()).unwrap_or_default());
+            }
+
+            Status::Timeout if event.reason == Some(Reason::KernelDrop) => {
+                writeln!(out, "{}Ping timed out, the reply was probably dropped by our kernel (receive buffer full). Lost {}/{} ({}%)", tag,
+                    event.lost.to_string().red().bold(), event.sent.to_string().bold(),
+                    locale::decimal(event.loss(), 2).bold());
+            }
+
+            Status::Timeout => {
+                writeln!(out, "{}Ping {}timed out. Lost {}/{} ({}%){}", tag,
+                    event.source.map_or(String::new(), |source| format!("from {} ", source)),
+                    event.lost.to_string().red().bold(), event.sent.to_string().bold(), 
+                    locale::decimal(event.loss(), 2).bold(), gateway_note(event));
+            }
+
+            Status::Interrupted => {
+                writeln!(out, "\n{}Pong-receive interrupted, counting as lost packet. Lost {}/{} ({}%)", tag,
+                    event.lost.to_string().red().bold(), event.sent.to_string().bold(), 
+                    locale::decimal(event.loss(), 2).bold());
+            }
+
+            Status::Error if event.reason == Some(Reason::SendFailed) => {
+                eprintln!("{}Error sending ping: {}", tag, event.detail.as_deref().unwrap_or(""));
+            }
+
+            Status::Error => {
+                eprintln!("{}Error receiving pong: {}{}", tag, event.detail.as_deref().unwrap_or(""), gateway_note(event));
+            }
+        }
+
+        if let Some(jump) = &event.clock_jump {
+            writeln!(out, "{}(seq={} not counted, {} while it was out)", tag, event.seq, jump);
+        }
+    }
+
+    /// Account for a probe that's been answered, or lost
+    fn finish_probe(&mut self, config: &Config, out: &Mutex<Output>, event: &ProbeEvent) {
+        // Late answers are losses everywhere but the lenient count
+        let rtt = if event.status == Status::Late { self.late += 1; None } else { event.rtt };
+        if let Some(reason) = event.reason {
+            *self.lost_reasons.entry(reason).or_insert(0) += 1;
+            if reason != Reason::Late && reason != Reason::SendFailed {
+                self.given_up.insert(event.seq, reason);
+            }
+        }
+        self.segments.last_mut().unwrap().probes.record(rtt);
+        if let Some((_, group)) = self.sources.iter_mut().find(|(source, _)| Some(*source) == event.source) {
+            group.record(rtt);
+        }
+        if let Some(size) = self.size(self.probe_number(event.seq)) {
+            if let Some((_, group)) = self.sizes.iter_mut().find(|(sweep_size, _)| *sweep_size == size) {
+                group.record(rtt);
+            }
+        }
+        if self.is_gateway && event.status != Status::Interrupted {
+            if let Some(watch) = config.gateways.iter().find(|watch| watch.address == self.destination) {
+                watch.record(sent_at(config, event), rtt.is_some());
+            }
+        }
+
+        if let Some(sla) = &config.sla {
+            self.record_sla(config, sla, out, rtt);
+        }
+
+        if event.status != Status::Interrupted {
+            self.unanswered = if rtt.is_some() { 0 } else { self.unanswered + 1 };
+            if let Some(cap) = config.backoff {
+                self.back_off(config, out, cap);
+            }
+            if let Some(thresholds) = &config.reachability {
+                let sent = SystemTime::now() - sent_at(config, event).elapsed();
+                if let Some(change) = self.reachability.record(thresholds, sent, rtt.is_some()) {
+                    self.report_reachability(config, out, change);
+                }
+            }
+        }
+
+        if let Some(alert) = &config.alert {
+            if let Some(change) = self.alert.record(alert, rtt) {
+                self.report_alert(config, alert, &mut self.lock(out), change);
+            }
+        }
+
+        if let Some(anomaly) = self.baseline.as_mut().and_then(|baseline| baseline.record(rtt)) {
+            self.report_anomaly(config, &mut self.lock(out), anomaly);
+        }
+
+        if let Some(size) = config.round {
+            self.round.record(rtt);
+            if self.round.sent >= size {
+                self.print_round(config, &mut self.lock(out));
+            }
+        }
+
+        if let Some(every) = config.summary_interval {
+            self.rollup.record(rtt);
+            let now = Instant::now();
+            let start = self.rollup_start.unwrap_or(now);
+            if now >= start + every {
+                self.print_rollup(config, &mut self.lock(out), now - start);
+                self.rollup_start = Some(now);
+            }
+        }
+
+        if config.burst.is_some() {
+            self.burst_probe_done(config, out, event.seq, (event.status != Status::Interrupted).then_some(rtt));
+        }
+
+        // Everything about this probe is out, with --group-by round the other destinations' can follow
+        self.lock(out).probe_done();
+    }
+
+    fn record_sla(&mut self, config: &Config, sla: &Sla, out: &Mutex<Output>, rtt: Option<Duration>) {
+        self.sla.resize(sla.windows.len(), ProbeGroup::default());
+
+        let (weekday, minute) = sla::local_time();
+        for (window, group) in sla.windows.iter().zip(&mut self.sla) {
+            if window.contains(weekday, minute) {
+                group.record(rtt);
+            }
+        }
+
+        let now = Instant::now();
+        if now >= *self.next_sla_report.get_or_insert(now + sla.report_every) {
+            self.report_sla(config, sla, &mut self.lock(out));
+            self.next_sla_report = Some(now + sla.report_every);
+        }
+    }
+
+    fn report_anomaly(&self, config: &Config, out: &mut Output, anomaly: PeriodAnomaly) {
+        let event = AnomalyEvent {
+            host: self.host.clone(),
+            destination: self.destination,
+            sent: anomaly.sent,
+            lost: anomaly.lost,
+            loss: 100f32 * anomaly.lost as f32 / anomaly.sent as f32,
+            rtt_median_ms: event::to_ms(anomaly.median),
+            baseline_rtt_ms: anomaly.baseline_rtt,
+            baseline_loss: anomaly.baseline_loss,
+            anomalies: anomaly.anomalies,
+        };
+
+        if config.publish(out, Event::Anomaly(&event)) {
+            writeln!(out, "{}{} {} (last {} probes)", self.tag(), "ANOMALY:".red().bold(), event.anomalies.join(", "), event.sent);
+        }
+    }
+
+    // A compliance report for every window that saw probes since the last one
+    fn report_sla(&mut self, config: &Config, sla: &Sla, out: &mut Output) {
+        let groups = std::mem::take(&mut self.sla);
+        for (window, group) in sla.windows.iter().zip(groups) {
+            if group.sent == 0 {
+                continue; // Closed the whole time, nothing to say
+            }
+
+            let breaches = window.breaches(&group);
+            let event = SlaEvent {
+                host: self.host.clone(),
+                window: window.name.clone(),
+                sent: group.sent,
+                received: group.sent - group.lost,
+                loss: group.loss(),
+                rtt_avg_ms: event::to_ms(group.rtt.average()),
+                rtt_max_ms: event::to_ms(group.rtt.max),
+                compliant: breaches.is_empty(),
+                breaches: breaches.iter().map(Breach::canonical).collect(),
+            };
+
+            if config.publish(out, Event::Sla(&event)) {
+                write!(out, "{}SLA {}: {}/{} received, loss={}%, rtt avg/max={}/{} ", self.tag(), window.name.bold(),
+                    event.received, event.sent, locale::decimal(event.loss, 2).bold(),
+                    group.rtt.average().map_or("-".to_string(), stats::format_rtt),
+                    group.rtt.max.map_or("-".to_string(), stats::format_rtt));
+
+                if event.compliant {
+                    writeln!(out, "{}", "OK".green().bold());
+                } else {
+                    writeln!(out, "{} ({})", "BREACH".red().bold(), describe(&breaches));
+                }
+            }
+
+            if let (false, Some(webhook)) = (event.compliant, &sla.webhook) {
+                self.post_webhook(webhook, Event::Sla(&event).to_json(config.origin.as_ref()), "SLA");
+            }
+        }
+    }
+
+    fn report_alert(&mut self, config: &Config, alert: &Alert, out: &mut Output, change: alert::Change) {
+        let event = AlertEvent {
+            host: self.host.clone(),
+            destination: self.destination,
+            state: if change.firing { "firing" } else { "cleared" },
+            sent: change.group.sent,
+            lost: change.group.lost,
+            loss: change.group.loss(),
+            rtt_avg_ms: event::to_ms(change.group.rtt.average()),
+            breaches: change.breaches.iter().map(Breach::canonical).collect(),
+        };
+
+        if config.publish(out, Event::Alert(&event)) {
+            if change.firing {
+                writeln!(out, "{}{} {} (last {} probes)", self.tag(), "ALERT:".red().bold(), describe(&change.breaches), event.sent);
+            } else {
+                writeln!(out, "{}{} loss={}%, rtt avg={} (last {} probes)", self.tag(), "ALERT CLEARED:".green().bold(),
+                    locale::decimal(event.loss, 2), change.group.rtt.average().map_or("-".to_string(), stats::format_rtt), event.sent);
+            }
+        }
+
+        if let Some(url) = &alert.url {
+            self.post_webhook(url, Event::Alert(&event).to_json(config.origin.as_ref()), "alert");
+        }
+    }
+
+    // On its own thread, a slow webhook shouldn't hold up pinging
+    fn post_webhook(&mut self, url: &str, body: String, what: &'static str) {
+        let url = url.to_string();
+        self.webhooks.retain(|webhook| !webhook.is_finished());
+        self.webhooks.push(thread::spawn(move || {
+            if let Err(e) = http::post_json(&url, &body, Duration::from_secs(10)) {
+                log::warn!("{} webhook failed: {}", what, e);
+            }
+        }));
+    }
+
+    fn print_rollup(&mut self, config: &Config, out: &mut Output, covering: Duration) {
+        self.rollups += 1;
+        let rollup = std::mem::take(&mut self.rollup);
+
+        let event = RollupEvent {
+            host: self.host.clone(),
+            rollup: self.rollups,
+            seconds: covering.as_secs_f64(),
+            sent: rollup.sent,
+            received: rollup.sent - rollup.lost,
+            loss: rollup.loss(),
+            rtt_min_ms: event::to_ms(rollup.rtt.min),
+            rtt_avg_ms: event::to_ms(rollup.rtt.average()),
+            rtt_max_ms: event::to_ms(rollup.rtt.max),
+        };
+
+        if config.publish(out, Event::Rollup(&event)) {
+            writeln!(out, "{}{} {}: {}/{} received, loss={}%, rtt min/avg/max={}", self.tag(), "last".cyan(),
+                humantime::format_duration(Duration::from_secs(covering.as_secs())),
+                (rollup.sent - rollup.lost).to_string().bold(), rollup.sent,
+                if rollup.lost > 0 { locale::decimal(rollup.loss(), 2).red().bold() } else { locale::decimal(rollup.loss(), 2).bold() },
+                rollup.rtt.format().bold());
+        }
+    }
+
+    fn print_round(&mut self, config: &Config, out: &mut Output) {
+        self.rounds += 1;
+        let round = std::mem::take(&mut self.round);
+
+        let event = RoundEvent {
+            host: self.host.clone(),
+            round: self.rounds,
+            sent: round.sent,
+            received: round.sent - round.lost,
+            loss: round.loss(),
+            rtt_min_ms: event::to_ms(round.rtt.min),
+            rtt_avg_ms: event::to_ms(round.rtt.average()),
+            rtt_max_ms: event::to_ms(round.rtt.max),
+        };
+
+        if config.publish(out, Event::Round(&event)) {
+            writeln!(out, "{}round {}: {}/{} received, loss={}%, rtt min/avg/max={}", self.tag(), self.rounds,
+                (round.sent - round.lost).to_string().bold(), round.sent,
+                if round.lost > 0 { locale::decimal(round.loss(), 2).red().bold() } else { locale::decimal(round.loss(), 2).bold() },
+                round.rtt.format().bold());
+        }
+    }
+
+    // A probe from a burst is done with, None if it doesn't count (Ctrl+C, a clock jump).
+    // The burst is reported once all of them are
+    fn burst_probe_done(&mut self, config: &Config, out: &Mutex<Output>, sequence_num: u16, rtt: Option<Option<Duration>>) {
+        let index = match self.bursts.iter().position(|burst| (sequence_num.wrapping_sub(burst.first) as u32) < burst.size) {
+            Some(index) => index,
+            None => return,
+        };
+        let burst = &mut self.bursts[index];
+        burst.done += 1;
+        if let Some(rtt) = rtt {
+            burst.probes.record(rtt);
+            if rtt.is_none() {
+                burst.lost_at.push(sequence_num.wrapping_sub(burst.first) as u32 + 1);
+            }
+        }
+        if burst.done < burst.size {
+            return;
+        }
+
+        let mut burst = self.bursts.remove(index).unwrap();
+        if burst.probes.sent > 0 {
+            burst.lost_at.sort_unstable(); // Timeouts land in order, errors straight away
+            self.print_burst(config, &mut self.lock(out), burst);
+        }
+    }
+
+    fn print_burst(&self, config: &Config, out: &mut Output, burst: Burst) {
+        let probes = &burst.probes;
+        let spread = probes.rtt.min.zip(probes.rtt.max).map(|(min, max)| max - min);
+        let event = BurstEvent {
+            host: self.host.clone(),
+            burst: burst.number,
+            sent: probes.sent,
+            received: probes.sent - probes.lost,
+            loss: probes.loss(),
+            lost_at: burst.lost_at,
+            rtt_min_ms: event::to_ms(probes.rtt.min),
+            rtt_avg_ms: event::to_ms(probes.rtt.average()),
+            rtt_max_ms: event::to_ms(probes.rtt.max),
+            rtt_spread_ms: event::to_ms(spread),
+        };
+
+        if config.publish(out, Event::Burst(&event)) {
+            let lost_at = if event.lost_at.is_empty() { String::new() } else {
+                format!(", lost #{}", event.lost_at.iter().map(u32::to_string).collect::<Vec<_>>().join(" #"))
+            };
+            writeln!(out, "{}burst {}: {}/{} received, loss={}%, rtt min/avg/max={}, spread={}{}", self.tag(), event.burst,
+                event.received.to_string().bold(), event.sent,
+                if probes.lost > 0 { locale::decimal(event.loss, 2).red().bold() } else { locale::decimal(event.loss, 2).bold() },
+                probes.rtt.format().bold(), event.rtt_spread_ms.map_or("-".to_string(), |ms| stats::format_rtt(Duration::from_secs_f64(ms / 1e3))), lost_at.red());
+        }
+    }
+
+    pub fn summary(&self) -> SummaryEvent {
+        SummaryEvent {
+            interim: false,
+            host: self.host.clone(),
+            destination: self.destination,
+            backend: self.pinger.backend(),
+            mac: self.mac.map(|mac| packet::format_mac(&mac)),
+            sent: self.sent,
+            received: self.sent - self.lost,
+            loss: self.loss(),
+            excluded: self.excluded,
+            lost_reasons: self.lost_reasons.clone(),
+            // Only worth breaking down if the run was actually split
+            segments: if self.segments.len() < 2 { Vec::new() } else {
+                self.segments.iter().enumerate().map(|(i, segment)| SegmentSummary {
+                    segment: i as u32 + 1,
+                    after: segment.after.map(|jump| jump.cause()),
+                    sent: segment.probes.sent,
+                    received: segment.probes.sent - segment.probes.lost,
+                    loss: segment.probes.loss(),
+                    rtt_min_ms: event::to_ms(segment.probes.rtt.min),
+                    rtt_avg_ms: event::to_ms(segment.probes.rtt.average()),
+                    rtt_max_ms: event::to_ms(segment.probes.rtt.max),
+                }).collect()
+            },
+            sources: self.sources.iter().map(|(source, probes)| SourceSummary {
+                source: *source,
+                sent: probes.sent,
+                received: probes.sent - probes.lost,
+                loss: probes.loss(),
+                rtt_min_ms: event::to_ms(probes.rtt.min),
+                rtt_avg_ms: event::to_ms(probes.rtt.average()),
+                rtt_max_ms: event::to_ms(probes.rtt.max),
+            }).collect(),
+            sizes: self.sizes.iter().map(|(size, probes)| SizeSummary {
+                size: *size,
+                sent: probes.sent,
+                received: probes.sent - probes.lost,
+                loss: probes.loss(),
+                rtt_min_ms: event::to_ms(probes.rtt.min),
+                rtt_avg_ms: event::to_ms(probes.rtt.average()),
+                rtt_max_ms: event::to_ms(probes.rtt.max),
+            }).collect(),
+            // Only with --with-gateway, once something's been lost
+            gateway_losses: if self.lost_with_gateway + self.lost_beyond_gateway == 0 { None } else {
+                Some(GatewayLosses { with_gateway: self.lost_with_gateway, beyond_gateway: self.lost_beyond_gateway })
+            },
+            // Only worth saying when something came in late
+            late: if self.late == 0 { None } else { Some(self.late) },
+            lenient_loss: if self.late == 0 { None } else { Some(100f32 * self.lost.saturating_sub(self.late) as f32 / self.sent as f32) },
+            ecn: self.ecn.clone(),
+            outages: self.reachability.outages.iter().map(|outage| OutageSummary {
+                start: timestamp(outage.start),
+                end: outage.end.map(timestamp),
+                seconds: outage.seconds(),
+                lost: outage.lost,
+            }).collect(),
+            size_mismatches: self.size_mismatches,
+            corrupted_payloads: self.corrupted_payloads,
+            checksum_failures: self.pinger.checksum_failures(),
+            // Not fatal if we can't tell, older kernels don't have SO_MEMINFO
+            kernel_drops: self.pinger.kernel_drops().unwrap_or(0),
+            duplicates: self.duplicates,
+        }
+    }
+}
+
+// Breaches for people, ex: "loss 25.00% over 10.00%, avg 80.12ms over 50.00ms"
+fn describe(breaches: &[Breach]) -> String {
+    breaches.iter().map(Breach::describe).collect::<Vec<_>>().join(", ")
+}
+
+// ex: " (quoting tcp 51234 > 443)", for errors about a TCP probe
+fn quoting(event: &ProbeEvent) -> String {
+    event.transport.as_ref().map_or(String::new(), |transport| format!(" ({})", transport.describe()))
+}
+
+// ex: " (gateway fine, lost beyond it)", for losses with --with-gateway
+fn gateway_note(event: &ProbeEvent) -> String {
+    match event.gateway_lossy {
+        Some(true) => format!(" {}", "(gateway losing too, lost on our side of it)".red()),
+        Some(false) => format!(" {}", "(gateway fine, lost beyond it)".yellow()),
+        None => String::new(),
+    }
+}
+
+// RFC 3339 to the second, in UTC
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+// Roughly when a probe went out, going by how long its answer took, or the whole timeout
+fn sent_at(config: &Config, event: &ProbeEvent) -> Instant {
+    let now = Instant::now();
+    let waited = match (event.rtt, event.status) {
+        (Some(rtt), _) => rtt,
+        (None, Status::Timeout) => config.timeout + config.grace,
+        _ => Duration::from_secs(0), // Errors come back about as soon as it's sent
+    };
+    now.checked_sub(waited).unwrap_or(now)
+}
+
+// What makes an error line the same as the one before, sequence numbers aside. Replies and
+// timeouts (which count up the loss) are never the same, they just end a run of errors
+fn repeat_key(event: &ProbeEvent) -> Option<(bool, String)> {
+    let detail = event.detail.as_deref().unwrap_or("");
+    match event.status {
+        Status::Error => Some((true, format!("{:?} {}", event.reason, detail))),
+        Status::TimeExceeded | Status::Unreachable | Status::ParameterProblem =>
+            Some((false, format!("{} {}", event.from.unwrap_or(event.destination), detail))),
+        _ => None,
+    }
+}
@@ -0,0 +1,93 @@
+//! Places events go besides stdout. Exporters for other systems can be written as
+//! their own programs and plugged in with --sink-exec, so they don't need to live in
+//! ring itself: the program gets every event as one line of JSON on its stdin (the
+//! same lines as --format json), and stdin closing means ring is done.
+
+use std::io::{Result, Write, BufWriter};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use crate::event::Event;
+
+pub trait Sink: Send + Sync {
+    /// Called for every event that gets past --filter, with its JSON line (derived fields included)
+    fn send(&self, event: Event, line: &str);
+
+    /// No more events are coming, flush anything buffered
+    fn finish(&self) {}
+}
+
+/// Runs a program and writes events to its stdin
+pub struct ExecSink {
+    command: String,
+    sender: Mutex<Option<SyncSender<String>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    child: Mutex<Child>,
+}
+
+// Events queued up for a slow sink before new ones get dropped, pinging never waits on a sink
+const QUEUE_LEN: usize = 4096;
+
+impl ExecSink {
+    pub fn new(command: &str) -> Result<Self> {
+        let mut child = Command::new("sh").arg("-c").arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null()) // Would get mixed up with our own output
+            .stderr(Stdio::inherit())
+            .process_group(0) // Keep Ctrl+C to ourselves, the sink should still get the summary
+            .spawn()?;
+
+        let mut stdin = BufWriter::new(child.stdin.take().unwrap());
+        let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        let name = command.to_string();
+
+        let writer = thread::spawn(move || {
+            for line in receiver {
+                let written = stdin.write_all(line.as_bytes()).and_then(|_| stdin.write_all(b"\n"))
+                    .and_then(|_| stdin.flush());
+
+                if let Err(e) = written {
+                    log::warn!("sink {:?} stopped taking events: {}", name, e);
+                    return;
+                }
+            }
+        });
+
+        Ok(ExecSink {
+            command: command.to_string(),
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(Some(writer)),
+            child: Mutex::new(child),
+        })
+    }
+}
+
+impl Sink for ExecSink {
+    fn send(&self, _event: Event, line: &str) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            match sender.try_send(line.to_string()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => log::warn!("sink {:?} is falling behind, dropped an event", self.command),
+                Err(TrySendError::Disconnected(_)) => {} // Already warned about when it went away
+            }
+        }
+    }
+
+    fn finish(&self) {
+        // Closing the channel ends the writer, which closes the program's stdin
+        self.sender.lock().unwrap().take();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+
+        match self.child.lock().unwrap().wait() {
+            Ok(status) if !status.success() =>
+                log::warn!("sink {:?} exited with {}", self.command, status),
+            Err(e) => log::warn!("couldn't wait for sink {:?}: {}", self.command, e),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Latency SLAs checked over windows of time (ex: business hours), so a long running
+//! ring can keep track of whether a link is holding up its end. Probes sent while a
+//! window is open count towards it, and every report period each window that saw
+//! probes gets a compliance report.
+
+use std::time::Duration;
+
+use crate::stats::{self, Breach, ProbeGroup};
+
+pub struct Sla {
+    pub windows: Vec<Window>,
+    pub report_every: Duration,
+    pub webhook: Option<String>, // Gets a POST of the report whenever a window is in breach
+}
+
+pub struct Window {
+    pub name: String,
+    days: [bool; 7],  // Sunday first, like tm_wday
+    start: u32,       // Minutes since midnight, local time
+    end: u32,         // Exclusive, and before start when the window goes past midnight
+    pub max_loss: Option<f32>,
+    pub max_avg: Option<Duration>,
+    pub max_rtt: Option<Duration>,
+}
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Window {
+    /// Parses `name=days/HH:MM-HH:MM,threshold,...`, where days is a range or list like
+    /// `mon-fri` or `sat,sun` (or `*`), the times can be left off for a whole day, and
+    /// thresholds are any of `loss<1%`, `avg<50ms`, `max<200ms`.
+    /// ex: `business=mon-fri/09:00-17:00,loss<1%,avg<50ms`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, rest) = spec.split_once('=').ok_or("expected name=schedule,thresholds")?;
+        let mut parts = rest.split(',');
+        let schedule = parts.next().unwrap_or("");
+
+        // Lists of days also use commas, so keep taking parts while they're days
+        let mut schedule = schedule.to_string();
+        let mut thresholds = Vec::new();
+        for part in parts {
+            if thresholds.is_empty() && !part.contains('<') {
+                schedule.push(',');
+                schedule.push_str(part);
+            } else {
+                thresholds.push(part);
+            }
+        }
+
+        let (days, hours) = match schedule.split_once('/') {
+            Some((days, hours)) => (days, Some(hours)),
+            None => (schedule.as_str(), None),
+        };
+
+        let (start, end) = match hours {
+            Some(hours) => {
+                let (start, end) = hours.split_once('-').ok_or("expected hours as HH:MM-HH:MM")?;
+                (parse_time(start)?, parse_time(end)?)
+            }
+            None => (0, 24 * 60),
+        };
+
+        let mut window = Window {
+            name: name.trim().to_string(),
+            days: parse_days(days)?,
+            start, end,
+            max_loss: None, max_avg: None, max_rtt: None,
+        };
+
+        for threshold in thresholds {
+            let (stat, limit) = threshold.split_once('<').ok_or_else(|| format!("invalid threshold {:?}", threshold))?;
+            let duration = || humantime::parse_duration(limit.trim()).map_err(|e| format!("invalid threshold {:?}: {}", threshold, e));
+            match stat.trim() {
+                "loss" => window.max_loss = Some(limit.trim().trim_end_matches('%').parse()
+                    .map_err(|_| format!("invalid loss threshold {:?}", threshold))?),
+                "avg" => window.max_avg = Some(duration()?),
+                "max" => window.max_rtt = Some(duration()?),
+                _ => return Err(format!("unknown threshold {:?}, expected loss, avg or max", stat)),
+            }
+        }
+
+        Ok(window)
+    }
+
+    /// Whether the window is open at `weekday` (0 is Sunday) and `minute` past midnight
+    pub fn contains(&self, weekday: usize, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.days[weekday] && minute >= self.start && minute < self.end
+        } else {
+            // Going past midnight, the early hours belong to the day before
+            (self.days[weekday] && minute >= self.start) || (self.days[(weekday + 6) % 7] && minute < self.end)
+        }
+    }
+
+    /// Everything about the group that's over the thresholds
+    pub fn breaches(&self, group: &ProbeGroup) -> Vec<Breach> {
+        stats::breaches(group, self.max_loss, self.max_avg, self.max_rtt)
+    }
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {:?}, expected HH:MM", time);
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let (hours, minutes): (u32, u32) = (hours.parse().map_err(|_| invalid())?, minutes.parse().map_err(|_| invalid())?);
+
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+fn parse_days(days: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| DAYS.iter().position(|&day| name.trim().eq_ignore_ascii_case(day))
+        .ok_or_else(|| format!("unknown day {:?}, expected sun, mon, ...", name));
+
+    let mut set = [false; 7];
+    if days.trim() == "*" || days.trim().is_empty() {
+        return Ok([true; 7]);
+    }
+
+    for part in days.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (mut from, to) = (day(from)?, day(to)?);
+                // Ranges can wrap around the weekend, like fri-mon
+                loop {
+                    set[from] = true;
+                    if from == to { break }
+                    from = (from + 1) % 7;
+                }
+            }
+            None => set[day(part)?] = true,
+        }
+    }
+
+    Ok(set)
+}
+
+/// The local weekday (0 is Sunday) and minutes since midnight right now
+pub fn local_time() -> (usize, u32) {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        (tm.tm_wday as usize, (tm.tm_hour * 60 + tm.tm_min) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: usize = 0;
+    const MON: usize = 1;
+    const FRI: usize = 5;
+    const SAT: usize = 6;
+
+    #[test]
+    fn day_lists_share_commas_with_thresholds() {
+        let window = Window::parse("weekend=sat,sun/10:00-18:00,loss<2.5%,max<200ms").unwrap();
+        assert_eq!(window.name, "weekend");
+        assert_eq!(window.days, [true, false, false, false, false, false, true]);
+        assert_eq!((window.start, window.end), (10 * 60, 18 * 60));
+        assert_eq!(window.max_loss, Some(2.5));
+        assert_eq!(window.max_avg, None);
+        assert_eq!(window.max_rtt, Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn day_ranges_wrap_around_the_weekend() {
+        assert_eq!(parse_days("fri-mon").unwrap(), [true, true, false, false, false, true, true]);
+        assert_eq!(parse_days("Mon-Wed,sat").unwrap(), [false, true, true, true, false, false, true]);
+        assert_eq!(parse_days("*").unwrap(), [true; 7]);
+        assert!(parse_days("mon-funday").is_err());
+    }
+
+    #[test]
+    fn no_hours_is_the_whole_day() {
+        let window = Window::parse("always=*,avg<50ms").unwrap();
+        assert!(window.contains(SUN, 0));
+        assert!(window.contains(SAT, 24 * 60 - 1));
+        assert_eq!(window.max_avg, Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn windows_past_midnight_give_the_early_hours_to_the_day_before() {
+        // Friday and Saturday nights
+        let window = Window::parse("nights=fri-sat/22:00-02:00").unwrap();
+        assert!(window.contains(FRI, 23 * 60));
+        assert!(window.contains(SAT, 60)); // Friday night still
+        assert!(window.contains(SUN, 60)); // Saturday night still
+        assert!(!window.contains(FRI, 60)); // Thursday night isn't in it
+        assert!(!window.contains(SUN, 23 * 60));
+        assert!(!window.contains(SAT, 2 * 60)); // The end's exclusive
+    }
+
+    #[test]
+    fn windows_in_a_day_include_the_start_only() {
+        let window = Window::parse("business=mon-fri/09:00-17:00").unwrap();
+        assert!(window.contains(MON, 9 * 60));
+        assert!(!window.contains(MON, 17 * 60));
+        assert!(!window.contains(SAT, 12 * 60));
+    }
+
+    #[test]
+    fn bad_specs_say_what_was_wrong() {
+        assert!(Window::parse("mon-fri/09:00-17:00").is_err());
+        assert!(Window::parse("late=*/24:30-25:00").is_err());
+        assert!(Window::parse("bad=*,jitter<5ms").is_err());
+        assert!(Window::parse("bad=*,loss<lots").is_err());
+    }
+}
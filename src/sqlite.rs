@@ -0,0 +1,259 @@
+//! Every probe result appended to an SQLite database (--log-db), so a long monitoring run
+//! can be queried afterwards without running a collector next to it:
+//!
+//!     sqlite3 ring.db "SELECT target, avg(rtt_ms) FROM probes GROUP BY target"
+//!
+//! The system's libsqlite3 is loaded when it's asked for, rather than linked, so ring
+//! still builds and runs anywhere without it.
+
+use std::ffi::{CStr, CString};
+use std::io::{Result, Error, ErrorKind};
+use std::os::raw::{c_char, c_double, c_int, c_void};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::event::Event;
+use crate::sink::Sink;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS probes (
+        time REAL NOT NULL,   -- Unix time in seconds
+        target TEXT NOT NULL, -- As given on the command line
+        destination TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        id TEXT,
+        status TEXT NOT NULL,
+        rtt_ms REAL,
+        ttl INTEGER,
+        reason TEXT           -- Why it was lost, if it was
+    );
+    CREATE INDEX IF NOT EXISTS probes_by_target ON probes (target, time);
+    PRAGMA journal_mode = WAL; -- Queries while we're still writing
+";
+const INSERT: &str = "INSERT INTO probes (time, target, destination, seq, id, status, rtt_ms, ttl, reason) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+// Results queued up for a slow disk before new ones get dropped, same as --sink-exec
+const QUEUE_LEN: usize = 4096;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+const SQLITE_OPEN_NOMUTEX: c_int = 0x8000; // Only the writer thread ever touches it
+
+/// A probe as it goes in the table
+struct Row {
+    time: f64,
+    target: String,
+    destination: String,
+    seq: u16,
+    id: Option<String>,
+    status: String,
+    rtt_ms: Option<f64>,
+    ttl: Option<u8>,
+    reason: Option<String>,
+}
+
+pub struct SqliteSink {
+    sender: Mutex<Option<SyncSender<Row>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SqliteSink {
+    /// Opens (or creates) the database at `path`, and the table in it
+    pub fn new(path: &str) -> Result<Self> {
+        let db = Database::open(path)?;
+        db.exec(SCHEMA)?;
+        let (sender, receiver) = mpsc::sync_channel::<Row>(QUEUE_LEN);
+
+        let name = path.to_string();
+        let writer = thread::spawn(move || {
+            // Whatever has piled up goes in together, one transaction (and sync) per batch
+            while let Ok(row) = receiver.recv() {
+                let rows = std::iter::once(row).chain(receiver.try_iter()).collect::<Vec<_>>();
+                if let Err(e) = db.insert(&rows) {
+                    log::warn!("writing to {} failed, it won't get any more results: {}", name, e);
+                    return;
+                }
+            }
+        });
+
+        Ok(SqliteSink { sender: Mutex::new(Some(sender)), writer: Mutex::new(Some(writer)) })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn send(&self, event: Event, _line: &str) {
+        let probe = match event {
+            Event::Probe(probe) => probe,
+            _ => return,
+        };
+
+        let row = Row {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |time| time.as_secs_f64()),
+            target: probe.host.clone(),
+            destination: probe.destination.to_string(),
+            seq: probe.seq,
+            id: probe.id.clone(),
+            // Same names as the JSON
+            status: serde_json::to_value(probe.status).ok().and_then(|status| status.as_str().map(String::from)).unwrap_or_default(),
+            rtt_ms: probe.rtt_ms,
+            ttl: probe.ttl,
+            reason: probe.reason.and_then(|reason| serde_json::to_value(reason).ok()).and_then(|reason| reason.as_str().map(String::from)),
+        };
+
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            match sender.try_send(row) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => log::warn!("--log-db is falling behind, dropped a result"),
+                Err(TrySendError::Disconnected(_)) => {} // Already warned about when it went away
+            }
+        }
+    }
+
+    fn finish(&self) {
+        // Dropping the sender ends the writer once everything queued is in
+        drop(self.sender.lock().unwrap().take());
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// The few functions of the sqlite3 C API needed to append rows
+struct Api {
+    open_v2: unsafe extern "C" fn(*const c_char, *mut *mut c_void, c_int, *const c_char) -> c_int,
+    close: unsafe extern "C" fn(*mut c_void) -> c_int,
+    errmsg: unsafe extern "C" fn(*mut c_void) -> *const c_char,
+    exec: unsafe extern "C" fn(*mut c_void, *const c_char, *const c_void, *mut c_void, *mut *mut c_char) -> c_int,
+    prepare_v2: unsafe extern "C" fn(*mut c_void, *const c_char, c_int, *mut *mut c_void, *mut *const c_char) -> c_int,
+    bind_double: unsafe extern "C" fn(*mut c_void, c_int, c_double) -> c_int,
+    bind_int: unsafe extern "C" fn(*mut c_void, c_int, c_int) -> c_int,
+    bind_text: unsafe extern "C" fn(*mut c_void, c_int, *const c_char, c_int, isize) -> c_int,
+    bind_null: unsafe extern "C" fn(*mut c_void, c_int) -> c_int,
+    step: unsafe extern "C" fn(*mut c_void) -> c_int,
+    reset: unsafe extern "C" fn(*mut c_void) -> c_int,
+    finalize: unsafe extern "C" fn(*mut c_void) -> c_int,
+}
+
+// Tells sqlite to copy strings it's given, SQLITE_TRANSIENT
+const TRANSIENT: isize = -1;
+
+impl Api {
+    fn load() -> Result<Self> {
+        let library = [&b"libsqlite3.so.0\0"[..], &b"libsqlite3.so\0"[..]].iter()
+            .map(|name| unsafe { libc::dlopen(name.as_ptr() as *const c_char, libc::RTLD_NOW | libc::RTLD_LOCAL) })
+            .find(|library| !library.is_null())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "couldn't load libsqlite3, is sqlite installed?"))?;
+
+        let symbol = |name: &[u8]| {
+            let address = unsafe { libc::dlsym(library, name.as_ptr() as *const c_char) };
+            if address.is_null() {
+                Err(Error::new(ErrorKind::NotFound, format!("libsqlite3 has no {}", String::from_utf8_lossy(&name[..name.len() - 1]))))
+            } else {
+                Ok(address)
+            }
+        };
+
+        // The library stays loaded for as long as we run
+        unsafe {
+            Ok(Api {
+                open_v2: function(symbol(b"sqlite3_open_v2\0")?),
+                close: function(symbol(b"sqlite3_close\0")?),
+                errmsg: function(symbol(b"sqlite3_errmsg\0")?),
+                exec: function(symbol(b"sqlite3_exec\0")?),
+                prepare_v2: function(symbol(b"sqlite3_prepare_v2\0")?),
+                bind_double: function(symbol(b"sqlite3_bind_double\0")?),
+                bind_int: function(symbol(b"sqlite3_bind_int\0")?),
+                bind_text: function(symbol(b"sqlite3_bind_text\0")?),
+                bind_null: function(symbol(b"sqlite3_bind_null\0")?),
+                step: function(symbol(b"sqlite3_step\0")?),
+                reset: function(symbol(b"sqlite3_reset\0")?),
+                finalize: function(symbol(b"sqlite3_finalize\0")?),
+            })
+        }
+    }
+}
+
+// A symbol as the function pointer type it's going in, which has to be the right one
+unsafe fn function<F: Copy>(address: *mut c_void) -> F {
+    std::mem::transmute_copy(&address)
+}
+
+struct Database {
+    api: Api,
+    db: *mut c_void,
+}
+
+// A connection opened NOMUTEX is fine to move to another thread, just not to share
+unsafe impl Send for Database {}
+
+impl Database {
+    fn open(path: &str) -> Result<Self> {
+        let api = Api::load()?;
+        let path = CString::new(path).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+        let mut db = std::ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_NOMUTEX;
+        let code = unsafe { (api.open_v2)(path.as_ptr(), &mut db, flags, std::ptr::null()) };
+        let database = Database { api, db }; // Closed on drop even if opening failed, like sqlite wants
+        if code != SQLITE_OK {
+            return Err(database.error());
+        }
+        Ok(database)
+    }
+
+    fn error(&self) -> Error {
+        let message = unsafe { CStr::from_ptr((self.api.errmsg)(self.db)) };
+        Error::other(message.to_string_lossy().into_owned())
+    }
+
+    fn check(&self, code: c_int) -> Result<()> {
+        if code == SQLITE_OK || code == SQLITE_DONE { Ok(()) } else { Err(self.error()) }
+    }
+
+    fn exec(&self, sql: &str) -> Result<()> {
+        let sql = CString::new(sql).unwrap();
+        self.check(unsafe { (self.api.exec)(self.db, sql.as_ptr(), std::ptr::null(), std::ptr::null_mut(), std::ptr::null_mut()) })
+    }
+
+    fn insert(&self, rows: &[Row]) -> Result<()> {
+        let sql = CString::new(INSERT).unwrap();
+        let mut statement = std::ptr::null_mut();
+        self.check(unsafe { (self.api.prepare_v2)(self.db, sql.as_ptr(), -1, &mut statement, std::ptr::null_mut()) })?;
+
+        self.exec("BEGIN")?;
+        let inserted = rows.iter().try_for_each(|row| unsafe {
+            let api = &self.api;
+            let text = |index, value: &str| (api.bind_text)(statement, index, value.as_ptr() as *const c_char, value.len() as c_int, TRANSIENT);
+            let codes = [
+                (api.bind_double)(statement, 1, row.time),
+                text(2, &row.target),
+                text(3, &row.destination),
+                (api.bind_int)(statement, 4, row.seq as c_int),
+                match &row.id { Some(id) => text(5, id), None => (api.bind_null)(statement, 5) },
+                text(6, &row.status),
+                match row.rtt_ms { Some(ms) => (api.bind_double)(statement, 7, ms), None => (api.bind_null)(statement, 7) },
+                match row.ttl { Some(ttl) => (api.bind_int)(statement, 8, ttl as c_int), None => (api.bind_null)(statement, 8) },
+                match &row.reason { Some(reason) => text(9, reason), None => (api.bind_null)(statement, 9) },
+            ];
+            codes.iter().try_for_each(|&code| self.check(code))?;
+            self.check((api.step)(statement))?;
+            self.check((api.reset)(statement))
+        });
+        unsafe { (self.api.finalize)(statement) };
+
+        match inserted {
+            Ok(()) => self.exec("COMMIT"),
+            Err(e) => { let _ = self.exec("ROLLBACK"); Err(e) }
+        }
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        unsafe { (self.api.close)(self.db) };
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::locale;
+
+/// Running min/avg/max of round trip times
+#[derive(Clone, Copy, Default)]
+pub struct RttStats {
+    pub count: u32,
+    pub total: Duration,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl RttStats {
+    pub fn record(&mut self, rtt: Duration) {
+        self.count += 1;
+        self.total += rtt;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+    }
+
+    /// Take in another group's times, as if they'd all been recorded here
+    pub fn add(&mut self, other: &RttStats) {
+        self.count += other.count;
+        self.total += other.total;
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.count == 0 { None } else { Some(self.total / self.count) }
+    }
+
+    /// `min/avg/max` in the --precision unit, which goes on the end, or dashes if nothing was recorded
+    pub fn format(&self) -> String {
+        match (self.min, self.average(), self.max) {
+            (Some(min), Some(avg), Some(max)) => format!("{}/{}/{}{}", decimal(min), decimal(avg), decimal(max), precision().unit()),
+            _ => "-/-/-".to_string(),
+        }
+    }
+}
+
+/// Statistics for a group of probes, like a `--round`
+#[derive(Clone, Copy, Default)]
+pub struct ProbeGroup {
+    pub sent: u32,
+    pub lost: u32,
+    pub rtt: RttStats,
+}
+
+impl ProbeGroup {
+    /// Count a probe, `rtt` is None if it was lost
+    pub fn record(&mut self, rtt: Option<Duration>) {
+        self.sent += 1;
+        match rtt {
+            Some(rtt) => self.rtt.record(rtt),
+            None => self.lost += 1,
+        }
+    }
+
+    /// Take in another group's probes
+    pub fn add(&mut self, other: &ProbeGroup) {
+        self.sent += other.sent;
+        self.lost += other.lost;
+        self.rtt.add(&other.rtt);
+    }
+
+    pub fn loss(&self) -> f32 {
+        if self.sent == 0 { 0f32 } else { 100f32 * (self.lost as f32) / (self.sent as f32) }
+    }
+}
+
+/// A threshold a group of probes went over, for the --sla windows and --alert
+pub enum Breach {
+    Loss(f32, f32),              // Percent lost, and the most allowed
+    Average(Duration, Duration), // The average rtt, and the limit
+    Max(Duration, Duration),
+}
+
+impl Breach {
+    /// The same whatever the locale, for the events
+    pub fn canonical(&self) -> String {
+        let ms = |time: &Duration| format!("{:.2}ms", time.as_nanos() as f64 / 1e6);
+        match self {
+            Breach::Loss(loss, limit) => format!("loss {:.2}% over {}%", loss, limit),
+            Breach::Average(avg, limit) => format!("avg {} over {}", ms(avg), ms(limit)),
+            Breach::Max(max, limit) => format!("max {} over {}", ms(max), ms(limit)),
+        }
+    }
+
+    /// For people, in their locale and the --precision unit
+    pub fn describe(&self) -> String {
+        match self {
+            Breach::Loss(loss, limit) => format!("loss {}% over {}%", locale::decimal(*loss, 2), locale::decimal(*limit, 2)),
+            Breach::Average(avg, limit) => format!("avg {} over {}", format_rtt(*avg), format_rtt(*limit)),
+            Breach::Max(max, limit) => format!("max {} over {}", format_rtt(*max), format_rtt(*limit)),
+        }
+    }
+}
+
+/// Everything about `group` that's over the limits given
+pub fn breaches(group: &ProbeGroup, max_loss: Option<f32>, max_avg: Option<Duration>, max_rtt: Option<Duration>) -> Vec<Breach> {
+    let mut breaches = Vec::new();
+    if let Some(limit) = max_loss.filter(|&limit| group.loss() > limit) {
+        breaches.push(Breach::Loss(group.loss(), limit));
+    }
+    if let (Some(limit), Some(avg)) = (max_avg, group.rtt.average()) {
+        if avg > limit {
+            breaches.push(Breach::Average(avg, limit));
+        }
+    }
+    if let (Some(limit), Some(max)) = (max_rtt, group.rtt.max) {
+        if max > limit {
+            breaches.push(Breach::Max(max, limit));
+        }
+    }
+    breaches
+}
+
+pub fn as_ms(time: Duration) -> f32 {
+    (time.as_nanos() as f64 / 1e6) as f32
+}
+
+/// What round trip times are shown in, picked with --precision. Localhost and LAN
+/// times are well under a millisecond, where two decimals of ms say next to nothing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Precision {
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl Precision {
+    pub fn parse(unit: &str) -> Option<Precision> {
+        match unit {
+            "ms" => Some(Precision::Milliseconds),
+            "us" => Some(Precision::Microseconds),
+            "ns" => Some(Precision::Nanoseconds),
+            _ => None,
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            Precision::Milliseconds => "ms",
+            Precision::Microseconds => "us",
+            Precision::Nanoseconds => "ns",
+        }
+    }
+}
+
+static PRECISION: OnceLock<Precision> = OnceLock::new();
+
+/// Show times in `precision` from now on, rather than milliseconds
+pub fn set_precision(precision: Precision) {
+    let _ = PRECISION.set(precision);
+}
+
+fn precision() -> Precision {
+    PRECISION.get().copied().unwrap_or(Precision::Milliseconds)
+}
+
+/// A round trip time for people, in the --precision unit with the unit on the end
+pub fn format_rtt(time: Duration) -> String {
+    format!("{}{}", decimal(time), precision().unit())
+}
+
+/// How much slower `time` was than `against`, signed, in the --precision unit
+pub fn format_delta(time: Duration, against: Duration) -> String {
+    if time >= against { format!("+{}", format_rtt(time - against)) } else { format!("-{}", format_rtt(against - time)) }
+}
+
+// Just the number: two decimals of ms or us, whole nanoseconds
+fn decimal(time: Duration) -> String {
+    match precision() {
+        Precision::Milliseconds => locale::decimal(time.as_nanos() as f64 / 1e6, 2),
+        Precision::Microseconds => locale::decimal(time.as_nanos() as f64 / 1e3, 2),
+        Precision::Nanoseconds => time.as_nanos().to_string(),
+    }
+}
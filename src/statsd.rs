@@ -0,0 +1,69 @@
+//! Probe results as StatsD metrics over UDP (--statsd), for telemetry pipelines that
+//! already take them. Plain StatsD has no tags, so the destination goes in the metric
+//! name: ring.example_com.rtt. With --statsd-tag it's DogStatsD, and the destination
+//! is a tag like any other: ring.rtt|#target:example.com.
+
+use std::io::Result;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::event::{Event, Status};
+use crate::sink::Sink;
+
+pub struct StatsdSink {
+    socket: UdpSocket,
+    tags: Vec<String>, // key:value, empty for plain StatsD
+    warned: AtomicBool,
+}
+
+impl StatsdSink {
+    /// Sends to `address`, ex: "127.0.0.1:8125"
+    pub fn new(address: &str, tags: Vec<String>) -> Result<Self> {
+        let socket = UdpSocket::bind(if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?; // A full buffer drops metrics, it never holds up pinging
+
+        Ok(StatsdSink { socket, tags, warned: AtomicBool::new(false) })
+    }
+
+    // One line of the packet for `metric`
+    fn line(&self, host: &str, metric: &str, value: &str, kind: &str) -> String {
+        if self.tags.is_empty() {
+            format!("ring.{}.{}:{}|{}", sanitize(host), metric, value, kind)
+        } else {
+            format!("ring.{}:{}|{}|#target:{},{}", metric, value, kind, host, self.tags.join(","))
+        }
+    }
+}
+
+impl Sink for StatsdSink {
+    fn send(&self, event: Event, _line: &str) {
+        let probe = match event {
+            Event::Probe(probe) => probe,
+            _ => return,
+        };
+
+        if !probe.counts_towards_totals() {
+            return;
+        }
+
+        let mut lines = vec![self.line(&probe.host, "sent", "1", "c")];
+        match (probe.status, probe.rtt_ms) {
+            (Status::Reply, Some(ms)) | (Status::Reset, Some(ms)) => lines.push(self.line(&probe.host, "rtt", &format!("{:.3}", ms), "ms")),
+            _ => lines.push(self.line(&probe.host, "lost", "1", "c")),
+        }
+
+        // Both take several metrics to a packet, one per line
+        if let Err(e) = self.socket.send(lines.join("\n").as_bytes()) {
+            // Nothing listening is normal for UDP, but say so once in case it's a typo
+            if !self.warned.swap(true, Ordering::Relaxed) {
+                log::warn!("couldn't send statsd metrics: {}", e);
+            }
+        }
+    }
+}
+
+// Dots separate the parts of a metric name, so they can't be in the destination
+fn sanitize(host: &str) -> String {
+    host.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
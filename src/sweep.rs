@@ -0,0 +1,180 @@
+use clap::ArgMatches;
+
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::ping::{Pinger, ReplyType};
+use crate::{locale, targets};
+use crate::stats::ProbeGroup;
+use crate::color::*;
+
+/// Refuse to sweep anything bigger than a /16, it would take forever and look like an attack
+const MAX_SWEEP_SIZE: u128 = 65536;
+
+/// Ping every address in the CIDR ranges (or made up from them, for IPv6) and the zones
+/// given, and report which ones answered
+pub fn run(matches: &ArgMatches) {
+    let ranges: Vec<&str> = matches.values_of("RANGE").into_iter().flatten().collect();
+    let mut addresses = Vec::new();
+    let mut what: Vec<String> = ranges.iter().map(|range| range.to_string()).collect(); // For the banner
+
+    // With host parts to put in them the ranges are only prefixes, they're too big to go through
+    let mut hosts = targets::suffixes(matches.values_of("suffix").into_iter().flatten()).expect("Invalid --suffix");
+    if let Some(path) = matches.value_of("eui64") {
+        hosts.extend(targets::eui64_ids(path).expect("Error reading --eui64 MAC list"));
+    }
+    for range in &ranges {
+        if matches.is_present("suffix") || matches.is_present("eui64") {
+            match parse_prefix(range).expect("Invalid prefix (ex: 2001:db8:1::/64)") {
+                (IpAddr::V6(prefix), length) => addresses.extend(targets::in_prefix(prefix, length, &hosts)),
+                _ => panic!("--suffix and --eui64 are only for IPv6 prefixes, {} isn't one", range),
+            }
+        } else {
+            addresses.extend(parse_cidr(range).expect("Invalid range (ex: 192.168.1.0/24, fd00::/120)"));
+        }
+    }
+
+    for path in matches.values_of("zone").into_iter().flatten() {
+        addresses.extend(targets::zone_file(path).expect("Error reading zone file"));
+        what.push(format!("zone file {}", path));
+    }
+    for transfer in matches.values_of("axfr").into_iter().flatten() {
+        let (zone, server) = transfer.split_once('@').expect("Invalid zone transfer: (ex: --axfr example.com@ns1.example.com)");
+        addresses.extend(targets::axfr(zone, server).unwrap_or_else(|e| panic!("Error transferring {} from {}: {}", zone, server, e)));
+        what.push(format!("zone {}", zone));
+    }
+
+    // Zones list the same host under several names
+    addresses.sort();
+    addresses.dedup();
+
+    let count = matches.value_of("count").unwrap_or("1");
+    let count = count.parse::<u32>().expect("Invalid count: (ex: -c 3)");
+
+    let timeout = matches.value_of("timeout").unwrap_or("1s");
+    let timeout = humantime::parse_duration(timeout).expect("Invalid duration for timeout (ex: -W 1s, -W 400ms, -W 1m)");
+
+    let parallel = matches.value_of("parallel").unwrap_or("64");
+    let parallel = parallel.parse::<usize>().expect("Invalid parallelism: (ex: -p 64)");
+
+    println!("{} {} ({} addresses)", "SWEEP".cyan(), what.join(", ").bold(), addresses.len());
+
+    let total = addresses.len();
+    let queue = Arc::new(Mutex::new(addresses.into_iter()));
+    let alive = Arc::new(Mutex::new(Vec::new()));
+
+    // A pool of workers, each pinging one address at a time
+    let workers: Vec<_> = (0..std::cmp::max(parallel, 1)).map(|_| {
+        let (queue, alive) = (queue.clone(), alive.clone());
+        thread::spawn(move || loop {
+            let address = match queue.lock().unwrap().next() {
+                Some(address) => address,
+                None => break,
+            };
+
+            match probe(address, count, timeout) {
+                Ok(group) if group.rtt.count > 0 => alive.lock().unwrap().push((address, group)),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error pinging {}: {}", address, e),
+            }
+        })
+    }).collect();
+
+    for worker in workers {
+        worker.join().expect("Sweep worker panicked");
+    }
+
+    let mut alive = alive.lock().unwrap();
+    alive.sort_by_key(|(address, _)| *address);
+
+    for (address, group) in alive.iter() {
+        println!("{} is {} rtt min/avg/max={} loss={}%", address.to_string().yellow(), "alive".green().bold(),
+            group.rtt.format(), locale::decimal(group.loss(), 2));
+    }
+
+    println!();
+    println!("{} {} {}", "===".yellow(), "sweep statistics".cyan(), "===".yellow());
+    println!("{} of {} hosts up", alive.len().to_string().bold(), total);
+}
+
+fn probe(address: IpAddr, count: u32, timeout: Duration) -> Result<ProbeGroup> {
+    let mut pinger = Pinger::new(address)?;
+    let mut group = ProbeGroup::default();
+
+    for _ in 0..count {
+        let sequence_num = pinger.ping()?;
+        group.sent += 1;
+
+        match pinger.receive_pong(sequence_num, timeout) {
+            Ok(pong) if pong.mtype == ReplyType::Reply => group.rtt.record(pong.rtt),
+            _ => group.lost += 1,
+        }
+    }
+
+    Ok(group)
+}
+
+/// Every usable host address in a CIDR range. For IPv4 the network and
+/// broadcast addresses are left out, unless the range is too small to have them.
+pub fn parse_cidr(range: &str) -> Result<Vec<IpAddr>> {
+    let (address, prefix) = parse_prefix(range)?;
+    let bits = if address.is_ipv6() { 128 } else { 32 };
+
+    // An IPv6 /0 is 2^128 addresses, more than a u128 holds
+    let size = match 1u128.checked_shl(bits - prefix) {
+        Some(size) if size <= MAX_SWEEP_SIZE => size,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "range too large to sweep")),
+    };
+
+    Ok(match address {
+        IpAddr::V4(address) => {
+            let network = u32::from(address) & !((size - 1) as u32);
+            let (first, last) = if size > 2 { (1, size - 1) } else { (0, size) };
+            (first..last).map(|i| IpAddr::from(Ipv4Addr::from(network + i as u32))).collect()
+        }
+
+        IpAddr::V6(address) => {
+            let network = u128::from(address) & !(size - 1);
+            (0..size).map(|i| IpAddr::from(Ipv6Addr::from(network + i))).collect()
+        }
+    })
+}
+
+/// The address and prefix length of a CIDR range, a lone address is a range of one
+fn parse_prefix(range: &str) -> Result<(IpAddr, u32)> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidInput, msg.to_string());
+
+    let (address, prefix) = match range.find('/') {
+        Some(i) => (&range[..i], &range[i + 1..]),
+        None => (range, if range.contains(':') { "128" } else { "32" }),
+    };
+
+    let address: IpAddr = address.parse().map_err(|_| invalid("invalid address"))?;
+    let prefix: u32 = prefix.parse().map_err(|_| invalid("invalid prefix length"))?;
+    if prefix > if address.is_ipv6() { 128 } else { 32 } {
+        return Err(invalid("prefix length too long"));
+    }
+
+    Ok((address, prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_leave_out_network_and_broadcast() {
+        let hosts = parse_cidr("192.0.2.7/30").unwrap();
+        assert_eq!(hosts, vec![IpAddr::from([192, 0, 2, 5]), IpAddr::from([192, 0, 2, 6])]);
+        assert_eq!(parse_cidr("fd00::/127").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn whole_address_spaces_are_too_large() {
+        assert!(parse_cidr("0.0.0.0/0").is_err());
+        assert!(parse_cidr("::/0").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Results to the local syslog daemon (--syslog), for long running monitoring that
+//! should end up wherever the rest of the system's logs go. Severities follow what
+//! needs attention: answers are info, losses warnings, a destination going quiet is
+//! an error, and it coming back a notice. Runs of the same loss are logged once with
+//! a count (unless --verbose), an hour of "network unreachable" is one message a minute.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::sync::Mutex;
+
+use crate::event::{Event, Reason, Status};
+use crate::repeat::Repeats;
+use crate::sink::Sink;
+
+// A loss as logged, by its severity and message
+type Loss = (libc::c_int, String);
+
+pub struct SyslogSink {
+    down: Mutex<HashSet<String>>, // Destinations whose latest probe was lost
+    repeats: Option<Mutex<HashMap<String, Repeats<Loss>>>>, // Each destination's latest loss, if collapsing
+}
+
+impl SyslogSink {
+    pub fn new(collapse_repeats: bool) -> Self {
+        // The ident has to stay around for as long as the log is open
+        unsafe { libc::openlog(b"ring\0".as_ptr() as *const libc::c_char, libc::LOG_PID, libc::LOG_USER) };
+        SyslogSink {
+            down: Mutex::new(HashSet::new()),
+            repeats: if collapse_repeats { Some(Mutex::new(HashMap::new())) } else { None },
+        }
+    }
+
+    // Whether to log a probe's line, `loss` is what identifies it if it was lost
+    fn should_log(&self, host: &str, loss: Option<Loss>) -> bool {
+        let repeats = match &self.repeats {
+            Some(repeats) => repeats,
+            None => return true,
+        };
+        let mut repeats = repeats.lock().unwrap();
+        let (repeated, show) = repeats.entry(host.to_string()).or_insert_with(Repeats::new).check(loss);
+        if let Some(repeated) = repeated {
+            log_repeats(host, repeated);
+        }
+        show
+    }
+}
+
+fn log_repeats(host: &str, ((severity, message), count): (Loss, u32)) {
+    log(severity, &format!("{} last message repeated {} time{}: {}", host, count, if count == 1 { "" } else { "s" }, message));
+}
+
+impl Sink for SyslogSink {
+    fn send(&self, event: Event, _line: &str) {
+        match event {
+            Event::Probe(probe) => {
+                if !probe.counts_towards_totals() {
+                    return;
+                }
+
+                let answered = probe.status == Status::Reply || probe.status == Status::Reset;
+                let mut down = self.down.lock().unwrap();
+                if answered {
+                    let answer = if probe.status == Status::Reply { "reply" } else { "refused" }; // TCP RSTs are answers too
+                    self.should_log(&probe.host, None);
+                    log(libc::LOG_INFO, &format!("{} seq={} {} rtt={:.3}ms", probe.host, probe.seq, answer, probe.rtt_ms.unwrap_or(0.0)));
+                    if down.remove(&probe.host) {
+                        log(libc::LOG_NOTICE, &format!("{} is answering again", probe.host));
+                    }
+                } else {
+                    let reason = probe.reason.unwrap_or(Reason::Error);
+                    let severity = if reason == Reason::SendFailed || reason == Reason::Error { libc::LOG_ERR } else { libc::LOG_WARNING };
+                    let loss = format!("lost ({}){}", reason.describe(), probe.detail.as_ref().map_or(String::new(), |detail| format!(": {}", detail)));
+                    if self.should_log(&probe.host, Some((severity, loss.clone()))) {
+                        log(severity, &format!("{} seq={} {}", probe.host, probe.seq, loss));
+                    }
+                    if reason != Reason::Interrupted && down.insert(probe.host.clone()) {
+                        log(libc::LOG_ERR, &format!("{} stopped answering", probe.host));
+                    }
+                }
+            }
+            Event::Round(round) => log(libc::LOG_INFO, &format!("{} round {}: {}/{} received, loss={:.2}%",
+                round.host, round.round, round.received, round.sent, round.loss)),
+            Event::Burst(burst) => log(libc::LOG_INFO, &format!("{} burst {}: {}/{} received, loss={:.2}%",
+                burst.host, burst.burst, burst.received, burst.sent, burst.loss)),
+            Event::Rollup(rollup) => log(libc::LOG_INFO, &format!("{} last {:.0}s: {}/{} received, loss={:.2}%",
+                rollup.host, rollup.seconds, rollup.received, rollup.sent, rollup.loss)),
+            Event::Reachability(change) if change.state == "down" => log(libc::LOG_ERR, &format!("{} DOWN since {}", change.host, change.start)),
+            Event::Reachability(change) => log(libc::LOG_NOTICE, &format!("{} UP after {:.0}s down, {} probes lost",
+                change.host, change.seconds.unwrap_or_default(), change.lost)),
+            Event::Sla(sla) if sla.compliant => log(libc::LOG_INFO, &format!("{} SLA {} OK", sla.host, sla.window)),
+            Event::Sla(sla) => log(libc::LOG_ERR, &format!("{} SLA {} BREACH ({})", sla.host, sla.window, sla.breaches.join(", "))),
+            Event::Anomaly(anomaly) => log(libc::LOG_WARNING, &format!("{} anomaly: {} (last {} probes)",
+                anomaly.host, anomaly.anomalies.join(", "), anomaly.sent)),
+            Event::Clock(clock) => log(libc::LOG_NOTICE, &format!("{} clock: {}", clock.host, clock.detail)),
+            Event::Alert(alert) if alert.state == "firing" => log(libc::LOG_ERR, &format!("{} ALERT: {} (last {} probes)",
+                alert.host, alert.breaches.join(", "), alert.sent)),
+            Event::Alert(alert) => log(libc::LOG_NOTICE, &format!("{} alert cleared: loss={:.2}% over the last {} probes",
+                alert.host, alert.loss, alert.sent)),
+            Event::Summary(summary) => log(libc::LOG_INFO, &format!("{} {}: {} transmitted, {} received, {:.2}% packet loss",
+                summary.host, if summary.interim { "so far" } else { "done" }, summary.sent, summary.received, summary.loss)),
+            Event::Icmp(_) => {} // Only ring listen has these, and it has no sinks
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(repeats) = &self.repeats {
+            for (host, repeats) in repeats.lock().unwrap().iter_mut() {
+                if let Some(repeated) = repeats.flush() {
+                    log_repeats(host, repeated);
+                }
+            }
+        }
+        unsafe { libc::closelog() };
+    }
+}
+
+fn log(severity: libc::c_int, message: &str) {
+    // Through "%s", so a % in a hostname can't be taken for a format
+    if let Ok(message) = CString::new(message) {
+        unsafe { libc::syslog(severity, b"%s\0".as_ptr() as *const libc::c_char, message.as_ptr()) };
+    }
+}
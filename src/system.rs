@@ -0,0 +1,151 @@
+use std::io::{Result, Error, ErrorKind, Read};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ping::{PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::util;
+
+/// Last resort for when we can't open an ICMP socket at all (locked down containers):
+/// run the platform's ping once per probe and parse what it prints. Needs to be asked
+/// for with --system-ping, it's slow and a lot less precise than the real thing.
+pub struct SystemPing {
+    address: IpAddr,
+    ttl: Option<u32>,
+    sequence: u16,
+}
+
+impl SystemPing {
+    pub fn new(address: IpAddr) -> Self {
+        SystemPing { address, ttl: None, sequence: 0 }
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = Some(ttl);
+    }
+
+    fn command(&self, timeout: Duration) -> Command {
+        // Always the same fixed set of arguments, nothing from the user goes through a shell
+        let mut command = Command::new("ping");
+        command.arg("-n").arg("-c").arg("1");
+        // -W only takes whole seconds on most pings, we enforce the real timeout ourselves
+        command.arg("-W").arg(timeout.as_secs().max(1).to_string());
+        if let Some(ttl) = self.ttl {
+            command.arg("-t").arg(ttl.to_string());
+        }
+        if self.address.is_ipv6() {
+            command.arg("-6");
+        }
+        command.arg(self.address.to_string());
+
+        command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null());
+        command
+    }
+}
+
+impl Probe for SystemPing {
+    fn ping(&mut self) -> Result<u16> {
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(self.sequence)
+    }
+
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
+        let begin_time = Instant::now();
+        let mut child = self.command(timeout).spawn()
+            .map_err(|e| Error::new(e.kind(), format!("couldn't run system ping: {}", e)))?;
+
+        // Poll rather than block, so a hung ping can't hold up the session
+        while child.try_wait()?.is_none() {
+            if begin_time.elapsed() > timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::new(ErrorKind::WouldBlock, "system ping timed out"));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut output = String::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            stdout.read_to_string(&mut output)?;
+        }
+
+        let (from, reply) = parse_output(&output)
+            .ok_or_else(|| Error::new(ErrorKind::WouldBlock, "no reply from system ping"))?;
+        let address = from.unwrap_or(self.address);
+
+        Ok(PongResult {
+            address,
+            hostname: util::hostname(&address),
+
+            sequence: sequence_num,
+            ttl: reply.ttl,
+            route: None,
+            size: reply.size,
+            // Falls back to our own (process spawning included) timing if ping didn't say
+            rtt: reply.rtt.unwrap_or_else(|| begin_time.elapsed()),
+            mtype: reply.mtype,
+            phases: Vec::new(), detail: None, transport: None, corruption: None, tos: None, duplicate: false, late: false, hardware: None,
+        })
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some("via system ping".to_string())
+    }
+
+    fn backend(&self) -> &'static str { "system-ping" }
+}
+
+struct SystemReply {
+    mtype: ReplyType,
+    ttl: Option<u8>,
+    size: u16,
+    rtt: Option<Duration>,
+}
+
+// Understands the iputils, busybox and BSD styles, which all look roughly like:
+//   64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=0.045 ms
+//   From 10.0.0.254 icmp_seq=1 Time to live exceeded
+//   From 10.0.0.254 icmp_seq=1 Destination Host Unreachable
+fn parse_output(output: &str) -> Option<(Option<IpAddr>, SystemReply)> {
+    for line in output.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        if let Some(at) = words.iter().position(|&w| w == "from") {
+            if at < 2 || words[at - 1] != "bytes" {
+                continue;
+            }
+
+            let from = words.get(at + 1).and_then(|w| w.trim_end_matches(':').parse().ok());
+            let value = |key: &str| words.iter().find_map(|w| w.strip_prefix(key));
+
+            let reply = SystemReply {
+                mtype: ReplyType::Reply,
+                ttl: value("ttl=").and_then(|ttl| ttl.parse().ok()),
+                size: words[0].parse().unwrap_or(0),
+                rtt: value("time=").or_else(|| value("time<"))
+                    .and_then(|time| time.trim_end_matches("ms").parse::<f64>().ok())
+                    .map(|ms| Duration::from_nanos((ms * 1e6) as u64)),
+            };
+            return Some((from, reply));
+        }
+
+        if words.first() == Some(&"From") {
+            let from = words.get(1).and_then(|w| w.trim_end_matches(':').parse().ok());
+            let lower = line.to_lowercase();
+            let mtype = if lower.contains("time to live exceeded") {
+                ReplyType::TimeLimitExceeded
+            } else if lower.contains("unreachable") {
+                // ping doesn't tell us the code in a useful way, host unreachable is the common one
+                ReplyType::DestinationUnreachable(1)
+            } else {
+                continue;
+            };
+
+            return Some((from, SystemReply { mtype, ttl: None, size: 0, rtt: None }));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,173 @@
+//! Where `ring sweep` gets its addresses besides plain CIDR ranges. An IPv6 subnet is far
+//! too big to sweep, but the hosts in one tend to sit at addresses that can be guessed:
+//! low or well known suffixes, EUI-64 interface ids made from their MACs, or whatever the
+//! zone for them lists.
+
+use std::fs;
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use rand::random;
+
+use crate::dns;
+use crate::util;
+
+/// What `--suffix common` stands for: the low ones and the ones people pick by hand
+const COMMON_SUFFIXES: &[&str] = &[
+    "::1", "::2", "::3", "::4", "::5", "::6", "::7", "::8", "::9", "::a", "::10", "::11", "::20",
+    "::53", "::80", "::100", "::101", "::123", "::443", "::1000", "::1:1", "::ffff",
+    "::babe", "::beef", "::cafe", "::dead", "::face", "::feed", "::dead:beef", "::cafe:babe",
+];
+
+// How long an AXFR gets to connect, and then between reads
+const AXFR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The host parts to put in each prefix, from `--suffix` values. "common" is a whole list of them
+pub fn suffixes<'a>(values: impl Iterator<Item = &'a str>) -> Result<Vec<Ipv6Addr>> {
+    let mut suffixes = Vec::new();
+    for value in values {
+        let list = if value == "common" { COMMON_SUFFIXES.to_vec() } else { vec![value] };
+        for suffix in list {
+            suffixes.push(suffix.parse::<Ipv6Addr>().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid suffix {} (ex: ::53)", suffix)))?);
+        }
+    }
+    Ok(suffixes)
+}
+
+/// The EUI-64 interface id for every MAC in a file, one per line. Lines can have other
+/// columns (like a copy of an ARP or DHCP table), the first word that's a MAC is used
+pub fn eui64_ids(path: &str) -> Result<Vec<Ipv6Addr>> {
+    let mut ids = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(mac) = line.split('#').next().unwrap_or("").split_whitespace().find_map(parse_mac) {
+            ids.push(eui64_id(mac));
+        }
+    }
+    Ok(ids)
+}
+
+// The universal/local bit flipped, and ff:fe in the middle
+fn eui64_id([a, b, c, d, e, f]: [u8; 6]) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from_be_bytes([0, 0, 0, 0, 0, 0, 0, 0, a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]))
+}
+
+// aa:bb:cc:dd:ee:ff, aa-bb-cc-dd-ee-ff and aabb.ccdd.eeff all go
+fn parse_mac(word: &str) -> Option<[u8; 6]> {
+    let digits: String = word.chars().filter(|c| !matches!(c, ':' | '-' | '.')).collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) || !word.contains([':', '-', '.']) {
+        return None;
+    }
+    let mut mac = [0; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(mac)
+}
+
+/// The network part of `prefix` (`length` bits long) with each host part in `suffixes`
+pub fn in_prefix(prefix: Ipv6Addr, length: u32, suffixes: &[Ipv6Addr]) -> Vec<IpAddr> {
+    let mask = u128::MAX.checked_shl(128 - length).unwrap_or(0);
+    suffixes.iter().map(|suffix| IpAddr::from(Ipv6Addr::from(u128::from(prefix) & mask | u128::from(*suffix) & !mask))).collect()
+}
+
+/// Every A and AAAA record in a zone file. Only the address records are looked at, so
+/// $ORIGIN, $INCLUDE and the rest don't matter
+pub fn zone_file(path: &str) -> Result<Vec<IpAddr>> {
+    Ok(zone_addresses(&fs::read_to_string(path)?))
+}
+
+fn zone_addresses(zone: &str) -> Vec<IpAddr> {
+    zone.lines().filter_map(|line| {
+        let words: Vec<&str> = line.split(';').next().unwrap_or("").split_whitespace().collect();
+        // owner, ttl and class are all optional, and the owner can be called a or aaaa too,
+        // so the type's the last A or AAAA with an address after it
+        words.windows(2).rev().find_map(|pair| match pair[1].parse::<IpAddr>() {
+            Ok(address @ IpAddr::V4(_)) if pair[0].eq_ignore_ascii_case("a") => Some(address),
+            Ok(address @ IpAddr::V6(_)) if pair[0].eq_ignore_ascii_case("aaaa") => Some(address),
+            _ => None,
+        })
+    }).collect()
+}
+
+/// Every A and AAAA record in `zone`, transferred from `server` (which has to allow it)
+pub fn axfr(zone: &str, server: &str) -> Result<Vec<IpAddr>> {
+    let address = SocketAddr::new(util::resolve_dest(server)?, 53);
+    let mut stream = TcpStream::connect_timeout(&address, AXFR_TIMEOUT)?;
+    stream.set_read_timeout(Some(AXFR_TIMEOUT))?;
+
+    let id = random::<u16>();
+    dns::write_tcp(&mut stream, &dns::query(id, zone, dns::TYPE_AXFR)?)?;
+
+    // The transfer starts with the zone's SOA and ends with it again
+    let mut addresses = Vec::new();
+    let mut soas = 0;
+    while soas < 2 {
+        let message = dns::read_tcp(&mut stream)?;
+        let records = match dns::parse(&message, Some(id))? {
+            (0, records) => records,
+            (5, _) => return Err(Error::new(ErrorKind::PermissionDenied, format!("{} refused to transfer {}", server, zone))),
+            (rcode, _) => return Err(Error::other(format!("{} couldn't transfer {} (rcode {})", server, zone, rcode))),
+        };
+
+        for record in records {
+            let data = &message[record.at..record.at + record.length];
+            match (record.rtype, record.length) {
+                (dns::TYPE_A, 4) => addresses.push(IpAddr::from(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+                (dns::TYPE_AAAA, 16) => addresses.push(IpAddr::from(dns::ipv6(data))),
+                (dns::TYPE_SOA, _) => soas += 1,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macs_go_in_all_three_spellings() {
+        let mac = Some([0x00, 0x1b, 0x21, 0x3a, 0x4c, 0x5d]);
+        assert_eq!(parse_mac("00:1b:21:3a:4c:5d"), mac);
+        assert_eq!(parse_mac("00-1B-21-3A-4C-5D"), mac);
+        assert_eq!(parse_mac("001b.213a.4c5d"), mac);
+        // A bare run of hex digits could be anything, and a short one isn't a MAC
+        assert_eq!(parse_mac("001b213a4c5d"), None);
+        assert_eq!(parse_mac("00:1b:21:3a:4c"), None);
+        assert_eq!(parse_mac("00:1b:21:3a:4c:5g"), None);
+    }
+
+    #[test]
+    fn eui64_ids_flip_the_universal_local_bit() {
+        assert_eq!(eui64_id([0x00, 0x1b, 0x21, 0x3a, 0x4c, 0x5d]), "::21b:21ff:fe3a:4c5d".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(eui64_id([0x02, 0x00, 0x5e, 0x10, 0x00, 0x01]), "::5eff:fe10:1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn suffixes_replace_the_host_part_of_the_prefix() {
+        let prefix = "2001:db8:1:2:aaaa::".parse::<Ipv6Addr>().unwrap();
+        let suffix = "::1:53".parse::<Ipv6Addr>().unwrap();
+        assert_eq!(in_prefix(prefix, 64, &[suffix]), vec!["2001:db8:1:2::1:53".parse::<IpAddr>().unwrap()]);
+        // A /0 keeps nothing of the prefix, a /128 nothing of the suffix
+        assert_eq!(in_prefix(prefix, 0, &[suffix]), vec![IpAddr::from(suffix)]);
+        assert_eq!(in_prefix(prefix, 128, &[suffix]), vec![IpAddr::from(prefix)]);
+    }
+
+    #[test]
+    fn zone_records_are_found_past_owners_named_like_types() {
+        let zone = "$ORIGIN example.\n\
+            a IN A 192.0.2.1\n\
+            aaaa 3600 IN AAAA 2001:db8::1\n\
+            \tA 192.0.2.2 ; no owner\n\
+            www IN CNAME a\n\
+            bad IN A 2001:db8::2\n";
+        assert_eq!(zone_addresses(zone), vec![
+            IpAddr::from([192, 0, 2, 1]),
+            "2001:db8::1".parse::<IpAddr>().unwrap(),
+            IpAddr::from([192, 0, 2, 2]),
+        ]);
+    }
+}
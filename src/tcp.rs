@@ -0,0 +1,148 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use socket2::{Socket, Domain, Protocol, SockAddr};
+
+use crate::event::Transport;
+use crate::ping::{Heard, Listener, Overheard, PongResult, ReplyType};
+use crate::probe::Probe;
+use crate::util;
+
+/// Measures round trips with TCP handshakes, for when ICMP is filtered. The time to
+/// connect is one round trip, and a RST (port closed) is just as good an answer.
+pub struct TcpProbe {
+    address: SocketAddr,
+    ttl: Option<u32>,
+    sequence: u16, // Only for display, TCP keeps its own sequence numbers
+    errors: Option<Mutex<Listener>>, // ICMP errors about our SYNs, a TCP socket only gets an errno
+}
+
+impl TcpProbe {
+    pub fn new(address: IpAddr, port: u16) -> Self {
+        TcpProbe {
+            address: SocketAddr::new(address, port),
+            ttl: None,
+            sequence: 0,
+            errors: None,
+        }
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = Some(ttl);
+    }
+
+    /// Look out for ICMP errors quoting our SYNs, to say which router sent what. Needs a raw
+    /// socket, so without the privileges for one connecting just fails the usual way
+    pub fn watch_errors(&mut self) -> Result<()> {
+        self.errors = Some(Mutex::new(Listener::new(self.address.is_ipv6())?));
+        Ok(())
+    }
+
+    // The ICMP error about the SYN we sent from `local_port`, if one came back. Everything
+    // else the raw socket got (other people's pings included) is skipped
+    fn quoted_error(&self, local_port: u16) -> Option<Overheard> {
+        let mut errors = self.errors.as_ref()?.lock().unwrap();
+        loop {
+            let heard = errors.receive(Duration::from_secs(0)).ok()?;
+            if heard.is_empty() {
+                return None;
+            }
+            let quoted = Some((libc::IPPROTO_TCP as u8, local_port, self.address.port()));
+            if let Some(error) = heard.into_iter().find(|heard| matches!(heard.heard, Heard::Error(_))
+                && heard.about == Some(self.address.ip()) && heard.quoted == quoted) {
+                return Some(error);
+            }
+        }
+    }
+
+    fn transport(&self, response: &'static str, local_port: u16, mss: Option<u32>) -> Option<Transport> {
+        Some(Transport { protocol: "tcp", response, local_port, port: self.address.port(), mss })
+    }
+}
+
+impl Probe for TcpProbe {
+    fn ping(&mut self) -> Result<u16> {
+        // Nothing to send yet, the handshake starts when we wait for it
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(self.sequence)
+    }
+
+    fn receive_pong(&self, sequence_num: u16, timeout: Duration) -> Result<PongResult> {
+        let domain = if self.address.is_ipv6() { Domain::ipv6() } else { Domain::ipv4() };
+        let socket = Socket::new(domain, socket2::Type::stream().cloexec(), Some(Protocol::tcp()))?;
+        if let Some(ttl) = self.ttl {
+            socket.set_ttl(ttl)?;
+        }
+
+        // Bound up front to know the port, it's how errors about this SYN are told apart
+        let unspecified = if self.address.is_ipv6() { IpAddr::from(Ipv6Addr::UNSPECIFIED) } else { IpAddr::from(Ipv4Addr::UNSPECIFIED) };
+        socket.bind(&SockAddr::from(SocketAddr::new(unspecified, 0)))?;
+        let local_port = socket.local_addr()?.as_std().map_or(0, |address| address.port());
+
+        // Whatever piled up since the last probe is old news, and no SYN goes out from port 0
+        let _ = self.quoted_error(0);
+
+        let begin_time = Instant::now();
+        let connected = socket.connect_timeout(&SockAddr::from(self.address), timeout);
+        let rtt = begin_time.elapsed();
+
+        let mut pong = PongResult {
+            address: self.address.ip(),
+            hostname: util::hostname(&self.address.ip()),
+
+            sequence: sequence_num,
+            ttl: None,
+            route: None,
+            size: 0,
+            rtt,
+            mtype: ReplyType::Reply,
+            phases: Vec::new(), detail: None, transport: None, corruption: None, tos: None, duplicate: false, late: false, hardware: None,
+        };
+
+        match connected {
+            Ok(()) => pong.transport = self.transport("syn-ack", local_port, mss(&socket)),
+            // A refusal or timeout can be down to an ICMP error, it says who sent it and why
+            Err(e) => match self.quoted_error(local_port) {
+                Some(error) => {
+                    if let Heard::Error(mtype) = error.heard {
+                        pong.mtype = mtype;
+                    }
+                    pong.hostname = util::hostname(&error.from);
+                    pong.address = error.from;
+                    pong.ttl = error.ttl;
+                    pong.transport = self.transport("icmp", local_port, None);
+                }
+                None if e.kind() == ErrorKind::ConnectionRefused => {
+                    pong.mtype = ReplyType::Reset;
+                    pong.transport = self.transport("rst", local_port, None);
+                }
+                None if e.kind() == ErrorKind::TimedOut => return Err(Error::new(ErrorKind::WouldBlock, e)),
+                None => return Err(e),
+            },
+        }
+
+        // Don't leave the connection hanging around in TIME_WAIT on our side
+        let _ = socket.set_linger(Some(Duration::from_secs(0)));
+
+        Ok(pong)
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(format!("tcp port {}", self.address.port()))
+    }
+
+    fn backend(&self) -> &'static str { "tcp" }
+}
+
+// The segment size the handshake settled on
+fn mss(socket: &Socket) -> Option<u32> {
+    let mut mss: libc::c_int = 0;
+    let mut len = std::mem::size_of_val(&mss) as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_MAXSEG, &mut mss as *mut libc::c_int as *mut libc::c_void, &mut len)
+    };
+    if ret == 0 && mss > 0 { Some(mss as u32) } else { None }
+}
@@ -0,0 +1,141 @@
+//! --trigger: probes go out when something else says so, instead of on an interval.
+//! Every trigger (a line on stdin, a SIGUSR1, a datagram on the control socket) sends
+//! one probe to each destination, so another tool can line probes up with whatever
+//! it's doing. Sessions keep count of the triggers they've acted on and compare it
+//! with `fired()`, waking up for each new one.
+
+use std::io::{BufRead, Error, Result};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+
+/// Cuts a session's wait for answers short, so it sends straight away
+pub type Waker = Box<dyn Fn() + Send>;
+
+pub enum Source {
+    Stdin,
+    Signal,
+    Socket(PathBuf),
+}
+
+static FIRED: AtomicU64 = AtomicU64::new(0);
+static OVER: AtomicBool = AtomicBool::new(false); // No more triggers are coming
+static WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+static FIRING: Condvar = Condvar::new(); // Paired with WAKERS, for sessions that wait on the trigger itself
+static SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
+/// Start listening for triggers, each on its own thread
+pub fn start(source: Source) -> Result<()> {
+    match source {
+        Source::Stdin => {
+            thread::spawn(|| {
+                let mut line = String::new();
+                while std::io::stdin().lock().read_line(&mut line).is_ok_and(|read| read > 0) {
+                    fire();
+                    line.clear();
+                }
+                // Nothing more is coming, sessions finish once their last probes are done
+                stop();
+            });
+        }
+        Source::Signal => {
+            let fds = signal_pipe()?;
+            unsafe { libc::signal(libc::SIGUSR1, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+            thread::spawn(move || {
+                let mut byte = 0u8;
+                loop {
+                    let read = unsafe { libc::read(fds[0], &mut byte as *mut u8 as *mut libc::c_void, 1) };
+                    if read == 1 {
+                        fire();
+                    } else if read == 0 || Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                        break;
+                    }
+                }
+            });
+        }
+        Source::Socket(path) => {
+            // Left over from an earlier run that didn't get to clean up
+            if std::fs::symlink_metadata(&path).is_ok_and(|meta| std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type())) {
+                std::fs::remove_file(&path)?;
+            }
+            let socket = UnixDatagram::bind(&path)?;
+            let _ = SOCKET.set(path);
+            thread::spawn(move || {
+                let mut buf = [0u8; 512]; // What's in it doesn't matter
+                while socket.recv(&mut buf).is_ok() {
+                    fire();
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+/// How many triggers there have been so far
+pub fn fired() -> u64 {
+    FIRED.load(Ordering::SeqCst)
+}
+
+/// Get woken up for every trigger from now on
+pub fn register(waker: Waker) {
+    WAKERS.lock().unwrap().push(waker);
+}
+
+/// Whether a session that's sent for the first `seen` triggers is done, there won't be any more
+pub fn over(seen: u64) -> bool {
+    OVER.load(Ordering::SeqCst) && fired() <= seen
+}
+
+/// Block until there's been a trigger past the first `seen`. False if it's over instead
+pub fn wait(seen: u64) -> bool {
+    let mut wakers = WAKERS.lock().unwrap();
+    while fired() <= seen {
+        if OVER.load(Ordering::SeqCst) {
+            return false;
+        }
+        wakers = FIRING.wait(wakers).unwrap();
+    }
+    true
+}
+
+/// For Ctrl+C or the end of stdin, wakes everyone up one last time so they notice
+/// the run is over
+pub fn stop() {
+    OVER.store(true, Ordering::SeqCst);
+    let wakers = WAKERS.lock().unwrap();
+    FIRING.notify_all();
+    for wake in wakers.iter() {
+        wake();
+    }
+    if let Some(path) = SOCKET.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn fire() {
+    let wakers = WAKERS.lock().unwrap();
+    FIRED.fetch_add(1, Ordering::SeqCst);
+    FIRING.notify_all();
+    for wake in wakers.iter() {
+        wake();
+    }
+}
+
+static SIGNAL_PIPE: OnceLock<[libc::c_int; 2]> = OnceLock::new();
+
+fn signal_pipe() -> Result<[libc::c_int; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(*SIGNAL_PIPE.get_or_init(|| fds))
+}
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    // Writing to a pipe is about all a signal handler gets to do, the thread reading it does the rest
+    if let Some(fds) = SIGNAL_PIPE.get() {
+        unsafe { libc::write(fds[1], b"!".as_ptr() as *const libc::c_void, 1) };
+    }
+}
@@ -1,10 +1,67 @@
 use std::io::{Result, Error, ErrorKind};
 use std::net::{ToSocketAddrs, IpAddr};
+use std::time::{Duration, Instant};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::dns;
+use crate::idna;
+
+/// The name an address reverse resolves to, if it does. Always None when built without
+/// the `dns` feature, minimal builds show addresses only, and after `set_numeric`.
+/// Each address is only looked up once, at short intervals a lookup per reply can't keep up
+#[cfg(feature = "dns")]
+pub fn hostname(address: &IpAddr) -> Option<String> {
+    static NAMES: OnceLock<std::sync::Mutex<std::collections::HashMap<IpAddr, Option<String>>>> = OnceLock::new();
+    if NUMERIC.load(Ordering::Relaxed) {
+        return None;
+    }
+    let names = NAMES.get_or_init(Default::default);
+    if let Some(name) = names.lock().unwrap().get(address) {
+        return name.clone();
+    }
+
+    // Not holding the lock meanwhile, other destinations shouldn't wait on this one's lookup
+    let name = match RESOLVER.get() {
+        Some(server) => dns::lookup_addr(server, *address).ok(),
+        None => dns_lookup::lookup_addr(address).ok(),
+    };
+    names.lock().unwrap().insert(*address, name.clone());
+    name
+}
+
+#[cfg(not(feature = "dns"))]
+pub fn hostname(_address: &IpAddr) -> Option<String> {
+    None
+}
+
+// Set by -n, no reverse lookups at all: they're slow on some networks, and tell whoever
+// runs the resolver what's being pinged
+static NUMERIC: AtomicBool = AtomicBool::new(false);
+
+/// No more reverse lookups, `hostname` is always None from now on
+pub fn set_numeric() {
+    NUMERIC.store(true, Ordering::Relaxed);
+}
+
+// Set by --resolver, the DNS server to ask instead of going through the system's resolver
+static RESOLVER: OnceLock<dns::Server> = OnceLock::new();
+
+/// Resolve names by asking `server` directly from now on, reverse lookups too
+pub fn set_resolver(server: dns::Server) {
+    let _ = RESOLVER.set(server);
+}
 
 pub fn resolve_dest(dest: &str) -> Result<IpAddr> {
+    let dest = &idna::to_ascii(dest)?;
+    if RESOLVER.get().is_some() {
+        return resolve_all(dest).map(|addresses| addresses[0]);
+    }
+    log::info!("looking up {} with the system's resolver", dest);
     match format!("{}:0", dest).to_socket_addrs() {
         Ok(mut addrs) => {
             if let Some(addr) = addrs.next() {
+                log::info!("{} is {}", dest, addr.ip());
                 Ok(addr.ip())
             } else {
                 Err(Error::new(ErrorKind::NotConnected, "empty iter"))
@@ -14,6 +71,93 @@ pub fn resolve_dest(dest: &str) -> Result<IpAddr> {
         Err(e) => Err(e)
     }
 }
+/// Every address a destination resolves to, in the order the resolver gave them
+pub fn resolve_all(dest: &str) -> Result<Vec<IpAddr>> {
+    // Names with non-ASCII labels only resolve in their punycode form
+    let dest = &idna::to_ascii(dest)?;
+    if let Some(server) = RESOLVER.get() {
+        // Addresses are taken as they are, there's nothing to ask about them
+        return match dest.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(address) => Ok(vec![address]),
+            Err(_) => dns::lookup_host(server, dest),
+        };
+    }
+    log::info!("looking up every address for {} with the system's resolver", dest);
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for addr in format!("{}:0", dest).to_socket_addrs()? {
+        // The resolver hands back one entry per socket type, only keep each address once
+        if !addresses.contains(&addr.ip()) {
+            addresses.push(addr.ip());
+        }
+    }
+
+    if addresses.is_empty() {
+        return Err(Error::new(ErrorKind::NotConnected, "empty iter"));
+    }
+    log::info!("{} is {}", dest, addresses.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", "));
+
+    Ok(addresses)
+}
+
+/// Try to give the calling thread soft real-time scheduling (SCHED_FIFO) and lock
+/// the process memory so page faults don't add latency. Each step is attempted
+/// independently, the returned vec holds a description of every step that failed.
+pub fn enable_realtime() -> Vec<String> {
+    let mut failures = Vec::new();
+
+    unsafe {
+        // A priority in the middle of the range, so we don't starve kernel threads
+        let min = libc::sched_get_priority_min(libc::SCHED_FIFO);
+        let max = libc::sched_get_priority_max(libc::SCHED_FIFO);
+        let param = libc::sched_param { sched_priority: (min + max) / 2 };
+
+        if libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) != 0 {
+            failures.push(format!("could not set SCHED_FIFO priority: {}", Error::last_os_error()));
+        }
+
+        if libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) != 0 {
+            failures.push(format!("could not lock memory: {}", Error::last_os_error()));
+        }
+    }
+
+    failures
+}
+
+/// Whether this process can run on `cpu` at all, so pinning to it would work
+pub fn check_cpu(cpu: usize) -> Result<()> {
+    let missing = || Err(Error::new(ErrorKind::InvalidInput, format!("cpu {} isn't one this process can run on", cpu)));
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return missing();
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(Error::last_os_error());
+        }
+        if !libc::CPU_ISSET(cpu, &set) {
+            return missing();
+        }
+    }
+    Ok(())
+}
+
+/// Pin the calling thread to a single cpu core
+pub fn pin_to_cpu(cpu: usize) -> Result<()> {
+    // CPU_SET doesn't check, it indexes past the end of the set
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("there's no cpu {}", cpu)));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
 
 
 #[allow(clippy::double_parens)] // For stylistic reasons
@@ -38,7 +182,7 @@ fn sum_be_words(data: &[u8], skipword: usize) -> u32 {
     let skipword = std::cmp::min(skipword, data.len() / 2 - 1);
     data.chunks(2)
         .map(|word| match *word {
-            [w] => w as u16,
+            [w] => u16::from_be_bytes([w, 0]), // An odd byte out is padded on the right
             [wh, wl] => u16::from_be_bytes([wh, wl]),
             _ => unreachable!(),
         })
@@ -46,3 +190,175 @@ fn sum_be_words(data: &[u8], skipword: usize) -> u32 {
         .filter_map(|(i, w)| if i == skipword { None } else { Some(w as u32) })
         .fold(0, u32::wrapping_add)
 }
+
+/// A local network interface, and our address on it
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub mac: [u8; 6],
+    pub address: IpAddr,
+}
+
+/// The interface whose subnet `target` is on, for talking to it directly at the link layer
+pub fn local_interface(target: IpAddr) -> Result<Interface> {
+    let mut found = None;
+
+    unsafe {
+        let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut cursor = addrs;
+        while !cursor.is_null() && found.is_none() {
+            let ifa = &*cursor;
+            cursor = ifa.ifa_next;
+            if ifa.ifa_addr.is_null() || ifa.ifa_netmask.is_null() {
+                continue;
+            }
+
+            let (address, netmask) = match (sockaddr_ip(ifa.ifa_addr), sockaddr_ip(ifa.ifa_netmask)) {
+                (Some(address), Some(netmask)) => (address, netmask),
+                _ => continue,
+            };
+
+            if same_subnet(address, target, netmask) {
+                let name = std::ffi::CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+                found = Some((name, address));
+            }
+        }
+
+        libc::freeifaddrs(addrs);
+    }
+
+    let (name, address) = found.ok_or_else(|| Error::new(ErrorKind::NotFound, "not on a local subnet"))?;
+    let index = unsafe { libc::if_nametoindex(std::ffi::CString::new(name.clone())?.as_ptr()) };
+
+    // Simpler than SIOCGIFHWADDR, and it's always there on linux
+    let mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", name))?;
+    let mac = crate::packet::parse_mac(&mac)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} has no ethernet address", name)))?;
+
+    Ok(Interface { name, index, mac, address })
+}
+
+unsafe fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    match (*addr).sa_family as i32 {
+        libc::AF_INET => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            Some(IpAddr::from(u32::from_be(addr.sin_addr.s_addr).to_be_bytes()))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            Some(IpAddr::from(addr.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
+
+fn same_subnet(a: IpAddr, b: IpAddr, netmask: IpAddr) -> bool {
+    match (a, b, netmask) {
+        (IpAddr::V4(a), IpAddr::V4(b), IpAddr::V4(mask)) => {
+            let mask = u32::from(mask);
+            // A /32 (or the loopback) isn't a subnet anyone else is on
+            mask != u32::MAX && !a.is_loopback() && u32::from(a) & mask == u32::from(b) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b), IpAddr::V6(mask)) => {
+            let mask = u128::from(mask);
+            mask != u128::MAX && !a.is_loopback() && u128::from(a) & mask == u128::from(b) & mask
+        }
+        _ => false,
+    }
+}
+
+// Becomes readable for good once interrupt() is called, so anything waiting can watch for Ctrl+C
+fn interrupt_pipe() -> [libc::c_int; 2] {
+    static PIPE: std::sync::OnceLock<[libc::c_int; 2]> = std::sync::OnceLock::new();
+    *PIPE.get_or_init(|| {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            panic!("Error creating interrupt pipe: {}", Error::last_os_error());
+        }
+        fds
+    })
+}
+
+/// Wake up everything waiting in `wait_readable` or `sleep`, for Ctrl+C
+pub fn interrupt() {
+    unsafe { libc::write(interrupt_pipe()[1], b"!".as_ptr() as *const libc::c_void, 1) };
+}
+
+static STATS_REQUESTS: AtomicU32 = AtomicU32::new(0);
+
+extern "C" fn request_stats(_signal: libc::c_int) {
+    STATS_REQUESTS.fetch_add(1, Ordering::SeqCst); // All a signal handler gets to do
+}
+
+/// Take Ctrl+\ (SIGQUIT) as asking for the stats so far, like ping does, and Enter too
+/// when we're reading from a terminal and `enter` allows it
+pub fn watch_stats_requests(enter: bool) {
+    unsafe { libc::signal(libc::SIGQUIT, request_stats as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+
+    if enter && unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        std::thread::spawn(|| {
+            let mut line = String::new();
+            while std::io::stdin().read_line(&mut line).is_ok_and(|read| read > 0) {
+                STATS_REQUESTS.fetch_add(1, Ordering::SeqCst);
+                line.clear();
+            }
+        });
+    }
+}
+
+/// How many times the stats have been asked for, anything showing them looks for a change
+pub fn stats_requests() -> u32 {
+    STATS_REQUESTS.load(Ordering::SeqCst)
+}
+
+/// Wait for `fd` to have something to read. Ok(false) when `timeout` passes first, and
+/// an `Interrupted` error after Ctrl+C
+pub fn wait_readable(fd: libc::c_int, timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut fds = [
+        libc::pollfd { fd, events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: interrupt_pipe()[0], events: libc::POLLIN, revents: 0 },
+    ];
+    // Without a socket to watch, only the interrupt pipe is polled
+    let watched = if fd < 0 { &mut fds[1..] } else { &mut fds[..] };
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let timespec = libc::timespec { tv_sec: left.as_secs() as libc::time_t, tv_nsec: left.subsec_nanos() as libc::c_long };
+
+        // ppoll rather than poll, milliseconds are too coarse for sub-ms intervals
+        let ready = unsafe { libc::ppoll(watched.as_mut_ptr(), watched.len() as libc::nfds_t, &timespec, std::ptr::null()) };
+        if ready < 0 {
+            let e = Error::last_os_error();
+            if e.kind() == ErrorKind::Interrupted { continue } // A signal, not necessarily Ctrl+C
+            return Err(e);
+        }
+
+        if watched.last().unwrap().revents != 0 {
+            return Err(Error::new(ErrorKind::Interrupted, "interrupted"));
+        }
+        if ready > 0 {
+            return Ok(true);
+        }
+        if left == Duration::from_secs(0) {
+            return Ok(false);
+        }
+    }
+}
+
+/// Sleep, but wake up straight away for Ctrl+C
+pub fn sleep(duration: Duration) {
+    let _ = wait_readable(-1, duration);
+}
+
+/// Sleep until `interval` after `last`, and make that the new `last`. Going by when the
+/// last one was due means the time spent waiting on answers doesn't add to the spacing.
+/// After falling behind it carries on from now, catching up would be a burst of probes
+pub fn sleep_until_next(last: &mut Instant, interval: Duration) {
+    *last = std::cmp::max(*last + interval, Instant::now());
+    sleep(last.saturating_duration_since(Instant::now()));
+}
@@ -0,0 +1,462 @@
+//! Events POSTed to a url (--sink-webhook), for receivers that take HTTP and nothing else.
+//! Events are batched (--sink-webhook-batch events, or whatever came in --sink-webhook-wait)
+//! so a burst of losses is one request instead of hundreds, and a template can shape the
+//! body into whatever the receiver wants (a chat message, an incident API, ...) without
+//! an adapter in between. Batches that don't go through are held on to and retried with
+//! backoff, with --sink-webhook-spool on disk, so they also survive ring restarting.
+//!
+//! Templates are a small piece of Handlebars: {{field}}, {{{field}}}, {{json field}},
+//! {{#each}}, {{#if}} and {{#unless}} (with {{else}}), @index, @first, @last and this.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Result, Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::event::Event;
+use crate::http;
+use crate::sink::Sink;
+use crate::color::*;
+
+// Events queued up for a slow receiver before new ones get dropped, same as --sink-exec
+const QUEUE_LEN: usize = 4096;
+// Batches held on to while the receiver is down, the oldest go first past this
+const PENDING_MAX: usize = 1000;
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+// How long to leave the receiver alone after a failed POST, doubling up to the most
+const RETRY_FIRST: Duration = Duration::from_secs(1);
+const RETRY_MOST: Duration = Duration::from_secs(300);
+
+/// How events are put together into requests
+pub struct Batching {
+    pub size: usize,    // Events in a full batch
+    pub wait: Duration, // The longest the first event in a batch waits for the rest
+}
+
+pub struct WebhookSink {
+    sender: Mutex<Option<SyncSender<String>>>,
+    poster: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebhookSink {
+    /// POSTs to `url`. With a `template` every body is rendered from it, otherwise it's the
+    /// event's JSON, or a JSON array of them when batching. `spool` is a directory to keep
+    /// undelivered batches in, anything left there from before is sent first
+    pub fn new(url: &str, template: Option<&str>, batching: Batching, spool: Option<PathBuf>) -> Result<Self> {
+        let template = template.map(Template::parse).transpose()?;
+        let mut poster = Poster { url: url.to_string(), pending: VecDeque::new(), spool, spooled: 0, retry_at: None, backoff: RETRY_FIRST, failing: false };
+        poster.load_spool()?;
+
+        let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        let poster = thread::spawn(move || {
+            let mut batch: Vec<String> = Vec::new();
+            let mut started = Instant::now();
+            poster.deliver();
+
+            loop {
+                // Up to when the batch is due, or the next retry if that's sooner
+                let due = [Some(started + batching.wait).filter(|_| !batch.is_empty()), poster.retry_at].iter().flatten().min().copied();
+                let line = match due {
+                    Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match line {
+                    Ok(line) => {
+                        if batch.is_empty() {
+                            started = Instant::now();
+                        }
+                        batch.push(line);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                if !batch.is_empty() && (batch.len() >= batching.size || started.elapsed() >= batching.wait) {
+                    poster.queue(body(&std::mem::take(&mut batch), template.as_ref(), batching.size > 1));
+                }
+                if poster.retry_at.is_none_or(|at| at <= Instant::now()) {
+                    poster.deliver();
+                }
+            }
+
+            // One last go at everything, whatever's still not through is left in the spool
+            if !batch.is_empty() {
+                poster.queue(body(&batch, template.as_ref(), batching.size > 1));
+            }
+            poster.deliver();
+            if !poster.pending.is_empty() {
+                match &poster.spool {
+                    Some(spool) => log::warn!("{} webhook batches couldn't be delivered, they're kept in {} for next time",
+                        poster.pending.len(), spool.display()),
+                    None => log::warn!("{} webhook batches couldn't be delivered", poster.pending.len()),
+                }
+            }
+        });
+
+        Ok(WebhookSink { sender: Mutex::new(Some(sender)), poster: Mutex::new(Some(poster)) })
+    }
+}
+
+impl Sink for WebhookSink {
+    fn send(&self, _event: Event, line: &str) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            match sender.try_send(line.to_string()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => log::warn!("webhook is falling behind, dropped an event"),
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+    }
+
+    fn finish(&self) {
+        drop(self.sender.lock().unwrap().take());
+        if let Some(poster) = self.poster.lock().unwrap().take() {
+            let _ = poster.join();
+        }
+    }
+}
+
+// The body for a batch of event lines
+fn body(lines: &[String], template: Option<&Template>, batching: bool) -> String {
+    match template {
+        Some(template) => {
+            let events: Vec<Value> = lines.iter().map(|line| serde_json::from_str(line).unwrap_or(Value::Null)).collect();
+            template.render(&json!({ "count": events.len(), "events": events }))
+        }
+        None if batching => format!("[{}]", lines.join(",")),
+        None => lines.join("\n"),
+    }
+}
+
+// A batch waiting to go out, and its file if it's spooled
+struct Pending {
+    body: String,
+    file: Option<PathBuf>,
+}
+
+// The thread's side of things: batches not delivered yet, oldest first
+struct Poster {
+    url: String,
+    pending: VecDeque<Pending>,
+    spool: Option<PathBuf>,
+    spooled: u64, // For naming spool files
+    retry_at: Option<Instant>, // Set while the receiver isn't taking them
+    backoff: Duration,
+    failing: bool,
+}
+
+impl Poster {
+    fn load_spool(&mut self) -> Result<()> {
+        let spool = match &self.spool {
+            Some(spool) => spool,
+            None => return Ok(()),
+        };
+        fs::create_dir_all(spool)?;
+        // Named so they sort in the order they were written
+        let mut files: Vec<PathBuf> = fs::read_dir(spool)?.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "batch")).collect();
+        files.sort();
+        for file in files {
+            self.pending.push_back(Pending { body: fs::read_to_string(&file)?, file: Some(file) });
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, body: String) {
+        if self.pending.len() >= PENDING_MAX {
+            log::warn!("too many webhook batches held back, dropped the oldest");
+            self.forget();
+        }
+
+        self.spooled += 1;
+        let file = self.spool.as_ref().and_then(|spool| {
+            let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            let file = spool.join(format!("{:016}-{:08}.batch", millis, self.spooled));
+            match fs::write(&file, &body) {
+                Ok(()) => Some(file),
+                Err(e) => {
+                    log::warn!("couldn't spool a webhook batch to {}: {}", file.display(), e);
+                    None
+                }
+            }
+        });
+        self.pending.push_back(Pending { body, file });
+    }
+
+    // Sends what's held back, in order, until the receiver stops taking them
+    fn deliver(&mut self) {
+        while let Some(pending) = self.pending.front() {
+            match post(&self.url, &pending.body) {
+                Ok(()) => {
+                    self.retry_at = None;
+                    if std::mem::replace(&mut self.failing, false) {
+                        eprintln!("{} webhook is taking events again", "Note:".cyan().bold());
+                    }
+                    self.backoff = RETRY_FIRST;
+                    self.forget();
+                }
+                // It'll never take this one, there's no point holding up the rest for it
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    log::warn!("webhook refused a batch: {}", e);
+                    self.forget();
+                }
+                Err(e) => {
+                    if !std::mem::replace(&mut self.failing, true) {
+                        log::warn!("webhook failed: {}, holding on to events to retry", e);
+                    }
+                    self.retry_at = Some(Instant::now() + self.backoff);
+                    self.backoff = (self.backoff * 2).min(RETRY_MOST);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self) {
+        if let Some(Pending { file: Some(file), .. }) = self.pending.pop_front() {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+// POSTs the body, anything but a 2xx is a failure. A 4xx other than for timing or
+// rate limits says retrying won't help, those come back as InvalidData
+fn post(url: &str, body: &str) -> Result<()> {
+    let status = http::post_json(url, body, POST_TIMEOUT)?;
+    match status.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()) {
+        Some(200..=299) => Ok(()),
+        Some(408) | Some(429) => Err(Error::other(status)),
+        Some(400..=499) => Err(Error::new(ErrorKind::InvalidData, status)),
+        _ => Err(Error::other(status)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    Each,
+    If,
+    Unless,
+}
+
+enum Node {
+    Text(String),
+    Value(String, bool), // A field, and whether it goes in raw instead of escaped for a JSON string
+    Json(String),
+    Block(Block, String, Vec<Node>, Vec<Node>), // Its field, then what's in it and what's after {{else}}
+}
+
+struct Template {
+    nodes: Vec<Node>,
+}
+
+// One level of what names are looked up in, inside an {{#each}} it's the item
+struct Scope {
+    this: Value,
+    index: Option<(usize, usize)>, // Where the item is, and how many there are
+}
+
+impl Template {
+    fn parse(source: &str) -> Result<Self> {
+        let mut rest = source;
+        let (nodes, _) = parse_nodes(&mut rest, None)?;
+        Ok(Template { nodes })
+    }
+
+    fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &mut vec![Scope { this: context.clone(), index: None }], &mut out);
+        out
+    }
+}
+
+// Everything up to the end of `block` (or of the template), split at its {{else}}. Leaves
+// `rest` after the closing tag
+fn parse_nodes(rest: &mut &str, block: Option<Block>) -> Result<(Vec<Node>, Vec<Node>)> {
+    let invalid = |what: String| Error::new(ErrorKind::InvalidInput, format!("invalid webhook template: {}", what));
+    let mut halves = vec![Vec::new()];
+
+    loop {
+        let at = match rest.find("{{") {
+            Some(at) => at,
+            None if block.is_some() => return Err(invalid("a block is never closed".to_string())),
+            None => {
+                halves[0].push(Node::Text(rest.to_string()));
+                *rest = "";
+                return Ok((halves.remove(0), Vec::new()));
+            }
+        };
+        if at > 0 {
+            halves.last_mut().unwrap().push(Node::Text(rest[..at].to_string()));
+        }
+
+        let raw = rest[at..].starts_with("{{{");
+        let (open, close) = if raw { ("{{{", "}}}") } else { ("{{", "}}") };
+        let tag_at = at + open.len();
+        let end = rest[tag_at..].find(close).ok_or_else(|| invalid("a tag is never closed".to_string()))?;
+        let tag = rest[tag_at..tag_at + end].trim();
+        *rest = &rest[tag_at + end + close.len()..];
+
+        let mut words = tag.split_whitespace();
+        let (first, field) = (words.next().unwrap_or(""), words.next().unwrap_or("this").to_string());
+        let kind = match first {
+            "#each" => Some(Block::Each),
+            "#if" => Some(Block::If),
+            "#unless" => Some(Block::Unless),
+            _ => None,
+        };
+
+        if first == "else" && block.is_some() && halves.len() == 1 {
+            halves.push(Vec::new());
+            continue;
+        }
+        let nodes = halves.last_mut().unwrap();
+        if let Some(kind) = kind {
+            let (body, otherwise) = parse_nodes(rest, Some(kind))?;
+            nodes.push(Node::Block(kind, field, body, otherwise));
+        } else if let Some(name) = first.strip_prefix('/') {
+            let expected = match block {
+                Some(Block::Each) => "each",
+                Some(Block::If) => "if",
+                Some(Block::Unless) => "unless",
+                None => return Err(invalid(format!("{{{{/{}}}}} without a block to close", name))),
+            };
+            if name != expected {
+                return Err(invalid(format!("{{{{/{}}}}} where {{{{/{}}}}} was expected", name, expected)));
+            }
+            let otherwise = if halves.len() > 1 { halves.pop().unwrap() } else { Vec::new() };
+            return Ok((halves.pop().unwrap(), otherwise));
+        } else if first.starts_with('!') {
+            // A comment
+        } else if first == "json" {
+            nodes.push(Node::Json(field));
+        } else if first.is_empty() || first.starts_with('#') || first == "else" {
+            return Err(invalid(format!("don't know what to do with {{{{{}}}}}", tag)));
+        } else {
+            nodes.push(Node::Value(first.to_string(), raw));
+        }
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<Scope>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(field, raw) => match lookup(scopes, field) {
+                Value::Null => {}
+                Value::String(text) if *raw => out.push_str(&text),
+                // Escaped to go inside a JSON string, without the quotes
+                Value::String(text) => {
+                    let quoted = Value::String(text).to_string();
+                    out.push_str(&quoted[1..quoted.len() - 1]);
+                }
+                value => out.push_str(&value.to_string()),
+            },
+            Node::Json(field) => out.push_str(&lookup(scopes, field).to_string()),
+            Node::Block(Block::Each, field, body, otherwise) => match lookup(scopes, field) {
+                Value::Array(items) if !items.is_empty() => {
+                    let count = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        scopes.push(Scope { this: item, index: Some((i, count)) });
+                        render_nodes(body, scopes, out);
+                        scopes.pop();
+                    }
+                }
+                _ => render_nodes(otherwise, scopes, out),
+            },
+            Node::Block(kind, field, body, otherwise) => {
+                let shown = truthy(&lookup(scopes, field)) == (*kind == Block::If);
+                render_nodes(if shown { body } else { otherwise }, scopes, out);
+            }
+        }
+    }
+}
+
+// A dotted field from the innermost scope that has it, so everything outside an {{#each}}
+// can still be got at from inside
+fn lookup(scopes: &[Scope], field: &str) -> Value {
+    let scope = scopes.last().unwrap();
+    match (field, scope.index) {
+        ("@index", Some((i, _))) => return json!(i),
+        ("@first", Some((i, _))) => return json!(i == 0),
+        ("@last", Some((i, count))) => return json!(i + 1 == count),
+        ("this", _) | (".", _) => return scope.this.clone(),
+        _ => {}
+    }
+
+    let field = field.strip_prefix("this.").unwrap_or(field);
+    for scope in scopes.iter().rev() {
+        let mut value = Some(&scope.this);
+        for part in field.split('.') {
+            value = value.and_then(|value| match value {
+                Value::Array(items) => part.parse::<usize>().ok().and_then(|i| items.get(i)),
+                value => value.get(part),
+            });
+        }
+        if let Some(value) = value {
+            return value.clone();
+        }
+    }
+    Value::Null
+}
+
+// What {{#if}} goes by, same as Handlebars
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, context: Value) -> String {
+        Template::parse(template).unwrap().render(&context)
+    }
+
+    #[test]
+    fn values_are_escaped_for_a_json_string_unless_raw() {
+        let context = json!({"host": "a \"b\"\n", "sent": 3, "gone": null});
+        assert_eq!(render(r#"{"text": "{{host}} sent {{sent}}{{gone}}"}"#, context.clone()), r#"{"text": "a \"b\"\n sent 3"}"#);
+        assert_eq!(render("{{{host}}}", context.clone()), "a \"b\"\n");
+        assert_eq!(render("{{json host}} {{! not shown }}", context), r#""a \"b\"\n" "#);
+    }
+
+    #[test]
+    fn if_and_unless_take_an_else() {
+        let template = "{{#if lost}}lost {{lost}}{{else}}all back{{/if}}, {{#unless late}}none late{{else}}late{{/unless}}";
+        assert_eq!(render(template, json!({"lost": 2, "late": true})), "lost 2, late");
+        assert_eq!(render(template, json!({"lost": 0, "late": []})), "all back, none late");
+    }
+
+    #[test]
+    fn each_nests_and_knows_where_it_is() {
+        let template = "{{#each hosts}}{{#unless @first}}, {{/unless}}{{name}}:{{#each rtts}}{{this}}{{#unless @last}}/{{/unless}}{{/each}} of {{window}}{{/each}}";
+        let context = json!({"window": "1m", "hosts": [{"name": "a", "rtts": [1, 2]}, {"name": "b", "rtts": [3]}]});
+        assert_eq!(render(template, context), "a:1/2 of 1m, b:3 of 1m");
+        assert_eq!(render("{{#each hosts}}{{@index}}{{else}}nothing{{/each}}", json!({"hosts": []})), "nothing");
+    }
+
+    #[test]
+    fn fields_can_be_dotted_into_objects_and_arrays() {
+        assert_eq!(render("{{summary.rtt.max}} {{hosts.1}}", json!({"summary": {"rtt": {"max": 9.5}}, "hosts": ["a", "b"]})), "9.5 b");
+    }
+
+    #[test]
+    fn blocks_have_to_be_closed_and_match() {
+        for template in ["{{#if lost}}lost", "{{#each hosts}}{{/if}}", "{{/each}}", "{{host", "{{}}", "{{else}}", "{{#if a}}{{else}}{{else}}{{/if}}"] {
+            assert!(Template::parse(template).is_err(), "{:?} parsed", template);
+        }
+    }
+}